@@ -1,7 +1,155 @@
 #![allow(unexpected_cfgs)]
 
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+#[cfg(target_os = "linux")]
+use std::sync::RwLock;
+#[cfg(target_os = "linux")]
+use std::{thread, time::Duration};
+
+#[cfg(target_os = "linux")]
+mod hyprland;
+#[cfg(target_os = "linux")]
+mod sway;
+#[cfg(target_os = "linux")]
+mod x11;
+
+/// 前面アプリの情報
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ActiveAppInfo {
+    pub name: String,
+    /// macOSではバンドルのパス、Windowsでは実行ファイルのパス
+    pub bundle_id: String,
+    pub pid: Option<u32>,
+    pub window_title: Option<String>,
+}
+
+impl ActiveAppInfo {
+    #[allow(dead_code)]
+    pub fn new(name: &str, bundle_id: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            bundle_id: bundle_id.to_string(),
+            ..Self::default()
+        }
+    }
+}
+
+/// 前面アプリの取得方法（OSごとの実装や、テスト用の固定値を差し替えられる）
+pub trait ActiveAppProvider: Send + Sync {
+    fn active_app(&self) -> Option<ActiveAppInfo>;
+}
+
+/// OSのAPIで前面アプリを取得する
+pub struct SystemAppProvider;
+
+impl ActiveAppProvider for SystemAppProvider {
+    fn active_app(&self) -> Option<ActiveAppInfo> {
+        get_active_app_info()
+    }
+}
+
+/// このOSで使える前面アプリの取得方法を選ぶ
+/// Linuxでは sway → Hyprland → X11 の順に試す（XWaylandでは DISPLAY も設定されるため）
+pub fn system_provider() -> Arc<dyn ActiveAppProvider> {
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(socket) = std::env::var_os("SWAYSOCK") {
+            match sway::connect(socket.as_ref()) {
+                Ok(provider) => return Arc::new(provider),
+                Err(e) => eprintln!("[KeyFit] sway IPC is not available: {}", e),
+            }
+        }
+        if let Some(dir) = hyprland::socket_dir() {
+            match hyprland::connect(&dir) {
+                Ok(provider) => return Arc::new(provider),
+                Err(e) => eprintln!("[KeyFit] Hyprland IPC is not available: {}", e),
+            }
+        }
+        match x11::X11AppProvider::connect() {
+            Ok(provider) => return Arc::new(provider),
+            Err(e) => eprintln!("[KeyFit] X11 is not available: {}", e),
+        }
+    }
+    Arc::new(SystemAppProvider)
+}
+
+/// コンポジターのフォーカス変更イベントで更新される前面アプリ（Wayland向け）
+#[cfg(target_os = "linux")]
+#[derive(Clone, Default)]
+pub struct FocusEventProvider {
+    focused: Arc<RwLock<Option<ActiveAppInfo>>>,
+}
+
+#[cfg(target_os = "linux")]
+impl FocusEventProvider {
+    fn set(&self, info: Option<ActiveAppInfo>) {
+        *self.focused.write().unwrap() = info;
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl ActiveAppProvider for FocusEventProvider {
+    fn active_app(&self) -> Option<ActiveAppInfo> {
+        self.focused.read().unwrap().clone()
+    }
+}
+
+/// IPCをつなぎ直す間隔（失敗するたびに倍にする）
+#[cfg(target_os = "linux")]
+const RECONNECT_MIN_DELAY: Duration = Duration::from_millis(500);
+#[cfg(target_os = "linux")]
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// コンポジターの再起動などで切れたIPCを、間隔を広げながらつなげるまで試す
+#[cfg(target_os = "linux")]
+fn reconnect<T>(name: &str, mut connect: impl FnMut() -> anyhow::Result<T>) -> T {
+    let mut delay = RECONNECT_MIN_DELAY;
+    loop {
+        thread::sleep(delay);
+        match connect() {
+            Ok(connection) => {
+                eprintln!("[KeyFit] Reconnected to {name} IPC");
+                return connection;
+            }
+            Err(_) => delay = (delay * 2).min(RECONNECT_MAX_DELAY),
+        }
+    }
+}
+
+/// プロセスの実行ファイルのパス
+#[cfg(target_os = "linux")]
+fn process_exe(pid: u32) -> Option<std::path::PathBuf> {
+    std::fs::read_link(format!("/proc/{pid}/exe")).ok()
+}
+
+/// Waylandのアプリ。識別子は実行ファイルのパス、取得できなければ app_id を使う
+#[cfg(target_os = "linux")]
+fn wayland_app(app_id: &str, pid: Option<u32>, window_title: Option<String>) -> ActiveAppInfo {
+    let bundle_id = pid
+        .and_then(process_exe)
+        .map(|exe| exe.to_string_lossy().to_string())
+        .unwrap_or_else(|| format!("wayland:{app_id}"));
+    ActiveAppInfo {
+        name: app_id.to_string(),
+        bundle_id,
+        pid,
+        window_title,
+    }
+}
+
+/// 常に同じアプリを返す（テストや前面アプリを取得できない環境向け）
+#[allow(dead_code)]
+pub struct FixedAppProvider(pub Option<ActiveAppInfo>);
+
+impl ActiveAppProvider for FixedAppProvider {
+    fn active_app(&self) -> Option<ActiveAppInfo> {
+        self.0.clone()
+    }
+}
+
 #[cfg(target_os = "windows")]
-pub fn get_active_app_info() -> Option<(String, String)> {
+fn get_active_app_info() -> Option<ActiveAppInfo> {
     use std::ffi::OsString;
     use std::os::windows::ffi::OsStringExt;
     use windows::Win32::System::ProcessStatus::*;
@@ -28,20 +176,26 @@ pub fn get_active_app_info() -> Option<(String, String)> {
         let os_str = OsString::from_wide(&buf[..len]);
         let exe_path = os_str.to_string_lossy().to_string();
         let exe_name = exe_path.split("\\").last().unwrap_or("").to_string();
-        Some((exe_name, exe_path))
+        let mut title = [0u16; 512];
+        let title_len = GetWindowTextW(hwnd, &mut title) as usize;
+        let window_title = (title_len > 0).then(|| String::from_utf16_lossy(&title[..title_len]));
+        Some(ActiveAppInfo {
+            name: exe_name,
+            bundle_id: exe_path,
+            pid: Some(pid),
+            window_title,
+        })
     }
 }
 
 #[cfg(target_os = "macos")]
-pub fn get_active_app_info() -> Option<(String, String)> {
+fn get_active_app_info() -> Option<ActiveAppInfo> {
     use cocoa::base::{id, nil};
     use cocoa::foundation::NSString;
     use objc::{class, msg_send, sel, sel_impl};
     use std::ffi::CStr;
 
     unsafe {
-        println!("macOS: アクティブアプリ情報取得開始");
-
         let workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
         if workspace == nil {
             eprintln!("macOS: NSWorkspace取得失敗");
@@ -70,8 +224,6 @@ pub fn get_active_app_info() -> Option<(String, String)> {
             name_cow => name_cow.into_owned(),
         };
 
-        println!("macOS: アプリ名取得成功: {}", name);
-
         let bundle_url: id = msg_send![active_app, bundleURL];
         let path = if bundle_url != nil {
             let path_ptr: *const i8 = msg_send![bundle_url, fileSystemRepresentation];
@@ -80,20 +232,24 @@ pub fn get_active_app_info() -> Option<(String, String)> {
                     path_cow => path_cow.into_owned(),
                 }
             } else {
-                println!("macOS: bundleURL fileSystemRepresentation取得失敗");
                 format!("com.{}.app", name.to_lowercase().replace(" ", ""))
             }
         } else {
-            println!("macOS: bundleURL取得失敗");
             format!("com.{}.app", name.to_lowercase().replace(" ", ""))
         };
 
-        println!("macOS: アプリ情報取得完了: {} -> {}", name, path);
-        Some((name, path))
+        let pid: i32 = msg_send![active_app, processIdentifier];
+
+        Some(ActiveAppInfo {
+            name,
+            bundle_id: path,
+            pid: u32::try_from(pid).ok(),
+            window_title: None,
+        })
     }
 }
 
 #[cfg(not(any(target_os = "windows", target_os = "macos")))]
-pub fn get_active_app_info() -> Option<(String, String)> {
+fn get_active_app_info() -> Option<ActiveAppInfo> {
     None
 }
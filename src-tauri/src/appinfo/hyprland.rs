@@ -0,0 +1,165 @@
+use super::{wayland_app, ActiveAppInfo, FocusEventProvider};
+use anyhow::Result;
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// Hyprland のソケットがあるディレクトリ（古い版は /tmp/hypr）
+pub fn socket_dir() -> Option<PathBuf> {
+    let signature = std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE")?;
+    let runtime = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(|dir| Path::new(&dir).join("hypr").join(&signature))
+        .filter(|dir| dir.exists());
+    Some(runtime.unwrap_or_else(|| Path::new("/tmp/hypr").join(&signature)))
+}
+
+/// コマンド用ソケットで現在のウィンドウを問い合わせる（1回ごとに接続し直す）
+fn query_active_window(dir: &Path) -> Result<Option<ActiveAppInfo>> {
+    let mut stream = UnixStream::connect(dir.join(".socket.sock"))?;
+    stream.write_all(b"j/activewindow")?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let window: Value = serde_json::from_str(&response)?;
+    // ウィンドウがないときは {} が返る
+    let Some(class) = window["class"].as_str().filter(|c| !c.is_empty()) else {
+        return Ok(None);
+    };
+    let pid = window["pid"]
+        .as_i64()
+        .and_then(|pid| u32::try_from(pid).ok());
+    let title = window["title"].as_str().map(|s| s.to_string());
+    Ok(Some(wayland_app(class, pid, title)))
+}
+
+/// `activewindow>>class,title` イベントのウィンドウ（pidは含まれない）
+fn event_app(data: &str) -> Option<ActiveAppInfo> {
+    let (class, title) = data.split_once(',').unwrap_or((data, ""));
+    if class.is_empty() {
+        return None;
+    }
+    Some(wayland_app(class, None, Some(title.to_string())))
+}
+
+/// イベントソケットに接続し、今の前面ウィンドウを問い合わせる
+fn subscribe(dir: &Path) -> Result<(UnixStream, Option<ActiveAppInfo>)> {
+    let events = UnixStream::connect(dir.join(".socket2.sock"))?;
+    Ok((events, query_active_window(dir)?))
+}
+
+/// 接続が切れるまでイベントを読み、前面アプリを更新する
+fn read_events(events: UnixStream, dir: &Path, focus: &FocusEventProvider) {
+    for line in BufReader::new(events).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("[KeyFit] Hyprland IPC connection closed: {}", e);
+                return;
+            }
+        };
+        let Some(data) = line.strip_prefix("activewindow>>") else {
+            continue;
+        };
+        // pidを得るために問い合わせ直す。失敗したらイベントの内容だけ使う
+        let info = query_active_window(dir).unwrap_or_else(|_| event_app(data));
+        focus.set(info);
+    }
+}
+
+/// Hyprland のイベントソケットに接続し、前面ウィンドウの変更を購読する
+/// 切れたら（Hyprland の再起動など）つなぎ直す
+pub fn connect(dir: &Path) -> Result<FocusEventProvider> {
+    let (mut events, focused) = subscribe(dir)?;
+    let provider = FocusEventProvider::default();
+    provider.set(focused);
+
+    let focus = provider.clone();
+    let dir = dir.to_path_buf();
+    thread::spawn(move || loop {
+        read_events(events, &dir, &focus);
+        // つなぎ直すまでの押下は前面アプリ不明として捨てる
+        focus.set(None);
+        let (next, focused) = super::reconnect("Hyprland", || subscribe(&dir));
+        events = next;
+        focus.set(focused);
+    });
+    Ok(provider)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::appinfo::ActiveAppProvider;
+    use crate::testutil::wait_until;
+    use serde_json::json;
+    use std::os::unix::net::UnixListener;
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Mutex};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_event_app() {
+        let info = event_app("kitty,~/src").unwrap();
+        assert_eq!(info.name, "kitty");
+        assert_eq!(info.bundle_id, "wayland:kitty");
+        assert_eq!(info.window_title.as_deref(), Some("~/src"));
+        assert_eq!(event_app(","), None);
+    }
+
+    #[test]
+    fn test_hyprland_focus_events() {
+        let dir = TempDir::new().unwrap();
+        let commands = UnixListener::bind(dir.path().join(".socket.sock")).unwrap();
+        let events = UnixListener::bind(dir.path().join(".socket2.sock")).unwrap();
+        let pid = std::process::id();
+        let active = Arc::new(Mutex::new(json!({})));
+
+        // 偽の Hyprland: 問い合わせには今のウィンドウを返す
+        let active_command = active.clone();
+        thread::spawn(move || {
+            for stream in commands.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0u8; 14];
+                stream.read_exact(&mut request).unwrap();
+                assert_eq!(&request, b"j/activewindow");
+                let window = active_command.lock().unwrap().to_string();
+                stream.write_all(window.as_bytes()).unwrap();
+            }
+        });
+        let (accepted, event_streams) = channel();
+        thread::spawn(move || {
+            for stream in events.incoming() {
+                if accepted.send(stream.unwrap()).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let provider = connect(dir.path()).unwrap();
+        assert_eq!(provider.active_app(), None);
+
+        *active.lock().unwrap() = json!({"class": "kitty", "title": "~", "pid": pid});
+        let mut event_stream = event_streams.recv().unwrap();
+        event_stream
+            .write_all(b"workspace>>2\nactivewindow>>kitty,~\n")
+            .unwrap();
+        wait_until("focus event", || {
+            provider.active_app().is_some_and(|info| {
+                info.pid == Some(pid)
+                    && info.bundle_id == std::env::current_exe().unwrap().to_string_lossy()
+            })
+        });
+
+        // 接続が切れたら前面アプリは不明になり、つなぎ直すと戻る
+        drop(event_stream);
+        wait_until("disconnect", || provider.active_app().is_none());
+        *active.lock().unwrap() = json!({"class": "foot", "title": "~"});
+        let _event_stream = event_streams.recv().unwrap();
+        wait_until("reconnect", || {
+            provider
+                .active_app()
+                .is_some_and(|info| info.bundle_id == "wayland:foot")
+        });
+    }
+}
@@ -0,0 +1,237 @@
+use super::{wayland_app, ActiveAppInfo, FocusEventProvider};
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::thread;
+
+const MAGIC: &[u8] = b"i3-ipc";
+const GET_TREE: u32 = 4;
+const SUBSCRIBE: u32 = 2;
+/// イベントは種類の最上位ビットが立つ
+const EVENT_WORKSPACE: u32 = 0x8000_0000;
+const EVENT_WINDOW: u32 = 0x8000_0003;
+
+fn send(stream: &mut UnixStream, kind: u32, payload: &[u8]) -> Result<()> {
+    let mut message = MAGIC.to_vec();
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(payload);
+    stream.write_all(&message)?;
+    Ok(())
+}
+
+fn receive(stream: &mut UnixStream) -> Result<(u32, Value)> {
+    let mut header = [0u8; 14];
+    stream.read_exact(&mut header)?;
+    if &header[..6] != MAGIC {
+        return Err(anyhow!("Invalid i3-ipc message"));
+    }
+    let len = u32::from_ne_bytes(header[6..10].try_into()?) as usize;
+    let kind = u32::from_ne_bytes(header[10..14].try_into()?);
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload)?;
+    Ok((kind, serde_json::from_slice(&payload)?))
+}
+
+/// ウィンドウ（コンテナ）の情報から前面アプリを作る
+/// XWaylandのウィンドウは app_id がなく window_properties.class を使う
+fn container_app(container: &Value) -> Option<ActiveAppInfo> {
+    let app_id = container["app_id"]
+        .as_str()
+        .or_else(|| container["window_properties"]["class"].as_str())?;
+    let pid = container["pid"].as_u64().map(|pid| pid as u32);
+    let title = container["name"].as_str().map(|s| s.to_string());
+    Some(wayland_app(app_id, pid, title))
+}
+
+/// GET_TREE の結果からフォーカスされているウィンドウを探す
+fn focused_container(node: &Value) -> Option<&Value> {
+    if node["focused"].as_bool() == Some(true) {
+        return Some(node);
+    }
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node[*key].as_array())
+        .flatten()
+        .find_map(focused_container)
+}
+
+/// 接続して今のフォーカスを取得し、ウィンドウとワークスペースのイベントを購読する
+fn subscribe(socket: &Path) -> Result<(UnixStream, Option<ActiveAppInfo>)> {
+    let mut stream = UnixStream::connect(socket)?;
+    send(&mut stream, GET_TREE, b"")?;
+    let (_, tree) = receive(&mut stream)?;
+    send(&mut stream, SUBSCRIBE, br#"["window", "workspace"]"#)?;
+    let (_, reply) = receive(&mut stream)?;
+    if reply["success"].as_bool() != Some(true) {
+        return Err(anyhow!("Failed to subscribe to sway events"));
+    }
+    Ok((stream, focused_container(&tree).and_then(container_app)))
+}
+
+/// 接続が切れるまでイベントを読み、前面アプリを更新する
+fn read_events(stream: &mut UnixStream, events: &FocusEventProvider) {
+    loop {
+        match receive(stream) {
+            Ok((EVENT_WINDOW, event)) => match event["change"].as_str() {
+                Some("focus") => events.set(container_app(&event["container"])),
+                // 前面のウィンドウのタイトル変更
+                Some("title") if event["container"]["focused"].as_bool() == Some(true) => {
+                    events.set(container_app(&event["container"]))
+                }
+                Some("close") if event["container"]["focused"].as_bool() == Some(true) => {
+                    events.set(None)
+                }
+                _ => {}
+            },
+            // 空のワークスペースに移るとウィンドウのイベントは来ない
+            // （ワークスペース自体がフォーカスを持ち、アプリは不明になる）
+            Ok((EVENT_WORKSPACE, event)) if event["change"].as_str() == Some("focus") => {
+                events.set(focused_container(&event["current"]).and_then(container_app))
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("[KeyFit] sway IPC connection closed: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// sway の i3-IPC ソケットに接続し、フォーカス変更イベントを購読する
+/// 切れたら（sway の再読み込みなど）つなぎ直す
+pub fn connect(socket: &Path) -> Result<FocusEventProvider> {
+    let (mut stream, focused) = subscribe(socket)?;
+    let provider = FocusEventProvider::default();
+    provider.set(focused);
+    let events = provider.clone();
+    let socket = socket.to_path_buf();
+    thread::spawn(move || loop {
+        read_events(&mut stream, &events);
+        // つなぎ直すまでの押下は前面アプリ不明として捨てる
+        events.set(None);
+        let (next, focused) = super::reconnect("sway", || subscribe(&socket));
+        stream = next;
+        events.set(focused);
+    });
+    Ok(provider)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::appinfo::ActiveAppProvider;
+    use crate::testutil::wait_until;
+    use serde_json::json;
+    use std::os::unix::net::UnixListener;
+    use std::sync::mpsc::channel;
+    use tempfile::TempDir;
+
+    fn reply(stream: &mut UnixStream, kind: u32, value: Value) {
+        send(stream, kind, value.to_string().as_bytes()).unwrap();
+    }
+
+    /// 偽の sway の接続を受け、ツリーと購読の応答を返す
+    fn accept(listener: &UnixListener, tree: Value) -> UnixStream {
+        let (mut stream, _) = listener.accept().unwrap();
+        assert_eq!(receive_request(&mut stream), GET_TREE);
+        reply(&mut stream, GET_TREE, tree);
+        assert_eq!(receive_request(&mut stream), SUBSCRIBE);
+        reply(&mut stream, SUBSCRIBE, json!({"success": true}));
+        stream
+    }
+
+    #[test]
+    fn test_sway_focus_events() {
+        let dir = TempDir::new().unwrap();
+        let socket = dir.path().join("sway-ipc.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let (next_event, events) = channel::<(u32, Value)>();
+        let pid = std::process::id();
+
+        // 偽の sway: 購読後は渡されたイベントを流し、切断後の再接続にも応える
+        let server = thread::spawn(move || {
+            let mut stream = accept(
+                &listener,
+                json!({"focused": false, "nodes": [
+                    {"focused": false, "nodes": [], "floating_nodes": [
+                        {"focused": true, "app_id": "foot", "pid": pid, "name": "~"}
+                    ]}
+                ]}),
+            );
+            for (kind, event) in events {
+                reply(&mut stream, kind, event);
+            }
+            drop(stream);
+            accept(
+                &listener,
+                json!({"focused": true, "app_id": "kitty", "nodes": []}),
+            )
+        });
+
+        let provider = connect(&socket).unwrap();
+        let info = provider.active_app().unwrap();
+        assert_eq!(info.name, "foot");
+        assert_eq!(info.pid, Some(pid));
+        assert_eq!(
+            info.bundle_id,
+            std::env::current_exe().unwrap().to_string_lossy()
+        );
+
+        // XWaylandのウィンドウにフォーカスが移る
+        let focus = |container: Value| {
+            (
+                EVENT_WINDOW,
+                json!({"change": "focus", "container": container}),
+            )
+        };
+        next_event
+            .send(focus(json!({
+                "focused": true, "app_id": null, "window_properties": {"class": "Firefox"}
+            })))
+            .unwrap();
+        wait_until("focus event", || {
+            provider
+                .active_app()
+                .is_some_and(|info| info.bundle_id == "wayland:Firefox")
+        });
+
+        // 空のワークスペースに移ると前面アプリは不明になる
+        next_event
+            .send((
+                EVENT_WORKSPACE,
+                json!({"change": "focus", "current": {
+                    "type": "workspace", "name": "3", "focused": true,
+                    "nodes": [], "floating_nodes": []
+                }}),
+            ))
+            .unwrap();
+        wait_until("empty workspace", || provider.active_app().is_none());
+        next_event
+            .send(focus(
+                json!({"focused": true, "app_id": "foot", "pid": pid}),
+            ))
+            .unwrap();
+        wait_until("focus event", || provider.active_app().is_some());
+
+        // 接続が切れたら前面アプリは不明になり、つなぎ直すと戻る
+        drop(next_event);
+        wait_until("disconnect", || provider.active_app().is_none());
+        let _stream = server.join().unwrap();
+        wait_until("reconnect", || {
+            provider
+                .active_app()
+                .is_some_and(|info| info.bundle_id == "wayland:kitty")
+        });
+    }
+
+    fn receive_request(stream: &mut UnixStream) -> u32 {
+        let mut header = [0u8; 14];
+        stream.read_exact(&mut header).unwrap();
+        let len = u32::from_ne_bytes(header[6..10].try_into().unwrap()) as usize;
+        stream.read_exact(&mut vec![0u8; len]).unwrap();
+        u32::from_ne_bytes(header[10..14].try_into().unwrap())
+    }
+}
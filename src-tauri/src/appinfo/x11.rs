@@ -0,0 +1,243 @@
+use super::{ActiveAppInfo, ActiveAppProvider};
+use anyhow::Result;
+use std::path::Path;
+use std::sync::Mutex;
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt, Window};
+use x11rb::rust_connection::RustConnection;
+
+struct Atoms {
+    net_active_window: Atom,
+    net_wm_pid: Atom,
+    net_wm_name: Atom,
+    utf8_string: Atom,
+}
+
+struct X11Connection {
+    conn: RustConnection,
+    root: Window,
+    atoms: Atoms,
+}
+
+impl X11Connection {
+    fn open() -> Result<Self> {
+        let (conn, screen_num) = x11rb::connect(None)?;
+        let root = conn.setup().roots[screen_num].root;
+        let intern =
+            |name: &[u8]| -> Result<Atom> { Ok(conn.intern_atom(false, name)?.reply()?.atom) };
+        let atoms = Atoms {
+            net_active_window: intern(b"_NET_ACTIVE_WINDOW")?,
+            net_wm_pid: intern(b"_NET_WM_PID")?,
+            net_wm_name: intern(b"_NET_WM_NAME")?,
+            utf8_string: intern(b"UTF8_STRING")?,
+        };
+        Ok(Self { conn, root, atoms })
+    }
+
+    fn property(&self, window: Window, property: Atom, kind: Atom) -> Result<Vec<u8>> {
+        let reply = self
+            .conn
+            .get_property(false, window, property, kind, 0, u32::MAX / 4)?
+            .reply()?;
+        Ok(reply.value)
+    }
+
+    fn property_u32(&self, window: Window, property: Atom, kind: Atom) -> Result<Option<u32>> {
+        let reply = self
+            .conn
+            .get_property(false, window, property, kind, 0, 1)?
+            .reply()?;
+        Ok(reply.value32().and_then(|mut values| values.next()))
+    }
+
+    fn active_app(&self) -> Result<Option<ActiveAppInfo>> {
+        let window = self.property_u32(
+            self.root,
+            self.atoms.net_active_window,
+            AtomEnum::WINDOW.into(),
+        )?;
+        // ウィンドウマネージャーによってはフォーカスがないと 0 になる
+        let Some(window) = window.filter(|w| *w != 0) else {
+            return Ok(None);
+        };
+        let wm_class = parse_wm_class(&self.property(
+            window,
+            AtomEnum::WM_CLASS.into(),
+            AtomEnum::STRING.into(),
+        )?);
+        let pid = self.property_u32(window, self.atoms.net_wm_pid, AtomEnum::CARDINAL.into())?;
+        let exe = pid.and_then(super::process_exe);
+        let Some(bundle_id) = stable_id(exe.as_deref(), wm_class.as_ref()) else {
+            return Ok(None);
+        };
+        let name = wm_class
+            .map(|(_, class)| class)
+            .or_else(|| {
+                exe.as_ref()
+                    .and_then(|exe| exe.file_name())
+                    .map(|name| name.to_string_lossy().to_string())
+            })
+            .unwrap_or_else(|| bundle_id.clone());
+        Ok(Some(ActiveAppInfo {
+            name,
+            bundle_id,
+            pid,
+            window_title: self.window_title(window)?,
+        }))
+    }
+
+    fn window_title(&self, window: Window) -> Result<Option<String>> {
+        let mut title = self.property(window, self.atoms.net_wm_name, self.atoms.utf8_string)?;
+        if title.is_empty() {
+            title = self.property(window, AtomEnum::WM_NAME.into(), AtomEnum::STRING.into())?;
+        }
+        Ok((!title.is_empty()).then(|| String::from_utf8_lossy(&title).to_string()))
+    }
+}
+
+/// X11の `_NET_ACTIVE_WINDOW` から前面アプリを取得する
+/// 接続が切れたら次の問い合わせでつなぎ直す
+pub struct X11AppProvider {
+    connection: Mutex<Option<X11Connection>>,
+}
+
+impl X11AppProvider {
+    pub fn connect() -> Result<Self> {
+        Ok(Self {
+            connection: Mutex::new(Some(X11Connection::open()?)),
+        })
+    }
+}
+
+impl ActiveAppProvider for X11AppProvider {
+    fn active_app(&self) -> Option<ActiveAppInfo> {
+        let mut connection = self.connection.lock().unwrap();
+        if connection.is_none() {
+            *connection = X11Connection::open().ok();
+        }
+        match connection.as_ref()?.active_app() {
+            Ok(info) => info,
+            Err(e) => {
+                eprintln!("[KeyFit] Failed to get active X11 window: {}", e);
+                *connection = None;
+                None
+            }
+        }
+    }
+}
+
+/// WM_CLASS（"instance\0class\0"）を (instance, class) に分ける
+fn parse_wm_class(value: &[u8]) -> Option<(String, String)> {
+    let mut parts = value
+        .split(|b| *b == 0)
+        .map(|part| String::from_utf8_lossy(part).to_string());
+    let instance = parts.next().filter(|s| !s.is_empty())?;
+    let class = parts
+        .next()
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| instance.clone());
+    Some((instance, class))
+}
+
+/// 実行ファイルのパスを識別子にする。取得できなければ WM_CLASS のクラス名を使う
+fn stable_id(exe: Option<&Path>, wm_class: Option<&(String, String)>) -> Option<String> {
+    if let Some(exe) = exe {
+        return Some(exe.to_string_lossy().to_string());
+    }
+    wm_class.map(|(_, class)| format!("x11:{class}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x11rb::protocol::xproto::{CreateWindowAux, PropMode, WindowClass};
+    use x11rb::wrapper::ConnectionExt as _;
+
+    #[test]
+    fn test_parse_wm_class() {
+        assert_eq!(
+            parse_wm_class(b"navigator\0Firefox\0"),
+            Some(("navigator".to_string(), "Firefox".to_string()))
+        );
+        assert_eq!(
+            parse_wm_class(b"xterm\0"),
+            Some(("xterm".to_string(), "xterm".to_string()))
+        );
+        assert_eq!(parse_wm_class(b""), None);
+
+        let class = ("code".to_string(), "Code".to_string());
+        assert_eq!(
+            stable_id(Some(Path::new("/usr/share/code/code")), Some(&class)),
+            Some("/usr/share/code/code".to_string())
+        );
+        assert_eq!(stable_id(None, Some(&class)), Some("x11:Code".to_string()));
+        assert_eq!(stable_id(None, None), None);
+    }
+
+    /// `xvfb-run cargo test -- --ignored` で実行する
+    #[test]
+    #[ignore = "requires an X server (e.g. Xvfb)"]
+    fn test_x11_active_window() {
+        let (conn, screen_num) = x11rb::connect(None).unwrap();
+        let root = conn.setup().roots[screen_num].root;
+        let window = conn.generate_id().unwrap();
+        conn.create_window(
+            0,
+            window,
+            root,
+            0,
+            0,
+            10,
+            10,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            0,
+            &CreateWindowAux::new(),
+        )
+        .unwrap();
+        let atom = |name: &[u8]| conn.intern_atom(false, name).unwrap().reply().unwrap().atom;
+        conn.change_property8(
+            PropMode::REPLACE,
+            window,
+            AtomEnum::WM_CLASS,
+            AtomEnum::STRING,
+            b"keyfit-test\0KeyFitTest\0",
+        )
+        .unwrap();
+        conn.change_property32(
+            PropMode::REPLACE,
+            window,
+            atom(b"_NET_WM_PID"),
+            AtomEnum::CARDINAL,
+            &[std::process::id()],
+        )
+        .unwrap();
+        conn.change_property8(
+            PropMode::REPLACE,
+            window,
+            atom(b"_NET_WM_NAME"),
+            atom(b"UTF8_STRING"),
+            "テスト".as_bytes(),
+        )
+        .unwrap();
+        // Xvfb にはウィンドウマネージャーがないので自分で前面ウィンドウを設定する
+        conn.change_property32(
+            PropMode::REPLACE,
+            root,
+            atom(b"_NET_ACTIVE_WINDOW"),
+            AtomEnum::WINDOW,
+            &[window],
+        )
+        .unwrap();
+        conn.sync().unwrap();
+
+        let info = X11AppProvider::connect().unwrap().active_app().unwrap();
+        assert_eq!(info.name, "KeyFitTest");
+        assert_eq!(info.pid, Some(std::process::id()));
+        assert_eq!(info.window_title.as_deref(), Some("テスト"));
+        assert_eq!(
+            info.bundle_id,
+            std::env::current_exe().unwrap().to_string_lossy()
+        );
+    }
+}
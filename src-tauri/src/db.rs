@@ -1,16 +1,43 @@
-use crate::keyboard::KeyStat;
-use anyhow::Result;
-use rusqlite::{params, Connection};
+use crate::exclusion::{ExclusionPattern, ExclusionSet, PatternKind};
+use crate::experiment::{Experiment, ExperimentSpec};
+use crate::input::InputDevice;
+use crate::inventory::{Keyboard, KeyboardPeriod, KeyboardSpec};
+use crate::keyboard::{KeyBigram, KeyDwell, KeyStat, ShortcutStat};
+use crate::migration;
+use crate::taphold::{TapHoldCounts, TapHoldItem, TapHoldKey, TAPPING_TERMS_MS};
+use anyhow::{anyhow, Result};
+use rusqlite::{params, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Mutex;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyRankingItem {
     pub key_code: String,
     pub count: i64,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyBigramItem {
+    pub prev_key: String,
+    pub key_code: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShortcutRankingItem {
+    pub shortcut: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyDwellItem {
+    pub key_code: String,
+    pub bucket_ms: i64,
+    pub count: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppInfo {
     pub id: i64,
@@ -18,6 +45,18 @@ pub struct AppInfo {
     pub bundle_id: String,
 }
 
+/// 入力元のキーボード
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub id: i64,
+    pub name: String,
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+}
+
+/// デバイスを区別できない入力元（rdev）の押下と、デバイス記録を導入する前の履歴
+pub const UNKNOWN_DEVICE_ID: i64 = 0;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct KeyStatsByDay {
     pub ts_day: i64,
@@ -31,6 +70,132 @@ pub struct DateRange {
     pub max: i64,
 }
 
+/// 時刻（ローカル時間）・曜日ごとの押下数
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HourlyKeyCount {
+    /// 0=日曜〜6=土曜
+    pub weekday: i64,
+    pub hour: i64,
+    pub count: i64,
+}
+
+/// 集計クエリの絞り込み条件（時刻・曜日はローカル時間）
+/// コマンドの `filter` 引数としてフロントエンドからそのまま受け取る
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StatFilter {
+    pub start_date: Option<i64>,
+    pub end_date: Option<i64>,
+    pub app_id: Option<i64>,
+    /// `UNKNOWN_DEVICE_ID` でデバイス不明の押下だけを絞り込む
+    pub device_id: Option<i64>,
+    /// 0〜23。日単位でしか記録されていない行は含まれない
+    pub hours: Option<Vec<u32>>,
+    /// 0=日曜〜6=土曜
+    pub weekdays: Option<Vec<u32>>,
+}
+
+impl StatFilter {
+    pub fn new(start_date: Option<i64>, end_date: Option<i64>, app_id: Option<i64>) -> Self {
+        Self {
+            start_date,
+            end_date,
+            app_id,
+            ..Default::default()
+        }
+    }
+
+    /// WHERE句の条件とパラメータ
+    fn conditions(&self) -> (Vec<String>, Vec<String>) {
+        let mut conditions = Vec::new();
+        let mut params_vec = Vec::new();
+
+        if let Some(start) = self.start_date {
+            conditions.push("ts_day >= ?".to_string());
+            params_vec.push(start.to_string());
+        }
+        if let Some(end) = self.end_date {
+            conditions.push("ts_day <= ?".to_string());
+            params_vec.push(end.to_string());
+        }
+        if let Some(app) = self.app_id {
+            conditions.push("app_id = ?".to_string());
+            params_vec.push(app.to_string());
+        }
+        if let Some(device) = self.device_id {
+            conditions.push("device_id = ?".to_string());
+            params_vec.push(device.to_string());
+        }
+        if let Some(hours) = &self.hours {
+            conditions.push(format!(
+                "ts_hour IS NOT NULL AND {} IN ({})",
+                LOCAL_HOUR_SQL,
+                join_numbers(hours)
+            ));
+        }
+        if let Some(weekdays) = &self.weekdays {
+            conditions.push(format!(
+                "{} IN ({})",
+                LOCAL_WEEKDAY_SQL,
+                join_numbers(weekdays)
+            ));
+        }
+
+        (conditions, params_vec)
+    }
+}
+
+/// ts_hour のローカル時刻（0〜23）
+const LOCAL_HOUR_SQL: &str = "CAST(strftime('%H', ts_hour, 'unixepoch', 'localtime') AS INTEGER)";
+/// ts_day のローカル曜日（0=日曜）
+const LOCAL_WEEKDAY_SQL: &str = "CAST(strftime('%w', ts_day, 'unixepoch', 'localtime') AS INTEGER)";
+
+fn join_numbers(values: &[u32]) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// 履歴の削除方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PurgeMode {
+    /// 行を削除する
+    Delete,
+    /// アプリを特定できない匿名アプリに付け替える（合計値は残る）
+    Anonymize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PurgeReport {
+    pub dry_run: bool,
+    /// 全集計テーブルで対象になった行数
+    pub rows: i64,
+    /// 対象になったキー押下数（key_stat）
+    pub presses: i64,
+    /// 履歴がなくなり app テーブルから削除されたか
+    pub app_removed: bool,
+}
+
+/// 匿名化した履歴の付け替え先
+const ANONYMIZED_APP_NAME: &str = "Anonymized";
+const ANONYMIZED_BUNDLE_ID: &str = "keyfit.anonymized";
+
+/// app_id・device_id を持つ集計テーブル（テーブル名, 集計の単位となる列, 加算する列）
+const STAT_TABLES: &[(&str, &[&str], &[&str])] = &[
+    ("key_stat", &["key_code"], &["count"]),
+    ("key_bigram", &["prev_key", "key_code"], &["count"]),
+    ("shortcut_stat", &["shortcut"], &["count"]),
+    ("key_dwell", &["key_code", "bucket_ms"], &["count"]),
+    (
+        "tap_hold_stat",
+        &["key_code", "tapping_term_ms"],
+        &["presses", "misfires"],
+    ),
+    ("key_repeat_stat", &["key_code"], &["count"]),
+];
+
 pub struct Database {
     conn: Mutex<Connection>,
 }
@@ -38,47 +203,11 @@ pub struct Database {
 impl Database {
     #[allow(dead_code)]
     pub fn new(db_path: &Path) -> Result<Self> {
-        let conn = Connection::open(db_path)?;
-        let db = Database {
+        let mut conn = Connection::open(db_path)?;
+        migration::migrate(&mut conn, db_path)?;
+        Ok(Database {
             conn: Mutex::new(conn),
-        };
-        db.init()?;
-        Ok(db)
-    }
-
-    #[allow(dead_code)]
-    fn init(&self) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS app (
-                id INTEGER PRIMARY KEY,
-                name TEXT NOT NULL,
-                bundle_id TEXT NOT NULL
-            )",
-            [],
-        )?;
-
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS key_stat (
-                ts_day INTEGER NOT NULL,
-                key_code TEXT NOT NULL,
-                app_id INTEGER NOT NULL,
-                count INTEGER NOT NULL,
-                PRIMARY KEY (ts_day, key_code, app_id),
-                FOREIGN KEY (app_id) REFERENCES app(id)
-            )",
-            [],
-        )?;
-
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS excluded_app (
-                app_id INTEGER PRIMARY KEY,
-                FOREIGN KEY (app_id) REFERENCES app(id)
-            )",
-            [],
-        )?;
-
-        Ok(())
+        })
     }
 
     pub fn get_or_create_app(&self, name: &str, bundle_id: &str) -> Result<i64> {
@@ -100,46 +229,187 @@ impl Database {
         Ok(app_id)
     }
 
-    pub fn batch_insert_key_stats(&self, stats: &[KeyStat]) -> anyhow::Result<()> {
+    /// キーごとにまとめた押下数を加算する（1キーにつき1回のUPSERT）
+    pub fn batch_insert_key_stats(&self, stats: &HashMap<KeyStat, i64>) -> anyhow::Result<()> {
         let mut conn = self.conn.lock().unwrap();
         let tx = conn.transaction()?;
-        for stat in stats {
-            tx.execute(
-                "INSERT INTO key_stat (ts_day, key_code, app_id, count)
-                VALUES (?1, ?2, ?3, 1)
-                ON CONFLICT(ts_day, key_code, app_id)
-                DO UPDATE SET count = count + 1",
-                rusqlite::params![stat.ts_day, stat.key_code, stat.app_id], // ←ここ
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO key_stat (ts_day, ts_hour, key_code, app_id, device_id, count)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                ON CONFLICT(ts_hour, key_code, app_id, device_id)
+                DO UPDATE SET count = count + excluded.count",
             )?;
+            for (stat, count) in stats {
+                stmt.execute(params![
+                    stat.ts_day,
+                    stat.ts_hour,
+                    stat.key_code,
+                    stat.app_id,
+                    stat.device_id,
+                    count
+                ])?;
+            }
         }
         tx.commit()?;
         Ok(())
     }
 
-    pub fn get_key_ranking(
+    pub fn batch_insert_key_bigrams(&self, bigrams: &HashMap<KeyBigram, i64>) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO key_bigram
+                (ts_day, ts_hour, prev_key, key_code, app_id, device_id, count)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                ON CONFLICT(ts_hour, prev_key, key_code, app_id, device_id)
+                DO UPDATE SET count = count + excluded.count",
+            )?;
+            for (bigram, count) in bigrams {
+                stmt.execute(params![
+                    bigram.ts_day,
+                    bigram.ts_hour,
+                    bigram.prev_key,
+                    bigram.key_code,
+                    bigram.app_id,
+                    bigram.device_id,
+                    count
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn batch_insert_shortcut_stats(
         &self,
-        start_date: Option<i64>,
-        end_date: Option<i64>,
-        app_id: Option<i64>,
-        limit: Option<i64>,
-    ) -> Result<Vec<KeyRankingItem>> {
-        let conn = self.conn.lock().unwrap();
-        let mut query = "SELECT key_code, SUM(count) as total_count FROM key_stat".to_string();
-        let mut conditions = Vec::new();
-        let mut params_vec = Vec::new();
+        shortcuts: &HashMap<ShortcutStat, i64>,
+    ) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO shortcut_stat (ts_day, ts_hour, shortcut, app_id, device_id, count)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                ON CONFLICT(ts_hour, shortcut, app_id, device_id)
+                DO UPDATE SET count = count + excluded.count",
+            )?;
+            for (shortcut, count) in shortcuts {
+                stmt.execute(params![
+                    shortcut.ts_day,
+                    shortcut.ts_hour,
+                    shortcut.shortcut,
+                    shortcut.app_id,
+                    shortcut.device_id,
+                    count
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
 
-        if let Some(start) = start_date {
-            conditions.push("ts_day >= ?".to_string());
-            params_vec.push(start.to_string());
+    pub fn batch_insert_key_dwells(&self, dwells: &HashMap<KeyDwell, i64>) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO key_dwell
+                (ts_day, ts_hour, key_code, app_id, device_id, bucket_ms, count)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                ON CONFLICT(ts_hour, key_code, app_id, device_id, bucket_ms)
+                DO UPDATE SET count = count + excluded.count",
+            )?;
+            for (dwell, count) in dwells {
+                stmt.execute(params![
+                    dwell.ts_day,
+                    dwell.ts_hour,
+                    dwell.key_code,
+                    dwell.app_id,
+                    dwell.device_id,
+                    dwell.bucket_ms,
+                    count
+                ])?;
+            }
         }
-        if let Some(end) = end_date {
-            conditions.push("ts_day <= ?".to_string());
-            params_vec.push(end.to_string());
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// タッピングタームごとに押下数と誤爆数を加算する
+    pub fn batch_insert_tap_hold_counts(
+        &self,
+        tap_holds: &HashMap<TapHoldKey, TapHoldCounts>,
+    ) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO tap_hold_stat
+                (ts_day, ts_hour, key_code, app_id, device_id, tapping_term_ms, presses, misfires)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                ON CONFLICT(ts_hour, key_code, app_id, device_id, tapping_term_ms)
+                DO UPDATE SET presses = presses + excluded.presses,
+                    misfires = misfires + excluded.misfires",
+            )?;
+            for (key, counts) in tap_holds {
+                for (term, misfires) in TAPPING_TERMS_MS.iter().zip(&counts.misfires) {
+                    stmt.execute(params![
+                        key.ts_day,
+                        key.ts_hour,
+                        key.key_code,
+                        key.app_id,
+                        key.device_id,
+                        term,
+                        counts.presses,
+                        misfires
+                    ])?;
+                }
+            }
         }
-        if let Some(app) = app_id {
-            conditions.push("app_id = ?".to_string());
-            params_vec.push(app.to_string());
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// OSのオートリピートによる押下を数える
+    pub fn batch_insert_key_repeats(&self, repeats: &HashMap<KeyStat, i64>) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO key_repeat_stat (ts_day, ts_hour, key_code, app_id, device_id, count)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                ON CONFLICT(ts_hour, key_code, app_id, device_id)
+                DO UPDATE SET count = count + excluded.count",
+            )?;
+            for (repeat, count) in repeats {
+                stmt.execute(params![
+                    repeat.ts_day,
+                    repeat.ts_hour,
+                    repeat.key_code,
+                    repeat.app_id,
+                    repeat.device_id,
+                    count
+                ])?;
+            }
         }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get_key_ranking(
+        &self,
+        filter: &StatFilter,
+        limit: Option<i64>,
+        include_repeats: bool,
+    ) -> Result<Vec<KeyRankingItem>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = format!(
+            "SELECT key_code, SUM(count) as total_count FROM {}",
+            key_count_source(include_repeats)
+        );
+        let (conditions, params_vec) = filter.conditions();
 
         if !conditions.is_empty() {
             query.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
@@ -171,55 +441,65 @@ impl Database {
         Ok(ranking)
     }
 
-    pub fn get_apps(&self) -> Result<Vec<AppInfo>> {
+    pub fn get_bigram_ranking(
+        &self,
+        filter: &StatFilter,
+        limit: Option<i64>,
+    ) -> Result<Vec<KeyBigramItem>> {
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT id, name, bundle_id FROM app ORDER BY name")?;
-        let rows = stmt.query_map([], |row| {
-            Ok(AppInfo {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                bundle_id: row.get(2)?,
+        let mut query =
+            "SELECT prev_key, key_code, SUM(count) as total_count FROM key_bigram".to_string();
+        let (conditions, params_vec) = filter.conditions();
+
+        if !conditions.is_empty() {
+            query.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        }
+
+        query.push_str(" GROUP BY prev_key, key_code ORDER BY total_count DESC");
+
+        if let Some(lim) = limit {
+            query.push_str(&format!(" LIMIT {}", lim));
+        }
+
+        let mut stmt = conn.prepare(&query)?;
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec
+            .iter()
+            .map(|s| s as &dyn rusqlite::ToSql)
+            .collect();
+
+        let rows = stmt.query_map(params_refs.as_slice(), |row| {
+            Ok(KeyBigramItem {
+                prev_key: row.get(0)?,
+                key_code: row.get(1)?,
+                count: row.get(2)?,
             })
         })?;
 
-        let mut apps = Vec::new();
+        let mut ranking = Vec::new();
         for row in rows {
-            apps.push(row?);
+            ranking.push(row?);
         }
-        Ok(apps)
+        Ok(ranking)
     }
 
-    #[allow(dead_code)]
-    pub fn get_key_stats_by_day(
+    pub fn get_shortcut_ranking(
         &self,
-        start_date: Option<i64>,
-        end_date: Option<i64>,
-        app_id: Option<i64>,
-    ) -> Result<Vec<KeyStatsByDay>> {
+        filter: &StatFilter,
+        limit: Option<i64>,
+    ) -> Result<Vec<ShortcutRankingItem>> {
         let conn = self.conn.lock().unwrap();
-        let mut query =
-            "SELECT ts_day, key_code, SUM(count) as total_count FROM key_stat".to_string();
-        let mut conditions = Vec::new();
-        let mut params_vec = Vec::new();
-
-        if let Some(start) = start_date {
-            conditions.push("ts_day >= ?".to_string());
-            params_vec.push(start.to_string());
-        }
-        if let Some(end) = end_date {
-            conditions.push("ts_day <= ?".to_string());
-            params_vec.push(end.to_string());
-        }
-        if let Some(app) = app_id {
-            conditions.push("app_id = ?".to_string());
-            params_vec.push(app.to_string());
-        }
+        let mut query = "SELECT shortcut, SUM(count) as total_count FROM shortcut_stat".to_string();
+        let (conditions, params_vec) = filter.conditions();
 
         if !conditions.is_empty() {
             query.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
         }
 
-        query.push_str(" GROUP BY ts_day, key_code ORDER BY ts_day, total_count DESC");
+        query.push_str(" GROUP BY shortcut ORDER BY total_count DESC");
+
+        if let Some(lim) = limit {
+            query.push_str(&format!(" LIMIT {}", lim));
+        }
 
         let mut stmt = conn.prepare(&query)?;
         let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec
@@ -228,94 +508,909 @@ impl Database {
             .collect();
 
         let rows = stmt.query_map(params_refs.as_slice(), |row| {
-            Ok(KeyStatsByDay {
-                ts_day: row.get(0)?,
-                key_code: row.get(1)?,
-                count: row.get(2)?,
+            Ok(ShortcutRankingItem {
+                shortcut: row.get(0)?,
+                count: row.get(1)?,
             })
         })?;
 
-        let mut stats = Vec::new();
+        let mut ranking = Vec::new();
         for row in rows {
-            stats.push(row?);
+            ranking.push(row?);
         }
-        Ok(stats)
+        Ok(ranking)
     }
 
-    pub fn get_total_key_count(
+    /// キーごとの押下時間ヒストグラム（key_code, bucket_ms の昇順）
+    pub fn get_key_dwell_histogram(
         &self,
-        start_date: Option<i64>,
-        end_date: Option<i64>,
-        app_id: Option<i64>,
-    ) -> Result<i64> {
+        filter: &StatFilter,
+        key_code: Option<String>,
+    ) -> Result<Vec<KeyDwellItem>> {
         let conn = self.conn.lock().unwrap();
-        let mut query = "SELECT SUM(count) FROM key_stat".to_string();
-        let mut conditions = Vec::new();
-        let mut params_vec = Vec::new();
-
-        if let Some(start) = start_date {
-            conditions.push("ts_day >= ?".to_string());
-            params_vec.push(start.to_string());
-        }
-        if let Some(end) = end_date {
-            conditions.push("ts_day <= ?".to_string());
-            params_vec.push(end.to_string());
-        }
-        if let Some(app) = app_id {
-            conditions.push("app_id = ?".to_string());
-            params_vec.push(app.to_string());
+        let mut query =
+            "SELECT key_code, bucket_ms, SUM(count) as total_count FROM key_dwell".to_string();
+        let (mut conditions, mut params_vec) = filter.conditions();
+        if let Some(key) = key_code {
+            conditions.push("key_code = ?".to_string());
+            params_vec.push(key);
         }
 
         if !conditions.is_empty() {
             query.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
         }
 
+        query.push_str(" GROUP BY key_code, bucket_ms ORDER BY key_code, bucket_ms");
+
         let mut stmt = conn.prepare(&query)?;
         let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec
             .iter()
             .map(|s| s as &dyn rusqlite::ToSql)
             .collect();
 
-        let total: Option<i64> = stmt.query_row(params_refs.as_slice(), |row| row.get(0))?;
-        Ok(total.unwrap_or(0))
+        let rows = stmt.query_map(params_refs.as_slice(), |row| {
+            Ok(KeyDwellItem {
+                key_code: row.get(0)?,
+                bucket_ms: row.get(1)?,
+                count: row.get(2)?,
+            })
+        })?;
+
+        let mut histogram = Vec::new();
+        for row in rows {
+            histogram.push(row?);
+        }
+        Ok(histogram)
     }
 
-    pub fn get_date_range(&self) -> Result<DateRange> {
+    pub fn get_tap_hold_stats(&self, filter: &StatFilter) -> Result<Vec<TapHoldItem>> {
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT MIN(ts_day), MAX(ts_day) FROM key_stat")?;
-        let (min, max): (Option<i64>, Option<i64>) =
-            stmt.query_row([], |row| Ok((row.get(0)?, row.get(1)?)))?;
-        Ok(DateRange {
-            min: min.unwrap_or(0),
-            max: max.unwrap_or(0),
-        })
-    }
-}
+        let mut query =
+            "SELECT tapping_term_ms, key_code, SUM(presses), SUM(misfires) FROM tap_hold_stat"
+                .to_string();
+        let (conditions, params_vec) = filter.conditions();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::NamedTempFile;
+        if !conditions.is_empty() {
+            query.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        }
 
-    fn setup_test_db() -> (Database, NamedTempFile) {
-        let temp_file = NamedTempFile::new().unwrap();
-        let db = Database::new(temp_file.path()).unwrap();
-        (db, temp_file)
-    }
+        query.push_str(" GROUP BY tapping_term_ms, key_code");
 
-    #[test]
-    fn test_record_key_press() {
-        let (db, _temp_file) = setup_test_db();
-        let app_id = db.get_or_create_app("Test App", "com.test.app").unwrap();
-        let key_code = "KeyA";
-        let now = chrono::Utc::now().date_naive();
-        let ts_day = now.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
-        let key_stat = KeyStat {
+        let mut stmt = conn.prepare(&query)?;
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec
+            .iter()
+            .map(|s| s as &dyn rusqlite::ToSql)
+            .collect();
+
+        let rows = stmt.query_map(params_refs.as_slice(), |row| {
+            Ok(TapHoldItem {
+                tapping_term_ms: row.get(0)?,
+                key_code: row.get(1)?,
+                presses: row.get(2)?,
+                misfires: row.get(3)?,
+            })
+        })?;
+
+        let mut items = Vec::new();
+        for row in rows {
+            items.push(row?);
+        }
+        Ok(items)
+    }
+
+    pub fn get_apps(&self) -> Result<Vec<AppInfo>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, name, bundle_id FROM app ORDER BY name")?;
+        let rows = stmt.query_map([], |row| {
+            Ok(AppInfo {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                bundle_id: row.get(2)?,
+            })
+        })?;
+
+        let mut apps = Vec::new();
+        for row in rows {
+            apps.push(row?);
+        }
+        Ok(apps)
+    }
+
+    /// キーボードの行を探し、なければ作る
+    pub fn get_or_create_device(&self, device: &InputDevice) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR IGNORE INTO device (name, vendor_id, product_id, identifier)
+            VALUES (?1, ?2, ?3, ?4)",
+            params![
+                device.name,
+                device.vendor_id,
+                device.product_id,
+                device.identifier()
+            ],
+        )?;
+        let device_id = conn.query_row(
+            "SELECT id FROM device WHERE identifier = ?1",
+            params![device.identifier()],
+            |row| row.get(0),
+        )?;
+        Ok(device_id)
+    }
+
+    pub fn get_devices(&self) -> Result<Vec<DeviceInfo>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare("SELECT id, name, vendor_id, product_id FROM device ORDER BY name")?;
+        let rows = stmt.query_map([], |row| {
+            Ok(DeviceInfo {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                vendor_id: row.get(2)?,
+                product_id: row.get(3)?,
+            })
+        })?;
+
+        let mut devices = Vec::new();
+        for row in rows {
+            devices.push(row?);
+        }
+        Ok(devices)
+    }
+
+    /// キーボードの在庫（割り当てたデバイスを含む）
+    pub fn get_keyboards(&self) -> Result<Vec<Keyboard>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT keyboard_id, device_id FROM keyboard_device")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get(1)?)))?;
+        let mut devices: HashMap<i64, Vec<i64>> = HashMap::new();
+        for row in rows {
+            let (keyboard_id, device_id) = row?;
+            devices.entry(keyboard_id).or_default().push(device_id);
+        }
+
+        let mut stmt = conn.prepare(
+            "SELECT id, name, layout_id, switch_type, rated_lifetime FROM keyboard ORDER BY name",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let id = row.get(0)?;
+            Ok(Keyboard {
+                id,
+                spec: KeyboardSpec {
+                    name: row.get(1)?,
+                    layout_id: row.get(2)?,
+                    switch_type: row.get(3)?,
+                    rated_lifetime: row.get(4)?,
+                },
+                device_ids: devices.remove(&id).unwrap_or_default(),
+            })
+        })?;
+
+        let mut keyboards = Vec::new();
+        for row in rows {
+            keyboards.push(row?);
+        }
+        Ok(keyboards)
+    }
+
+    pub fn add_keyboard(&self, spec: &KeyboardSpec) -> Result<i64> {
+        spec.validate()?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO keyboard (name, layout_id, switch_type, rated_lifetime)
+            VALUES (?1, ?2, ?3, ?4)",
+            params![
+                spec.name,
+                spec.layout_id,
+                spec.switch_type,
+                spec.rated_lifetime
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn update_keyboard(&self, keyboard_id: i64, spec: &KeyboardSpec) -> Result<()> {
+        spec.validate()?;
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE keyboard SET name = ?2, layout_id = ?3, switch_type = ?4, rated_lifetime = ?5
+            WHERE id = ?1",
+            params![
+                keyboard_id,
+                spec.name,
+                spec.layout_id,
+                spec.switch_type,
+                spec.rated_lifetime
+            ],
+        )?;
+        if updated == 0 {
+            return Err(anyhow!("Unknown keyboard: {keyboard_id}"));
+        }
+        Ok(())
+    }
+
+    /// キーボードと割り当てを削除する（押下の履歴は残る）
+    pub fn remove_keyboard(&self, keyboard_id: i64) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM keyboard_device WHERE keyboard_id = ?1",
+            params![keyboard_id],
+        )?;
+        tx.execute(
+            "DELETE FROM keyboard_period WHERE keyboard_id = ?1",
+            params![keyboard_id],
+        )?;
+        tx.execute("DELETE FROM keyboard WHERE id = ?1", params![keyboard_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// デバイスの押下を自動でキーボードに割り当てる（None で割り当てを外す）
+    pub fn set_device_keyboard(&self, device_id: i64, keyboard_id: Option<i64>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        if !row_exists(&conn, "device", device_id)? {
+            return Err(anyhow!("Unknown device: {device_id}"));
+        }
+        match keyboard_id {
+            Some(keyboard_id) => {
+                if !row_exists(&conn, "keyboard", keyboard_id)? {
+                    return Err(anyhow!("Unknown keyboard: {keyboard_id}"));
+                }
+                conn.execute(
+                    "INSERT INTO keyboard_device (device_id, keyboard_id) VALUES (?1, ?2)
+                    ON CONFLICT(device_id) DO UPDATE SET keyboard_id = excluded.keyboard_id",
+                    params![device_id, keyboard_id],
+                )?
+            }
+            None => conn.execute(
+                "DELETE FROM keyboard_device WHERE device_id = ?1",
+                params![device_id],
+            )?,
+        };
+        Ok(())
+    }
+
+    pub fn get_keyboard_periods(&self, keyboard_id: i64) -> Result<Vec<KeyboardPeriod>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, keyboard_id, start_date, end_date FROM keyboard_period
+            WHERE keyboard_id = ?1 ORDER BY start_date",
+        )?;
+        let rows = stmt.query_map(params![keyboard_id], |row| {
+            Ok(KeyboardPeriod {
+                id: row.get(0)?,
+                keyboard_id: row.get(1)?,
+                start_date: row.get(2)?,
+                end_date: row.get(3)?,
+            })
+        })?;
+
+        let mut periods = Vec::new();
+        for row in rows {
+            periods.push(row?);
+        }
+        Ok(periods)
+    }
+
+    /// デバイス不明の押下を期間で手動割り当てする
+    pub fn add_keyboard_period(
+        &self,
+        keyboard_id: i64,
+        start_date: i64,
+        end_date: Option<i64>,
+    ) -> Result<i64> {
+        if end_date.is_some_and(|end| end < start_date) {
+            return Err(anyhow!("Period ends before it starts"));
+        }
+        let conn = self.conn.lock().unwrap();
+        if !row_exists(&conn, "keyboard", keyboard_id)? {
+            return Err(anyhow!("Unknown keyboard: {keyboard_id}"));
+        }
+        conn.execute(
+            "INSERT INTO keyboard_period (keyboard_id, start_date, end_date) VALUES (?1, ?2, ?3)",
+            params![keyboard_id, start_date, end_date],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn remove_keyboard_period(&self, period_id: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM keyboard_period WHERE id = ?1",
+            params![period_id],
+        )?;
+        Ok(())
+    }
+
+    /// キーボードに割り当てた押下数（オートリピートはスイッチを打鍵しないので含めない）
+    pub fn get_keyboard_key_ranking(&self, keyboard_id: i64) -> Result<Vec<KeyRankingItem>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT key_code, SUM(count) as total_count FROM key_stat WHERE {}
+            GROUP BY key_code ORDER BY total_count DESC",
+            keyboard_condition()
+        ))?;
+        let rows = stmt.query_map(params![keyboard_id], |row| {
+            Ok(KeyRankingItem {
+                key_code: row.get(0)?,
+                count: row.get(1)?,
+            })
+        })?;
+
+        let mut ranking = Vec::new();
+        for row in rows {
+            ranking.push(row?);
+        }
+        Ok(ranking)
+    }
+
+    /// キーボードで押下を記録した日数
+    pub fn get_keyboard_active_day_count(&self, keyboard_id: i64) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        let days = conn.query_row(
+            &format!(
+                "SELECT COUNT(DISTINCT ts_day) FROM key_stat WHERE {}",
+                keyboard_condition()
+            ),
+            params![keyboard_id],
+            |row| row.get(0),
+        )?;
+        Ok(days)
+    }
+
+    pub fn get_experiments(&self) -> Result<Vec<Experiment>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, name, start_date, end_date, baseline_start_date, baseline_end_date
+            FROM experiment ORDER BY start_date DESC",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(Experiment {
+                id: row.get(0)?,
+                spec: ExperimentSpec {
+                    name: row.get(1)?,
+                    start_date: row.get(2)?,
+                    end_date: row.get(3)?,
+                    baseline_start_date: row.get(4)?,
+                    baseline_end_date: row.get(5)?,
+                },
+            })
+        })?;
+
+        let mut experiments = Vec::new();
+        for row in rows {
+            experiments.push(row?);
+        }
+        Ok(experiments)
+    }
+
+    pub fn add_experiment(&self, spec: &ExperimentSpec) -> Result<i64> {
+        spec.validate()?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO experiment
+            (name, start_date, end_date, baseline_start_date, baseline_end_date)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                spec.name,
+                spec.start_date,
+                spec.end_date,
+                spec.baseline_start_date,
+                spec.baseline_end_date
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn update_experiment(&self, experiment_id: i64, spec: &ExperimentSpec) -> Result<()> {
+        spec.validate()?;
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE experiment SET name = ?2, start_date = ?3, end_date = ?4,
+            baseline_start_date = ?5, baseline_end_date = ?6
+            WHERE id = ?1",
+            params![
+                experiment_id,
+                spec.name,
+                spec.start_date,
+                spec.end_date,
+                spec.baseline_start_date,
+                spec.baseline_end_date
+            ],
+        )?;
+        if updated == 0 {
+            return Err(anyhow!("Unknown experiment: {experiment_id}"));
+        }
+        Ok(())
+    }
+
+    /// 実験の期間を削除する（押下の履歴は残る）
+    pub fn remove_experiment(&self, experiment_id: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let removed = conn.execute(
+            "DELETE FROM experiment WHERE id = ?1",
+            params![experiment_id],
+        )?;
+        if removed == 0 {
+            return Err(anyhow!("Unknown experiment: {experiment_id}"));
+        }
+        Ok(())
+    }
+
+    pub fn get_excluded_apps(&self) -> Result<Vec<AppInfo>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT app.id, app.name, app.bundle_id FROM excluded_app
+            JOIN app ON app.id = excluded_app.app_id ORDER BY app.name",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(AppInfo {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                bundle_id: row.get(2)?,
+            })
+        })?;
+
+        let mut apps = Vec::new();
+        for row in rows {
+            apps.push(row?);
+        }
+        Ok(apps)
+    }
+
+    pub fn add_excluded_app(&self, app_id: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR IGNORE INTO excluded_app (app_id) VALUES (?1)",
+            params![app_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_excluded_app(&self, app_id: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM excluded_app WHERE app_id = ?1",
+            params![app_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_excluded_patterns(&self) -> Result<Vec<ExclusionPattern>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare("SELECT id, kind, pattern FROM excluded_pattern ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+
+        let mut patterns = Vec::new();
+        for row in rows {
+            let (id, kind, pattern) = row?;
+            patterns.push(ExclusionPattern {
+                id,
+                kind: PatternKind::parse(&kind)?,
+                pattern,
+            });
+        }
+        Ok(patterns)
+    }
+
+    pub fn add_excluded_pattern(&self, kind: PatternKind, pattern: &str) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR IGNORE INTO excluded_pattern (kind, pattern) VALUES (?1, ?2)",
+            params![kind.as_str(), pattern],
+        )?;
+        let id = conn.query_row(
+            "SELECT id FROM excluded_pattern WHERE kind = ?1 AND pattern = ?2",
+            params![kind.as_str(), pattern],
+            |row| row.get(0),
+        )?;
+        Ok(id)
+    }
+
+    pub fn remove_excluded_pattern(&self, id: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM excluded_pattern WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// 削除した内容がファイルの空き領域に残らないようにする
+    /// 終わるまでフックの書き込みも待たせるので、必要なときだけ呼ぶ
+    pub fn vacuum(&self) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("VACUUM", [])?;
+        Ok(())
+    }
+
+    /// アプリの履歴を削除または匿名化する（期間指定がなければ全期間）
+    /// dry_run では件数だけを返し、何も変更しない
+    pub fn purge_app_data(
+        &self,
+        app_id: i64,
+        start_date: Option<i64>,
+        end_date: Option<i64>,
+        mode: PurgeMode,
+        dry_run: bool,
+    ) -> Result<PurgeReport> {
+        let filter = StatFilter::new(start_date, end_date, Some(app_id));
+        let (conditions, params_vec) = filter.conditions();
+        let where_clause = conditions.join(" AND ");
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec
+            .iter()
+            .map(|s| s as &dyn rusqlite::ToSql)
+            .collect();
+
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        // 匿名アプリを自分自身に付け替えると、付け替えた行ごと削除してしまう
+        if mode == PurgeMode::Anonymize {
+            let bundle_id: Option<String> = tx
+                .query_row(
+                    "SELECT bundle_id FROM app WHERE id = ?1",
+                    params![app_id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if bundle_id.as_deref() == Some(ANONYMIZED_BUNDLE_ID) {
+                return Err(anyhow!("Anonymized data cannot be anonymized again"));
+            }
+        }
+
+        let mut rows = 0;
+        for (table, _, _) in STAT_TABLES {
+            let count: i64 = tx.query_row(
+                &format!("SELECT COUNT(*) FROM {table} WHERE {where_clause}"),
+                params_refs.as_slice(),
+                |row| row.get(0),
+            )?;
+            rows += count;
+        }
+        let presses: Option<i64> = tx.query_row(
+            &format!("SELECT SUM(count) FROM key_stat WHERE {where_clause}"),
+            params_refs.as_slice(),
+            |row| row.get(0),
+        )?;
+        let presses = presses.unwrap_or(0);
+
+        if dry_run {
+            return Ok(PurgeReport {
+                dry_run,
+                rows,
+                presses,
+                app_removed: false,
+            });
+        }
+
+        let anonymized_id = if mode == PurgeMode::Anonymize {
+            tx.execute(
+                "INSERT INTO app (name, bundle_id)
+                SELECT ?1, ?2 WHERE NOT EXISTS (SELECT 1 FROM app WHERE bundle_id = ?2)",
+                params![ANONYMIZED_APP_NAME, ANONYMIZED_BUNDLE_ID],
+            )?;
+            let id: i64 = tx.query_row(
+                "SELECT id FROM app WHERE bundle_id = ?1",
+                params![ANONYMIZED_BUNDLE_ID],
+                |row| row.get(0),
+            )?;
+            Some(id)
+        } else {
+            None
+        };
+
+        for (table, dims, sums) in STAT_TABLES {
+            if let Some(anonymized_id) = anonymized_id {
+                let updates = sums
+                    .iter()
+                    .map(|c| format!("{c} = {c} + excluded.{c}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let dim_list = dims.join(", ");
+                let sum_list = sums.join(", ");
+                tx.execute(
+                    &format!(
+                        "INSERT INTO {table} (ts_day, ts_hour, {dim_list}, app_id, device_id, {sum_list})
+                        SELECT ts_day, ts_hour, {dim_list}, {anonymized_id}, device_id, {sum_list}
+                        FROM {table} WHERE {where_clause} AND ts_hour IS NOT NULL
+                        ON CONFLICT(ts_hour, {dim_list}, app_id, device_id) DO UPDATE SET {updates}"
+                    ),
+                    params_refs.as_slice(),
+                )?;
+
+                // ts_hour が NULL の古い行は一意制約で衝突しないので ts_day でまとめる
+                let same_key = ["ts_day", "device_id"]
+                    .iter()
+                    .chain(dims.iter())
+                    .map(|c| format!("anon.{c} = src.{c}"))
+                    .collect::<Vec<_>>()
+                    .join(" AND ");
+                let updates = sums
+                    .iter()
+                    .map(|c| format!("{c} = anon.{c} + src.{c}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let totals = sums
+                    .iter()
+                    .map(|c| format!("SUM({c}) AS {c}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                tx.execute(
+                    &format!(
+                        "UPDATE {table} AS anon SET {updates}
+                        FROM (SELECT ts_day, device_id, {dim_list}, {totals} FROM {table}
+                            WHERE {where_clause} AND ts_hour IS NULL
+                            GROUP BY ts_day, device_id, {dim_list}) AS src
+                        WHERE anon.app_id = {anonymized_id} AND anon.ts_hour IS NULL
+                        AND {same_key}"
+                    ),
+                    params_refs.as_slice(),
+                )?;
+                tx.execute(
+                    &format!(
+                        "INSERT INTO {table} (ts_day, ts_hour, {dim_list}, app_id, device_id, {sum_list})
+                        SELECT ts_day, NULL, {dim_list}, {anonymized_id}, device_id, {totals}
+                        FROM {table} AS src WHERE {where_clause} AND ts_hour IS NULL
+                        AND NOT EXISTS (SELECT 1 FROM {table} AS anon
+                            WHERE anon.app_id = {anonymized_id} AND anon.ts_hour IS NULL
+                            AND {same_key})
+                        GROUP BY ts_day, device_id, {dim_list}"
+                    ),
+                    params_refs.as_slice(),
+                )?;
+            }
+            tx.execute(
+                &format!("DELETE FROM {table} WHERE {where_clause}"),
+                params_refs.as_slice(),
+            )?;
+        }
+
+        // 履歴が残っておらず除外設定にも使われていなければアプリ自体も消す
+        let mut remaining = 0;
+        for (table, _, _) in STAT_TABLES {
+            let count: i64 = tx.query_row(
+                &format!("SELECT COUNT(*) FROM {table} WHERE app_id = ?1"),
+                params![app_id],
+                |row| row.get(0),
+            )?;
+            remaining += count;
+        }
+        let app_removed = remaining == 0
+            && tx.execute(
+                "DELETE FROM app WHERE id = ?1
+                AND NOT EXISTS (SELECT 1 FROM excluded_app WHERE app_id = ?1)",
+                params![app_id],
+            )? > 0;
+        tx.commit()?;
+
+        Ok(PurgeReport {
+            dry_run,
+            rows,
+            presses,
+            app_removed,
+        })
+    }
+
+    /// キーフックで使う除外設定を読み込む
+    pub fn load_exclusion_set(&self) -> Result<ExclusionSet> {
+        let app_ids: Vec<i64> = self.get_excluded_apps()?.iter().map(|a| a.id).collect();
+        let patterns = self.get_excluded_patterns()?;
+        Ok(ExclusionSet::new(app_ids, &patterns))
+    }
+
+    #[allow(dead_code)]
+    pub fn get_key_stats_by_day(&self, filter: &StatFilter) -> Result<Vec<KeyStatsByDay>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query =
+            "SELECT ts_day, key_code, SUM(count) as total_count FROM key_stat".to_string();
+        let (conditions, params_vec) = filter.conditions();
+
+        if !conditions.is_empty() {
+            query.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        }
+
+        query.push_str(" GROUP BY ts_day, key_code ORDER BY ts_day, total_count DESC");
+
+        let mut stmt = conn.prepare(&query)?;
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec
+            .iter()
+            .map(|s| s as &dyn rusqlite::ToSql)
+            .collect();
+
+        let rows = stmt.query_map(params_refs.as_slice(), |row| {
+            Ok(KeyStatsByDay {
+                ts_day: row.get(0)?,
+                key_code: row.get(1)?,
+                count: row.get(2)?,
+            })
+        })?;
+
+        let mut stats = Vec::new();
+        for row in rows {
+            stats.push(row?);
+        }
+        Ok(stats)
+    }
+
+    /// 曜日×時刻ごとの押下数（時刻ごとのヒートマップ用）
+    pub fn get_hourly_key_counts(
+        &self,
+        filter: &StatFilter,
+        include_repeats: bool,
+    ) -> Result<Vec<HourlyKeyCount>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = format!(
+            "SELECT {} AS weekday, {} AS hour, SUM(count) as total_count FROM {}",
+            LOCAL_WEEKDAY_SQL,
+            LOCAL_HOUR_SQL,
+            key_count_source(include_repeats)
+        );
+        let (mut conditions, params_vec) = filter.conditions();
+        conditions.push("ts_hour IS NOT NULL".to_string());
+
+        query.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        query.push_str(" GROUP BY weekday, hour ORDER BY weekday, hour");
+
+        let mut stmt = conn.prepare(&query)?;
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec
+            .iter()
+            .map(|s| s as &dyn rusqlite::ToSql)
+            .collect();
+
+        let rows = stmt.query_map(params_refs.as_slice(), |row| {
+            Ok(HourlyKeyCount {
+                weekday: row.get(0)?,
+                hour: row.get(1)?,
+                count: row.get(2)?,
+            })
+        })?;
+
+        let mut counts = Vec::new();
+        for row in rows {
+            counts.push(row?);
+        }
+        Ok(counts)
+    }
+
+    pub fn get_total_key_count(&self, filter: &StatFilter, include_repeats: bool) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = format!(
+            "SELECT SUM(count) FROM {}",
+            key_count_source(include_repeats)
+        );
+        let (conditions, params_vec) = filter.conditions();
+
+        if !conditions.is_empty() {
+            query.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        }
+
+        let mut stmt = conn.prepare(&query)?;
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec
+            .iter()
+            .map(|s| s as &dyn rusqlite::ToSql)
+            .collect();
+
+        let total: Option<i64> = stmt.query_row(params_refs.as_slice(), |row| row.get(0))?;
+        Ok(total.unwrap_or(0))
+    }
+
+    /// 記録のある日数（1日あたりの平均を出すため）
+    pub fn get_active_day_count(&self, filter: &StatFilter) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = "SELECT COUNT(DISTINCT ts_day) FROM key_stat".to_string();
+        let (conditions, params_vec) = filter.conditions();
+
+        if !conditions.is_empty() {
+            query.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        }
+
+        let mut stmt = conn.prepare(&query)?;
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec
+            .iter()
+            .map(|s| s as &dyn rusqlite::ToSql)
+            .collect();
+
+        let days: i64 = stmt.query_row(params_refs.as_slice(), |row| row.get(0))?;
+        Ok(days)
+    }
+
+    /// 記録のある期間（アプリやデバイスで絞り込める）
+    pub fn get_date_range(&self, filter: &StatFilter) -> Result<DateRange> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = "SELECT MIN(ts_day), MAX(ts_day) FROM key_stat".to_string();
+        let (conditions, params_vec) = filter.conditions();
+
+        if !conditions.is_empty() {
+            query.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        }
+
+        let mut stmt = conn.prepare(&query)?;
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec
+            .iter()
+            .map(|s| s as &dyn rusqlite::ToSql)
+            .collect();
+        let (min, max): (Option<i64>, Option<i64>) =
+            stmt.query_row(params_refs.as_slice(), |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(DateRange {
+            min: min.unwrap_or(0),
+            max: max.unwrap_or(0),
+        })
+    }
+}
+
+/// id の行がテーブルにあるか
+fn row_exists(conn: &Connection, table: &str, id: i64) -> Result<bool> {
+    let exists = conn.query_row(
+        &format!("SELECT EXISTS(SELECT 1 FROM {table} WHERE id = ?1)"),
+        params![id],
+        |row| row.get(0),
+    )?;
+    Ok(exists)
+}
+
+/// キーボード（?1）に割り当てた行の条件
+/// デバイスが分かる行はデバイスの割り当てで、分からない行は期間の割り当てで決める
+fn keyboard_condition() -> String {
+    format!(
+        "(device_id IN (SELECT device_id FROM keyboard_device WHERE keyboard_id = ?1)
+        OR (device_id = {UNKNOWN_DEVICE_ID} AND EXISTS (
+            SELECT 1 FROM keyboard_period AS period WHERE period.keyboard_id = ?1
+            AND ts_day >= period.start_date
+            AND (period.end_date IS NULL OR ts_day <= period.end_date))))"
+    )
+}
+
+/// キー押下数を集計する対象（オートリピートを含めるかどうか）
+fn key_count_source(include_repeats: bool) -> &'static str {
+    if include_repeats {
+        "(SELECT ts_day, ts_hour, key_code, app_id, device_id, count FROM key_stat
+        UNION ALL SELECT ts_day, ts_hour, key_code, app_id, device_id, count FROM key_repeat_stat)"
+    } else {
+        "key_stat"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hash::Hash;
+    use tempfile::NamedTempFile;
+
+    /// 同じ要素の数を数えてバッファと同じ形にする
+    fn counts<K: Hash + Eq>(items: impl IntoIterator<Item = K>) -> HashMap<K, i64> {
+        let mut counts = HashMap::new();
+        for item in items {
+            *counts.entry(item).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    fn setup_test_db() -> (Database, NamedTempFile) {
+        let temp_file = NamedTempFile::new().unwrap();
+        let db = Database::new(temp_file.path()).unwrap();
+        (db, temp_file)
+    }
+
+    #[test]
+    fn test_record_key_press() {
+        let (db, _temp_file) = setup_test_db();
+        let app_id = db.get_or_create_app("Test App", "com.test.app").unwrap();
+        let key_code = "KeyA";
+        let now = chrono::Utc::now().date_naive();
+        let ts_day = now.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+        let key_stat = KeyStat {
             ts_day,
+            ts_hour: ts_day,
             key_code: key_code.to_string(),
             app_id,
+            device_id: UNKNOWN_DEVICE_ID,
         };
-        db.batch_insert_key_stats(&[key_stat]).unwrap();
+        db.batch_insert_key_stats(&counts([key_stat])).unwrap();
         let conn = db.conn.lock().unwrap();
         let count: i32 = conn
             .query_row(
@@ -326,4 +1421,588 @@ mod tests {
             .unwrap();
         assert_eq!(count, 1);
     }
+
+    #[test]
+    fn test_bigram_ranking() {
+        let (db, _temp_file) = setup_test_db();
+        let app_a = db.get_or_create_app("A", "com.test.a").unwrap();
+        let app_b = db.get_or_create_app("B", "com.test.b").unwrap();
+        let bigram = |ts_day, prev_key: &str, key_code: &str, app_id| KeyBigram {
+            ts_day,
+            ts_hour: ts_day,
+            prev_key: prev_key.to_string(),
+            key_code: key_code.to_string(),
+            app_id,
+            device_id: UNKNOWN_DEVICE_ID,
+        };
+        db.batch_insert_key_bigrams(&counts([
+            bigram(100, "KeyT", "KeyH", app_a),
+            bigram(100, "KeyT", "KeyH", app_a),
+            bigram(200, "KeyT", "KeyH", app_b),
+            bigram(200, "KeyH", "KeyE", app_b),
+        ]))
+        .unwrap();
+
+        let all = db.get_bigram_ranking(&StatFilter::default(), None).unwrap();
+        assert_eq!(all[0].prev_key, "KeyT");
+        assert_eq!(all[0].key_code, "KeyH");
+        assert_eq!(all[0].count, 3);
+        assert_eq!(all.len(), 2);
+
+        let filtered = db
+            .get_bigram_ranking(&StatFilter::new(Some(150), None, Some(app_b)), Some(1))
+            .unwrap();
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].count, 1);
+    }
+
+    #[test]
+    fn test_shortcut_ranking() {
+        let (db, _temp_file) = setup_test_db();
+        let app_id = db.get_or_create_app("Test App", "com.test.app").unwrap();
+        let shortcuts: Vec<ShortcutStat> = ["Ctrl+KeyC", "Ctrl+KeyV", "Ctrl+KeyC"]
+            .iter()
+            .map(|s| ShortcutStat {
+                ts_day: 100,
+                ts_hour: 100,
+                shortcut: s.to_string(),
+                app_id,
+                device_id: UNKNOWN_DEVICE_ID,
+            })
+            .collect();
+        db.batch_insert_shortcut_stats(&counts(shortcuts)).unwrap();
+
+        let ranking = db
+            .get_shortcut_ranking(&StatFilter::default(), None)
+            .unwrap();
+        assert_eq!(ranking.len(), 2);
+        assert_eq!(ranking[0].shortcut, "Ctrl+KeyC");
+        assert_eq!(ranking[0].count, 2);
+    }
+
+    #[test]
+    fn test_key_dwell_histogram() {
+        let (db, _temp_file) = setup_test_db();
+        let app_id = db.get_or_create_app("Test App", "com.test.app").unwrap();
+        let dwell = |key_code: &str, bucket_ms| KeyDwell {
+            ts_day: 100,
+            ts_hour: 100,
+            key_code: key_code.to_string(),
+            app_id,
+            device_id: UNKNOWN_DEVICE_ID,
+            bucket_ms,
+        };
+        db.batch_insert_key_dwells(&counts([
+            dwell("Space", 100),
+            dwell("Space", 100),
+            dwell("Space", 500),
+            dwell("KeyA", 50),
+        ]))
+        .unwrap();
+
+        let all = db
+            .get_key_dwell_histogram(&StatFilter::default(), None)
+            .unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].key_code, "KeyA");
+
+        let space = db
+            .get_key_dwell_histogram(&StatFilter::default(), Some("Space".to_string()))
+            .unwrap();
+        assert_eq!(space.len(), 2);
+        assert_eq!(space[0].bucket_ms, 100);
+        assert_eq!(space[0].count, 2);
+        assert_eq!(space[1].bucket_ms, 500);
+    }
+
+    #[test]
+    fn test_tap_hold_stats() {
+        let (db, _temp_file) = setup_test_db();
+        let app_id = db.get_or_create_app("Test App", "com.test.app").unwrap();
+        let key = TapHoldKey {
+            ts_day: 100,
+            ts_hour: 100,
+            key_code: "KeyF".to_string(),
+            app_id,
+            device_id: UNKNOWN_DEVICE_ID,
+        };
+        let mut tap_holds = HashMap::new();
+        let counts = tap_holds.entry(key).or_insert_with(TapHoldCounts::default);
+        counts.add(90, true);
+        counts.add(180, true);
+        counts.add(400, false);
+        db.batch_insert_tap_hold_counts(&tap_holds).unwrap();
+        db.batch_insert_tap_hold_counts(&tap_holds).unwrap();
+
+        let items = db.get_tap_hold_stats(&StatFilter::default()).unwrap();
+        assert_eq!(items.len(), TAPPING_TERMS_MS.len());
+        let at = |term| items.iter().find(|i| i.tapping_term_ms == term).unwrap();
+        assert_eq!(at(150).presses, 6);
+        assert_eq!(at(150).misfires, 2);
+        assert_eq!(at(200).misfires, 0);
+    }
+
+    #[test]
+    fn test_key_repeats() {
+        let (db, _temp_file) = setup_test_db();
+        let app_id = db.get_or_create_app("Test App", "com.test.app").unwrap();
+        let stat = |key_code: &str| KeyStat {
+            ts_day: 100,
+            ts_hour: 100,
+            key_code: key_code.to_string(),
+            app_id,
+            device_id: UNKNOWN_DEVICE_ID,
+        };
+        db.batch_insert_key_stats(&counts([stat("Backspace"), stat("KeyA"), stat("KeyA")]))
+            .unwrap();
+        db.batch_insert_key_repeats(&counts([
+            stat("Backspace"),
+            stat("Backspace"),
+            stat("Backspace"),
+        ]))
+        .unwrap();
+
+        let ranking = db
+            .get_key_ranking(&StatFilter::default(), None, false)
+            .unwrap();
+        assert_eq!(ranking[0].key_code, "KeyA");
+        assert_eq!(
+            db.get_total_key_count(&StatFilter::default(), false)
+                .unwrap(),
+            3
+        );
+
+        let ranking = db
+            .get_key_ranking(&StatFilter::new(None, None, Some(app_id)), None, true)
+            .unwrap();
+        assert_eq!(ranking[0].key_code, "Backspace");
+        assert_eq!(ranking[0].count, 4);
+        assert_eq!(
+            db.get_total_key_count(&StatFilter::default(), true)
+                .unwrap(),
+            6
+        );
+    }
+
+    #[test]
+    fn test_hour_and_weekday_filters() {
+        let (db, _temp_file) = setup_test_db();
+        let app_id = db.get_or_create_app("Test App", "com.test.app").unwrap();
+        // 2024-01-01（月曜）のローカル時刻
+        let day = chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let local = |hour| {
+            day.and_hms_opt(hour, 0, 0)
+                .unwrap()
+                .and_local_timezone(chrono::Local)
+                .unwrap()
+                .timestamp()
+        };
+        let stat = |hour| KeyStat {
+            ts_day: local(0),
+            ts_hour: local(hour),
+            key_code: "KeyA".to_string(),
+            app_id,
+            device_id: UNKNOWN_DEVICE_ID,
+        };
+        db.batch_insert_key_stats(&counts([stat(9), stat(9), stat(22)]))
+            .unwrap();
+
+        let filter = StatFilter {
+            hours: Some(vec![9]),
+            ..Default::default()
+        };
+        assert_eq!(db.get_total_key_count(&filter, false).unwrap(), 2);
+        let filter = StatFilter {
+            weekdays: Some(vec![0, 6]),
+            ..Default::default()
+        };
+        assert_eq!(db.get_total_key_count(&filter, false).unwrap(), 0);
+
+        let hourly = db
+            .get_hourly_key_counts(&StatFilter::default(), false)
+            .unwrap();
+        assert_eq!(hourly.len(), 2);
+        assert_eq!(hourly[0].weekday, 1);
+        assert_eq!(hourly[0].hour, 9);
+        assert_eq!(hourly[0].count, 2);
+    }
+
+    #[test]
+    fn test_devices() {
+        let (db, _temp_file) = setup_test_db();
+        let app_id = db.get_or_create_app("Test App", "com.test.app").unwrap();
+        let keyboard = |name: &str| InputDevice {
+            name: name.to_string(),
+            vendor_id: Some(0x4653),
+            product_id: Some(0x0001),
+        };
+        let laptop = db.get_or_create_device(&keyboard("Laptop")).unwrap();
+        let sixty = db.get_or_create_device(&keyboard("Sixty")).unwrap();
+        assert_eq!(
+            db.get_or_create_device(&keyboard("Laptop")).unwrap(),
+            laptop
+        );
+        assert_eq!(db.get_devices().unwrap().len(), 2);
+
+        let stat = |key_code: &str, device_id| KeyStat {
+            ts_day: 100,
+            ts_hour: 100,
+            key_code: key_code.to_string(),
+            app_id,
+            device_id,
+        };
+        db.batch_insert_key_stats(&counts([
+            stat("KeyA", laptop),
+            stat("KeyA", sixty),
+            stat("KeyA", sixty),
+            stat("KeyB", sixty),
+        ]))
+        .unwrap();
+
+        let filter = StatFilter {
+            device_id: Some(sixty),
+            ..Default::default()
+        };
+        let ranking = db.get_key_ranking(&filter, None, false).unwrap();
+        assert_eq!(ranking[0].key_code, "KeyA");
+        assert_eq!(ranking[0].count, 2);
+        assert_eq!(db.get_total_key_count(&filter, false).unwrap(), 3);
+        assert_eq!(
+            db.get_total_key_count(&StatFilter::default(), false)
+                .unwrap(),
+            4
+        );
+
+        db.batch_insert_key_stats(&counts([KeyStat {
+            ts_day: 200,
+            ..stat("KeyC", laptop)
+        }]))
+        .unwrap();
+        let range = db.get_date_range(&filter).unwrap();
+        assert_eq!((range.min, range.max), (100, 100));
+        let range = db.get_date_range(&StatFilter::default()).unwrap();
+        assert_eq!((range.min, range.max), (100, 200));
+    }
+
+    #[test]
+    fn test_keyboard_inventory() {
+        let (db, _temp_file) = setup_test_db();
+        let app_id = db.get_or_create_app("Test App", "com.test.app").unwrap();
+        let device = |name: &str| InputDevice {
+            name: name.to_string(),
+            vendor_id: None,
+            product_id: None,
+        };
+        let sixty = db.get_or_create_device(&device("Sixty")).unwrap();
+        let laptop = db.get_or_create_device(&device("Laptop")).unwrap();
+        let spec = KeyboardSpec {
+            name: "60%".to_string(),
+            layout_id: Some("ansi-60".to_string()),
+            switch_type: Some("Gateron Yellow".to_string()),
+            rated_lifetime: 50_000_000,
+        };
+        let keyboard_id = db.add_keyboard(&spec).unwrap();
+        db.set_device_keyboard(sixty, Some(keyboard_id)).unwrap();
+        // rdev で記録した期間を手動で割り当てる
+        db.add_keyboard_period(keyboard_id, 200, Some(300)).unwrap();
+
+        let stat = |ts_day, key_code: &str, device_id| KeyStat {
+            ts_day,
+            ts_hour: ts_day,
+            key_code: key_code.to_string(),
+            app_id,
+            device_id,
+        };
+        db.batch_insert_key_stats(&counts([
+            stat(100, "Space", sixty),
+            stat(100, "Space", laptop),
+            stat(100, "KeyE", UNKNOWN_DEVICE_ID),
+            stat(250, "KeyE", UNKNOWN_DEVICE_ID),
+            stat(400, "KeyE", UNKNOWN_DEVICE_ID),
+        ]))
+        .unwrap();
+
+        let ranking = db.get_keyboard_key_ranking(keyboard_id).unwrap();
+        assert_eq!(ranking.len(), 2);
+        assert!(ranking.iter().all(|item| item.count == 1));
+        assert_eq!(db.get_keyboard_active_day_count(keyboard_id).unwrap(), 2);
+
+        let keyboards = db.get_keyboards().unwrap();
+        assert_eq!(keyboards[0].device_ids, vec![sixty]);
+        assert_eq!(
+            keyboards[0].spec.switch_type.as_deref(),
+            Some("Gateron Yellow")
+        );
+
+        // デバイスの割り当てを外すと期間の分だけが残る
+        db.set_device_keyboard(sixty, None).unwrap();
+        assert_eq!(db.get_keyboard_key_ranking(keyboard_id).unwrap().len(), 1);
+
+        db.remove_keyboard(keyboard_id).unwrap();
+        assert!(db.get_keyboards().unwrap().is_empty());
+        assert!(db.get_keyboard_periods(keyboard_id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_keyboard_validation() {
+        let (db, _temp_file) = setup_test_db();
+        let device_id = db
+            .get_or_create_device(&InputDevice {
+                name: "Sixty".to_string(),
+                vendor_id: None,
+                product_id: None,
+            })
+            .unwrap();
+        let mut spec = KeyboardSpec {
+            name: "60%".to_string(),
+            layout_id: None,
+            switch_type: None,
+            rated_lifetime: 0,
+        };
+        assert!(db.add_keyboard(&spec).is_err());
+        spec.rated_lifetime = 50_000_000;
+        let keyboard_id = db.add_keyboard(&spec).unwrap();
+        spec.rated_lifetime = -1;
+        assert!(db.update_keyboard(keyboard_id, &spec).is_err());
+        spec.rated_lifetime = 50_000_000;
+        assert!(db.update_keyboard(keyboard_id + 1, &spec).is_err());
+
+        assert!(db.add_keyboard_period(keyboard_id, 300, Some(200)).is_err());
+        assert!(db.add_keyboard_period(keyboard_id + 1, 200, None).is_err());
+        db.add_keyboard_period(keyboard_id, 200, Some(200)).unwrap();
+
+        assert!(db
+            .set_device_keyboard(device_id, Some(keyboard_id + 1))
+            .is_err());
+        assert!(db
+            .set_device_keyboard(device_id + 1, Some(keyboard_id))
+            .is_err());
+        db.set_device_keyboard(device_id, Some(keyboard_id))
+            .unwrap();
+        assert_eq!(db.get_keyboards().unwrap()[0].device_ids, vec![device_id]);
+    }
+
+    #[test]
+    fn test_experiments() {
+        let (db, _temp_file) = setup_test_db();
+        let mut spec = ExperimentSpec {
+            name: "Split keyboard".to_string(),
+            start_date: 300,
+            end_date: None,
+            baseline_start_date: 100,
+            baseline_end_date: 200,
+        };
+        let older = db
+            .add_experiment(&ExperimentSpec {
+                name: "Colemak".to_string(),
+                start_date: 100,
+                end_date: Some(200),
+                baseline_start_date: 0,
+                baseline_end_date: 99,
+            })
+            .unwrap();
+        let experiment_id = db.add_experiment(&spec).unwrap();
+
+        let experiments = db.get_experiments().unwrap();
+        let ids: Vec<i64> = experiments.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![experiment_id, older]);
+        assert_eq!(experiments[0].spec.end_date, None);
+
+        spec.end_date = Some(400);
+        db.update_experiment(experiment_id, &spec).unwrap();
+        assert_eq!(db.get_experiments().unwrap()[0].spec.end_date, Some(400));
+
+        db.remove_experiment(older).unwrap();
+        assert_eq!(db.get_experiments().unwrap().len(), 1);
+
+        // 存在しない実験や重なる期間は変更しない
+        assert!(db.update_experiment(older, &spec).is_err());
+        assert!(db.remove_experiment(older).is_err());
+        spec.baseline_end_date = 350;
+        assert!(db.add_experiment(&spec).is_err());
+        assert!(db.update_experiment(experiment_id, &spec).is_err());
+        assert_eq!(db.get_experiments().unwrap()[0].spec.baseline_end_date, 200);
+    }
+
+    #[test]
+    fn test_migrate_daily_key_stat() {
+        let temp_file = NamedTempFile::new().unwrap();
+        {
+            let conn = Connection::open(temp_file.path()).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE app (id INTEGER PRIMARY KEY, name TEXT NOT NULL, bundle_id TEXT NOT NULL);
+                CREATE TABLE key_stat (
+                    ts_day INTEGER NOT NULL,
+                    key_code TEXT NOT NULL,
+                    app_id INTEGER NOT NULL,
+                    count INTEGER NOT NULL,
+                    PRIMARY KEY (ts_day, key_code, app_id)
+                );
+                INSERT INTO app (id, name, bundle_id) VALUES (1, 'Test App', 'com.test.app');
+                INSERT INTO key_stat VALUES (100, 'KeyA', 1, 5);",
+            )
+            .unwrap();
+        }
+
+        let db = Database::new(temp_file.path()).unwrap();
+        db.batch_insert_key_stats(&counts([KeyStat {
+            ts_day: 100,
+            ts_hour: 100,
+            key_code: "KeyA".to_string(),
+            app_id: 1,
+            device_id: UNKNOWN_DEVICE_ID,
+        }]))
+        .unwrap();
+
+        // 日単位の行も読めるが、時刻での絞り込みには含まれない
+        let ranking = db
+            .get_key_ranking(&StatFilter::default(), None, false)
+            .unwrap();
+        assert_eq!(ranking[0].count, 6);
+        let filter = StatFilter {
+            hours: Some((0..24).collect()),
+            ..Default::default()
+        };
+        assert_eq!(db.get_total_key_count(&filter, false).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_exclusions() {
+        let (db, _temp_file) = setup_test_db();
+        let app_id = db
+            .get_or_create_app("1Password", "/Applications/1Password.app")
+            .unwrap();
+        let other_id = db
+            .get_or_create_app("Safari", "/Applications/Safari.app")
+            .unwrap();
+
+        db.add_excluded_app(app_id).unwrap();
+        db.add_excluded_app(app_id).unwrap();
+        assert_eq!(db.get_excluded_apps().unwrap().len(), 1);
+        let id = db
+            .add_excluded_pattern(PatternKind::Glob, "/Applications/Saf*")
+            .unwrap();
+        assert_eq!(db.get_excluded_patterns().unwrap()[0].id, id);
+
+        let set = db.load_exclusion_set().unwrap();
+        assert!(set.is_excluded(app_id, "/Applications/1Password.app"));
+        assert!(set.is_excluded(other_id, "/Applications/Safari.app"));
+
+        db.remove_excluded_app(app_id).unwrap();
+        db.remove_excluded_pattern(id).unwrap();
+        let set = db.load_exclusion_set().unwrap();
+        assert!(!set.is_excluded(app_id, "/Applications/1Password.app"));
+        assert!(!set.is_excluded(other_id, "/Applications/Safari.app"));
+    }
+
+    #[test]
+    fn test_purge_app_data() {
+        let (db, _temp_file) = setup_test_db();
+        let secret = db.get_or_create_app("Secret", "com.test.secret").unwrap();
+        let other = db.get_or_create_app("Other", "com.test.other").unwrap();
+        let stat = |ts_day, app_id| KeyStat {
+            ts_day,
+            ts_hour: ts_day,
+            key_code: "KeyA".to_string(),
+            app_id,
+            device_id: UNKNOWN_DEVICE_ID,
+        };
+        db.batch_insert_key_stats(&counts([
+            stat(100, secret),
+            stat(100, secret),
+            stat(200, secret),
+            stat(100, other),
+        ]))
+        .unwrap();
+        db.batch_insert_key_repeats(&counts([stat(200, secret)]))
+            .unwrap();
+
+        let report = db
+            .purge_app_data(secret, None, None, PurgeMode::Delete, true)
+            .unwrap();
+        assert_eq!(report.rows, 3);
+        assert_eq!(report.presses, 3);
+        assert_eq!(
+            db.get_total_key_count(&StatFilter::default(), false)
+                .unwrap(),
+            4
+        );
+
+        // 期間指定の匿名化では合計が変わらない
+        let report = db
+            .purge_app_data(secret, Some(150), None, PurgeMode::Anonymize, false)
+            .unwrap();
+        assert_eq!(report.rows, 2);
+        assert!(!report.app_removed);
+        assert_eq!(
+            db.get_total_key_count(&StatFilter::default(), true)
+                .unwrap(),
+            5
+        );
+        let filter = StatFilter::new(None, None, Some(secret));
+        assert_eq!(db.get_total_key_count(&filter, true).unwrap(), 2);
+
+        let report = db
+            .purge_app_data(secret, None, None, PurgeMode::Delete, false)
+            .unwrap();
+        assert_eq!(report.presses, 2);
+        assert!(report.app_removed);
+        assert!(db.get_apps().unwrap().iter().all(|a| a.id != secret));
+        assert_eq!(
+            db.get_total_key_count(&StatFilter::default(), true)
+                .unwrap(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_anonymize_legacy_and_anonymized_rows() {
+        let (db, _temp_file) = setup_test_db();
+        let first = db.get_or_create_app("First", "com.test.first").unwrap();
+        let second = db.get_or_create_app("Second", "com.test.second").unwrap();
+        {
+            // 時間単位の集計を導入する前の行（ts_hour が NULL）
+            let conn = db.conn.lock().unwrap();
+            conn.execute(
+                "INSERT INTO key_stat (ts_day, ts_hour, key_code, app_id, device_id, count)
+                VALUES (100, NULL, 'KeyA', ?1, 0, 3), (100, NULL, 'KeyA', ?2, 0, 4)",
+                params![first, second],
+            )
+            .unwrap();
+        }
+        db.purge_app_data(first, None, None, PurgeMode::Anonymize, false)
+            .unwrap();
+        db.purge_app_data(second, None, None, PurgeMode::Anonymize, false)
+            .unwrap();
+
+        let anonymized = db
+            .get_apps()
+            .unwrap()
+            .into_iter()
+            .find(|a| a.bundle_id == ANONYMIZED_BUNDLE_ID)
+            .unwrap();
+        let conn = db.conn.lock().unwrap();
+        let rows: Vec<(i64, i64)> = conn
+            .prepare("SELECT app_id, count FROM key_stat")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(rows, vec![(anonymized.id, 7)]);
+        drop(conn);
+
+        // 匿名アプリ自体は匿名化できない（付け替えた履歴が消えてしまう）
+        assert!(db
+            .purge_app_data(anonymized.id, None, None, PurgeMode::Anonymize, true)
+            .is_err());
+        assert!(db
+            .purge_app_data(anonymized.id, None, None, PurgeMode::Anonymize, false)
+            .is_err());
+        assert_eq!(
+            db.get_total_key_count(&StatFilter::default(), false)
+                .unwrap(),
+            7
+        );
+    }
 }
@@ -9,3 +9,13 @@ pub fn show_error(app: &AppHandle, message: &str, title: Option<&str>) {
     }
     dialog.blocking_show();
 }
+
+/// エラーダイアログを表示（non-blocking）
+/// メインスレッドやロック中から呼んでも待たない
+pub fn notify_error(app: &AppHandle, message: &str, title: Option<&str>) {
+    let mut dialog = app.dialog().message(message).kind(MessageDialogKind::Error);
+    if let Some(t) = title {
+        dialog = dialog.title(t);
+    }
+    dialog.show(|_| {});
+}
@@ -0,0 +1,130 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// バンドルパス（Windowsでは実行ファイルのパス）に対するパターンの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PatternKind {
+    Glob,
+    Regex,
+}
+
+impl PatternKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PatternKind::Glob => "glob",
+            PatternKind::Regex => "regex",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Result<Self> {
+        match kind {
+            "glob" => Ok(PatternKind::Glob),
+            "regex" => Ok(PatternKind::Regex),
+            _ => Err(anyhow!("Unknown pattern kind: {kind}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExclusionPattern {
+    pub id: i64,
+    pub kind: PatternKind,
+    pub pattern: String,
+}
+
+enum Matcher {
+    Glob(glob::Pattern),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn matches(&self, bundle_id: &str) -> bool {
+        match self {
+            Matcher::Glob(pattern) => pattern.matches(bundle_id),
+            Matcher::Regex(regex) => regex.is_match(bundle_id),
+        }
+    }
+}
+
+fn compile(kind: PatternKind, pattern: &str) -> Result<Matcher> {
+    match kind {
+        PatternKind::Glob => Ok(Matcher::Glob(
+            glob::Pattern::new(pattern).map_err(|e| anyhow!("Invalid glob pattern: {e}"))?,
+        )),
+        PatternKind::Regex => Ok(Matcher::Regex(
+            Regex::new(pattern).map_err(|e| anyhow!("Invalid regex pattern: {e}"))?,
+        )),
+    }
+}
+
+/// パターンが正しく解釈できるか確認する
+pub fn validate(kind: PatternKind, pattern: &str) -> Result<()> {
+    compile(kind, pattern).map(|_| ())
+}
+
+/// キーフックで参照する除外設定（excluded_app / excluded_pattern の内容を保持）
+#[derive(Default)]
+pub struct ExclusionSet {
+    app_ids: HashSet<i64>,
+    matchers: Vec<Matcher>,
+}
+
+impl ExclusionSet {
+    pub fn new(app_ids: impl IntoIterator<Item = i64>, patterns: &[ExclusionPattern]) -> Self {
+        let mut matchers = Vec::new();
+        for pattern in patterns {
+            match compile(pattern.kind, &pattern.pattern) {
+                Ok(matcher) => matchers.push(matcher),
+                Err(e) => eprintln!(
+                    "[KeyFit] Skipping exclusion pattern {:?}: {}",
+                    pattern.pattern, e
+                ),
+            }
+        }
+        Self {
+            app_ids: app_ids.into_iter().collect(),
+            matchers,
+        }
+    }
+
+    pub fn is_excluded(&self, app_id: i64, bundle_id: &str) -> bool {
+        self.app_ids.contains(&app_id) || self.matchers.iter().any(|m| m.matches(bundle_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclusion_set() {
+        let pattern = |kind, pattern: &str| ExclusionPattern {
+            id: 0,
+            kind,
+            pattern: pattern.to_string(),
+        };
+        let set = ExclusionSet::new(
+            [3],
+            &[
+                pattern(PatternKind::Glob, "/Applications/1Password*.app"),
+                pattern(PatternKind::Regex, r"(?i)\\keepass[^\\]*\.exe$"),
+                pattern(PatternKind::Regex, "("),
+            ],
+        );
+        assert!(set.is_excluded(3, "/Applications/Safari.app"));
+        assert!(set.is_excluded(1, "/Applications/1Password 7.app"));
+        assert!(set.is_excluded(1, r"C:\Program Files\KeePassXC\KeePassXC.exe"));
+        assert!(!set.is_excluded(1, "/Applications/Safari.app"));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(PatternKind::Glob, "/Applications/*.app").is_ok());
+        assert!(validate(PatternKind::Glob, "[").is_err());
+        assert!(validate(PatternKind::Regex, "(").is_err());
+        assert_eq!(PatternKind::parse("regex").unwrap(), PatternKind::Regex);
+    }
+}
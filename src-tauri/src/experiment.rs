@@ -0,0 +1,308 @@
+use crate::db::KeyRankingItem;
+use crate::keyboard::is_modifier;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// 期間ごとに表示する上位キーの数
+const TOP_KEY_COUNT: usize = 10;
+
+/// 登録時にフロントエンドから渡す実験の情報（日付は ts_day）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExperimentSpec {
+    /// 例: "Split keyboard"
+    pub name: String,
+    pub start_date: i64,
+    /// None なら現在まで
+    pub end_date: Option<i64>,
+    /// 比較の基準にする期間
+    pub baseline_start_date: i64,
+    pub baseline_end_date: i64,
+}
+
+impl ExperimentSpec {
+    /// 期間は ts_day の両端を含むので、基準期間の最終日と実験の開始日が同じ日でも重なる
+    pub fn validate(&self) -> Result<()> {
+        if self.end_date.is_some_and(|end| end < self.start_date) {
+            return Err(anyhow!("Experiment ends before it starts"));
+        }
+        if self.baseline_end_date < self.baseline_start_date {
+            return Err(anyhow!("Baseline ends before it starts"));
+        }
+        let ends_before_baseline = self
+            .end_date
+            .is_some_and(|end| end < self.baseline_start_date);
+        if self.start_date <= self.baseline_end_date && !ends_before_baseline {
+            return Err(anyhow!("Baseline overlaps the experiment period"));
+        }
+        Ok(())
+    }
+}
+
+/// 分割キーボードや新しいレイアウトを試した期間
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Experiment {
+    pub id: i64,
+    #[serde(flatten)]
+    pub spec: ExperimentSpec,
+}
+
+/// 1つの期間の集計結果（get_key_ranking / get_total_key_count の結果）
+pub struct PeriodStats {
+    pub ranking: Vec<KeyRankingItem>,
+    pub total: i64,
+    /// 押下を記録した日数
+    pub days: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeriodMetrics {
+    pub total_presses: i64,
+    pub days: i64,
+    pub presses_per_day: f64,
+    /// 全押下に対する Backspace の割合（%）
+    pub backspace_percentage: f64,
+    /// 全押下に対する修飾キーの割合（%）
+    pub modifier_percentage: f64,
+    pub top_keys: Vec<KeyRankingItem>,
+}
+
+/// キーごとの1日あたりの押下数の変化
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyDelta {
+    pub key_code: String,
+    pub baseline_per_day: f64,
+    pub experiment_per_day: f64,
+    pub delta_per_day: f64,
+    /// 基準期間に押されていないキーは None
+    pub change_percentage: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExperimentReport {
+    pub experiment_id: i64,
+    pub baseline: PeriodMetrics,
+    pub experiment: PeriodMetrics,
+    /// 1日あたりの押下数の変化率（%）
+    pub presses_per_day_change: Option<f64>,
+    /// 割合の差（ポイント）
+    pub backspace_change: f64,
+    pub modifier_change: f64,
+    /// 変化の大きい順
+    pub keys: Vec<KeyDelta>,
+}
+
+fn per_day(count: i64, days: i64) -> f64 {
+    if days > 0 {
+        count as f64 / days as f64
+    } else {
+        0.0
+    }
+}
+
+fn percentage(count: i64, total: i64) -> f64 {
+    if total > 0 {
+        count as f64 / total as f64 * 100.0
+    } else {
+        0.0
+    }
+}
+
+fn change_percentage(baseline: f64, experiment: f64) -> Option<f64> {
+    (baseline > 0.0).then(|| (experiment - baseline) / baseline * 100.0)
+}
+
+fn period_metrics(stats: &PeriodStats) -> PeriodMetrics {
+    let count_of = |matches: fn(&str) -> bool| -> i64 {
+        stats
+            .ranking
+            .iter()
+            .filter(|item| matches(&item.key_code))
+            .map(|item| item.count)
+            .sum()
+    };
+    PeriodMetrics {
+        total_presses: stats.total,
+        days: stats.days,
+        presses_per_day: per_day(stats.total, stats.days),
+        backspace_percentage: percentage(count_of(|key| key == "Backspace"), stats.total),
+        modifier_percentage: percentage(count_of(is_modifier), stats.total),
+        top_keys: stats.ranking.iter().take(TOP_KEY_COUNT).cloned().collect(),
+    }
+}
+
+/// 基準期間と実験期間を比べる
+/// 期間の長さが違っても比べられるよう、キーごとの差は1日あたりの押下数で出す
+pub fn build_report(
+    experiment_id: i64,
+    baseline: &PeriodStats,
+    experiment: &PeriodStats,
+) -> ExperimentReport {
+    let mut counts: HashMap<&str, (i64, i64)> = HashMap::new();
+    for item in &baseline.ranking {
+        counts.entry(&item.key_code).or_default().0 += item.count;
+    }
+    for item in &experiment.ranking {
+        counts.entry(&item.key_code).or_default().1 += item.count;
+    }
+
+    let mut keys: Vec<KeyDelta> = counts
+        .into_iter()
+        .map(|(key_code, (before, after))| {
+            let baseline_per_day = per_day(before, baseline.days);
+            let experiment_per_day = per_day(after, experiment.days);
+            KeyDelta {
+                key_code: key_code.to_string(),
+                baseline_per_day,
+                experiment_per_day,
+                delta_per_day: experiment_per_day - baseline_per_day,
+                change_percentage: change_percentage(baseline_per_day, experiment_per_day),
+            }
+        })
+        .collect();
+    keys.sort_by(|a, b| {
+        b.delta_per_day
+            .abs()
+            .total_cmp(&a.delta_per_day.abs())
+            .then(a.key_code.cmp(&b.key_code))
+    });
+
+    let baseline = period_metrics(baseline);
+    let experiment = period_metrics(experiment);
+    ExperimentReport {
+        experiment_id,
+        presses_per_day_change: change_percentage(
+            baseline.presses_per_day,
+            experiment.presses_per_day,
+        ),
+        backspace_change: experiment.backspace_percentage - baseline.backspace_percentage,
+        modifier_change: experiment.modifier_percentage - baseline.modifier_percentage,
+        baseline,
+        experiment,
+        keys,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(ranking: &[(&str, i64)], days: i64) -> PeriodStats {
+        let ranking: Vec<KeyRankingItem> = ranking
+            .iter()
+            .map(|(key_code, count)| KeyRankingItem {
+                key_code: key_code.to_string(),
+                count: *count,
+            })
+            .collect();
+        PeriodStats {
+            total: ranking.iter().map(|item| item.count).sum(),
+            ranking,
+            days,
+        }
+    }
+
+    #[test]
+    fn test_build_report() {
+        let baseline = stats(
+            &[
+                ("KeyE", 300),
+                ("Backspace", 100),
+                ("ShiftLeft", 50),
+                ("MetaLeft", 50),
+                ("KeyQ", 0),
+            ],
+            10,
+        );
+        // 分割キーボードに変えて2週間
+        let experiment = stats(
+            &[
+                ("KeyE", 840),
+                ("Backspace", 140),
+                ("ShiftLeft", 140),
+                ("Escape", 280),
+            ],
+            14,
+        );
+
+        let report = build_report(1, &baseline, &experiment);
+        assert_eq!(report.baseline.total_presses, 500);
+        assert_eq!(report.baseline.presses_per_day, 50.0);
+        assert_eq!(report.experiment.presses_per_day, 100.0);
+        assert_eq!(report.presses_per_day_change, Some(100.0));
+        assert_eq!(report.baseline.backspace_percentage, 20.0);
+        assert_eq!(report.backspace_change, -10.0);
+        assert_eq!(report.modifier_change, -10.0);
+        assert_eq!(report.baseline.top_keys[0].key_code, "KeyE");
+
+        let keys: Vec<&str> = report.keys.iter().map(|k| k.key_code.as_str()).collect();
+        assert_eq!(
+            keys,
+            [
+                "KeyE",
+                "Escape",
+                "MetaLeft",
+                "ShiftLeft",
+                "Backspace",
+                "KeyQ"
+            ]
+        );
+        let key_e = &report.keys[0];
+        assert_eq!(key_e.baseline_per_day, 30.0);
+        assert_eq!(key_e.experiment_per_day, 60.0);
+        assert_eq!(key_e.change_percentage, Some(100.0));
+        // 基準期間に押されていないキーは変化率を出せない
+        assert_eq!(report.keys[1].change_percentage, None);
+        assert_eq!(report.keys[2].change_percentage, Some(-100.0));
+    }
+
+    #[test]
+    fn test_validate() {
+        let spec = ExperimentSpec {
+            name: "Split keyboard".to_string(),
+            start_date: 300,
+            end_date: Some(400),
+            baseline_start_date: 100,
+            baseline_end_date: 200,
+        };
+        assert!(spec.validate().is_ok());
+        let invalid = [
+            ExperimentSpec {
+                end_date: Some(299),
+                ..spec.clone()
+            },
+            ExperimentSpec {
+                baseline_end_date: 99,
+                ..spec.clone()
+            },
+            ExperimentSpec {
+                baseline_end_date: 300,
+                ..spec.clone()
+            },
+            // 実験期間のあとを基準にしてもよいが、終わっていない実験とは重なる
+            ExperimentSpec {
+                end_date: None,
+                baseline_start_date: 500,
+                baseline_end_date: 600,
+                ..spec.clone()
+            },
+        ];
+        for spec in invalid {
+            assert!(spec.validate().is_err(), "{spec:?}");
+        }
+        let after = ExperimentSpec {
+            baseline_start_date: 500,
+            baseline_end_date: 600,
+            ..spec
+        };
+        assert!(after.validate().is_ok());
+    }
+
+    #[test]
+    fn test_build_report_without_presses() {
+        let report = build_report(1, &stats(&[], 0), &stats(&[], 0));
+        assert_eq!(report.presses_per_day_change, None);
+        assert_eq!(report.backspace_change, 0.0);
+        assert!(report.keys.is_empty());
+    }
+}
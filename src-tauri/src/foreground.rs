@@ -0,0 +1,115 @@
+use crate::appinfo::ActiveAppInfo;
+use crate::db::Database;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// 前面アプリを確認する間隔（切り替え直後はこの間だけ前のアプリに記録されうる）
+pub const FOREGROUND_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 記録先となる前面アプリ
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActiveApp {
+    pub app_id: i64,
+    pub bundle_id: String,
+}
+
+/// bundle_id から app_id への対応を覚えておき、DBへの問い合わせは初回だけにする
+#[derive(Default)]
+pub struct AppIdCache {
+    ids: HashMap<String, i64>,
+}
+
+impl AppIdCache {
+    pub fn resolve(&mut self, db: &Database, name: &str, bundle_id: &str) -> Result<i64> {
+        if let Some(app_id) = self.ids.get(bundle_id) {
+            return Ok(*app_id);
+        }
+        let app_id = db.get_or_create_app(name, bundle_id)?;
+        self.ids.insert(bundle_id.to_string(), app_id);
+        Ok(app_id)
+    }
+}
+
+/// 前面アプリの切り替えを検出し、切り替わったときだけ app_id を引き直す
+#[derive(Default)]
+pub struct ForegroundTracker {
+    cache: AppIdCache,
+    last: Option<(String, String)>,
+    current: Option<ActiveApp>,
+}
+
+impl ForegroundTracker {
+    /// 取得した前面アプリを反映する。アプリ（名前とbundle_id）が変わったら true
+    /// ウィンドウタイトルだけの変化は切り替えとみなさない
+    pub fn update(&mut self, db: &Database, info: Option<ActiveAppInfo>) -> Result<bool> {
+        let key = info
+            .as_ref()
+            .map(|info| (info.name.clone(), info.bundle_id.clone()));
+        if key == self.last {
+            return Ok(false);
+        }
+        // 失敗しても同じアプリで問い合わせを繰り返さない
+        self.last = key;
+        self.current = None;
+        if let Some(info) = info {
+            let app_id = self.cache.resolve(db, &info.name, &info.bundle_id)?;
+            self.current = Some(ActiveApp {
+                app_id,
+                bundle_id: info.bundle_id,
+            });
+        }
+        Ok(true)
+    }
+
+    pub fn current(&self) -> Option<ActiveApp> {
+        self.current.clone()
+    }
+
+    /// アプリの行が削除されたときなど、覚えている app_id を捨てる
+    pub fn invalidate(&mut self) {
+        self.cache = AppIdCache::default();
+        self.last = None;
+        self.current = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_foreground_tracker() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let db = Database::new(temp_file.path()).unwrap();
+        let info = |name: &str, bundle_id: &str| Some(ActiveAppInfo::new(name, bundle_id));
+        let mut tracker = ForegroundTracker::default();
+
+        assert!(tracker
+            .update(&db, info("Editor", "com.test.editor"))
+            .unwrap());
+        let editor = tracker.current().unwrap();
+        assert!(!tracker
+            .update(&db, info("Editor", "com.test.editor"))
+            .unwrap());
+        let titled = ActiveAppInfo {
+            window_title: Some("main.rs".to_string()),
+            ..ActiveAppInfo::new("Editor", "com.test.editor")
+        };
+        assert!(!tracker.update(&db, Some(titled)).unwrap());
+
+        tracker
+            .update(&db, info("Browser", "com.test.browser"))
+            .unwrap();
+        assert_ne!(tracker.current().unwrap().app_id, editor.app_id);
+        tracker
+            .update(&db, info("Editor", "com.test.editor"))
+            .unwrap();
+        assert_eq!(tracker.current(), Some(editor));
+        assert_eq!(db.get_apps().unwrap().len(), 2);
+
+        assert!(tracker.update(&db, None).unwrap());
+        assert_eq!(tracker.current(), None);
+    }
+}
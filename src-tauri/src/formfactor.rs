@@ -0,0 +1,214 @@
+use crate::db::KeyRankingItem;
+use crate::layout::LayoutDefinition;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// ブロッキングキーとして返す最大件数
+const BLOCKING_KEY_LIMIT: usize = 20;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FormFactorScore {
+    pub layout_id: String,
+    pub name: String,
+    pub form_factor: Option<String>,
+    pub key_count: usize,
+    pub total_presses: i64,
+    pub base_layer_presses: i64,
+    pub base_layer_percentage: f64,
+    pub layer_presses_per_day: f64,
+    pub blocking_keys: Vec<KeyRankingItem>,
+}
+
+/// 1つのレイアウトについてランキングを採点する
+pub fn score_layout(
+    layout: &LayoutDefinition,
+    ranking: &[KeyRankingItem],
+    days: i64,
+) -> FormFactorScore {
+    let keys = layout.key_set();
+    let mut total_presses = 0;
+    let mut base_layer_presses = 0;
+    let mut blocking_keys = Vec::new();
+
+    for item in ranking {
+        total_presses += item.count;
+        if keys.contains(item.key_code.as_str()) {
+            base_layer_presses += item.count;
+        } else {
+            blocking_keys.push(KeyRankingItem {
+                key_code: item.key_code.clone(),
+                count: item.count,
+            });
+        }
+    }
+
+    // ランキングの順序に依存せず押下数の多い順に並べる
+    blocking_keys.sort_by(|a, b| b.count.cmp(&a.count).then(a.key_code.cmp(&b.key_code)));
+    blocking_keys.truncate(BLOCKING_KEY_LIMIT);
+
+    let base_layer_percentage = if total_presses > 0 {
+        base_layer_presses as f64 / total_presses as f64 * 100.0
+    } else {
+        100.0
+    };
+    let layer_presses = total_presses - base_layer_presses;
+
+    FormFactorScore {
+        layout_id: layout.id.clone(),
+        name: layout.name.clone(),
+        form_factor: layout.form_factor.clone(),
+        key_count: keys.len(),
+        total_presses,
+        base_layer_presses,
+        base_layer_percentage,
+        layer_presses_per_day: layer_presses as f64 / days.max(1) as f64,
+        blocking_keys,
+    }
+}
+
+/// 複数のレイアウトをまとめて採点する
+pub fn score_layouts<'a>(
+    layouts: impl IntoIterator<Item = &'a LayoutDefinition>,
+    ranking: &[KeyRankingItem],
+    days: i64,
+) -> Vec<FormFactorScore> {
+    layouts
+        .into_iter()
+        .map(|layout| score_layout(layout, ranking, days))
+        .collect()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HeatmapKey {
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+    pub legend: String,
+    pub key_code: Option<String>,
+    pub count: i64,
+    /// 最も多く押されたキーを1.0とした相対値
+    pub ratio: f64,
+}
+
+/// レイアウトの物理配置に押下数を割り当てたヒートマップ
+pub fn layout_heatmap(layout: &LayoutDefinition, ranking: &[KeyRankingItem]) -> Vec<HeatmapKey> {
+    let counts: HashMap<&str, i64> = ranking
+        .iter()
+        .map(|item| (item.key_code.as_str(), item.count))
+        .collect();
+    let key_count = |key: &Option<String>| {
+        key.as_deref()
+            .and_then(|k| counts.get(k).copied())
+            .unwrap_or(0)
+    };
+    let max = layout
+        .geometry
+        .iter()
+        .map(|k| key_count(&k.key_code))
+        .max()
+        .unwrap_or(0);
+
+    layout
+        .geometry
+        .iter()
+        .map(|k| {
+            let count = key_count(&k.key_code);
+            HeatmapKey {
+                x: k.x,
+                y: k.y,
+                w: k.w,
+                h: k.h,
+                legend: k.legend.clone(),
+                key_code: k.key_code.clone(),
+                count,
+                ratio: if max > 0 {
+                    count as f64 / max as f64
+                } else {
+                    0.0
+                },
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::LayoutCatalog;
+
+    fn item(key_code: &str, count: i64) -> KeyRankingItem {
+        KeyRankingItem {
+            key_code: key_code.to_string(),
+            count,
+        }
+    }
+
+    #[test]
+    fn test_ansi_form_factors_are_nested() {
+        let catalog = LayoutCatalog::builtin();
+        let ids = [
+            "ansi-40",
+            "ansi-60",
+            "ansi-65",
+            "ansi-75",
+            "ansi-tkl",
+            "ansi-full",
+        ];
+        for pair in ids.windows(2) {
+            let smaller = catalog.get(pair[0]).unwrap().key_set();
+            let larger = catalog.get(pair[1]).unwrap().key_set();
+            assert!(smaller.len() < larger.len());
+            assert!(smaller.is_subset(&larger));
+        }
+    }
+
+    #[test]
+    fn test_score_layout() {
+        let ranking = vec![
+            item("KeyE", 600),
+            item("F5", 100),
+            item("UpArrow", 200),
+            item("Kp1", 100),
+        ];
+        let catalog = LayoutCatalog::builtin();
+        let score = score_layout(catalog.get("ansi-60").unwrap(), &ranking, 10);
+        assert_eq!(score.total_presses, 1000);
+        assert_eq!(score.base_layer_presses, 600);
+        assert!((score.base_layer_percentage - 60.0).abs() < f64::EPSILON);
+        assert!((score.layer_presses_per_day - 40.0).abs() < f64::EPSILON);
+        let blocking: Vec<&str> = score
+            .blocking_keys
+            .iter()
+            .map(|k| k.key_code.as_str())
+            .collect();
+        assert_eq!(blocking, vec!["UpArrow", "F5", "Kp1"]);
+
+        let full = score_layout(catalog.get("ansi-full").unwrap(), &ranking, 10);
+        assert_eq!(full.base_layer_presses, 1000);
+        assert!(full.blocking_keys.is_empty());
+    }
+
+    #[test]
+    fn test_score_layout_empty() {
+        let catalog = LayoutCatalog::builtin();
+        let score = score_layout(catalog.get("ansi-40").unwrap(), &[], 0);
+        assert_eq!(score.total_presses, 0);
+        assert!((score.base_layer_percentage - 100.0).abs() < f64::EPSILON);
+        assert!((score.layer_presses_per_day - 0.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_layout_heatmap() {
+        let kle =
+            crate::kle::parse_kle(r#"[["Q","W",{w:2},"Fn"]]"#, "tiny", "Tiny", &HashMap::new())
+                .unwrap();
+        let heatmap = layout_heatmap(&kle.layout, &[item("KeyQ", 50), item("KeyW", 100)]);
+        assert_eq!(heatmap.len(), 3);
+        assert_eq!(heatmap[0].count, 50);
+        assert!((heatmap[0].ratio - 0.5).abs() < f64::EPSILON);
+        assert!((heatmap[1].ratio - 1.0).abs() < f64::EPSILON);
+        assert_eq!(heatmap[2].key_code, None);
+        assert_eq!(heatmap[2].count, 0);
+    }
+}
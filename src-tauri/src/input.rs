@@ -0,0 +1,232 @@
+use anyhow::{anyhow, Result};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+#[cfg(target_os = "linux")]
+mod evdev_input;
+#[cfg(target_os = "linux")]
+pub use evdev_input::EvdevSource;
+
+/// キーボードイベント（key_code は rdev の `Key` の Debug 表記、例: "KeyA"）
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputEventKind {
+    KeyPress(String),
+    KeyRelease(String),
+}
+
+/// イベントの元になった物理キーボード
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InputDevice {
+    pub name: String,
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+}
+
+impl InputDevice {
+    /// 同じ型番・名前のキーボードは同じデバイスとみなす（差し直しやポートの変更で変わらない）
+    pub fn identifier(&self) -> String {
+        format!(
+            "{:04x}:{:04x}:{}",
+            self.vendor_id.unwrap_or(0),
+            self.product_id.unwrap_or(0),
+            self.name
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputEvent {
+    pub kind: InputEventKind,
+    pub time: SystemTime,
+    /// デバイスを区別できない入力元（rdev）では None
+    pub device: Option<Arc<InputDevice>>,
+}
+
+#[allow(dead_code)]
+impl InputEvent {
+    pub fn press(key_code: &str, time: SystemTime) -> Self {
+        Self {
+            kind: InputEventKind::KeyPress(key_code.to_string()),
+            time,
+            device: None,
+        }
+    }
+
+    pub fn release(key_code: &str, time: SystemTime) -> Self {
+        Self {
+            kind: InputEventKind::KeyRelease(key_code.to_string()),
+            time,
+            device: None,
+        }
+    }
+
+    pub fn from_device(mut self, device: &Arc<InputDevice>) -> Self {
+        self.device = Some(device.clone());
+        self
+    }
+}
+
+pub type EventCallback = Box<dyn FnMut(InputEvent) + Send>;
+
+/// キーボードイベントの入力元
+pub trait EventSource: Send + Sync {
+    /// listenの前に必要な権限などを確認する
+    fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// イベントごとに `callback` を呼ぶ。入力が終わるか失敗するまで戻らない
+    fn listen(&self, callback: EventCallback) -> Result<()>;
+
+    /// オートリピートを解放と押下の組で送るか（X11）
+    fn repeats_as_release_press(&self) -> bool {
+        false
+    }
+}
+
+/// このOSで使える入力元を選ぶ
+/// rdev はLinuxではX11経由でしか入力を受け取れないため、Wayland・TTYでは evdev を使う
+pub fn system_source() -> Arc<dyn EventSource> {
+    #[cfg(target_os = "linux")]
+    {
+        let has_x11 = std::env::var_os("DISPLAY").is_some();
+        let is_wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+        if is_wayland || !has_x11 {
+            let source = EvdevSource::default();
+            match source.prepare() {
+                Ok(()) => return Arc::new(source),
+                Err(e) => eprintln!("[KeyFit] evdev is not available: {}", e),
+            }
+        }
+    }
+    Arc::new(RdevSource)
+}
+
+/// rdev によるOS全体のキーフック（一度listenすると抜けられない）
+pub struct RdevSource;
+
+impl EventSource for RdevSource {
+    #[cfg(target_os = "macos")]
+    fn prepare(&self) -> Result<()> {
+        if !check_accessibility_permission() {
+            return Err(anyhow!("Insufficient macOS permissions. Please follow the instructions above to set permissions."));
+        }
+        Ok(())
+    }
+
+    fn listen(&self, mut callback: EventCallback) -> Result<()> {
+        rdev::listen(move |event: rdev::Event| {
+            let kind = match event.event_type {
+                rdev::EventType::KeyPress(key) => InputEventKind::KeyPress(format!("{:?}", key)),
+                rdev::EventType::KeyRelease(key) => {
+                    InputEventKind::KeyRelease(format!("{:?}", key))
+                }
+                _ => return,
+            };
+            callback(InputEvent {
+                kind,
+                time: event.time,
+                device: None,
+            });
+        })
+        .map_err(|e| anyhow!("Keyboard hook error: {:?}", e))
+    }
+
+    /// Linux の rdev は X11 から受け取る（macOS・Windows は押しっぱなしの間、押下だけを送る）
+    fn repeats_as_release_press(&self) -> bool {
+        cfg!(target_os = "linux")
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn check_accessibility_permission() -> bool {
+    use std::process::Command;
+    // Check Input Monitoring permission
+    let input_monitoring_check = Command::new("osascript")
+        .arg("-e")
+        .arg("tell application \"System Events\" to keystroke \"\"")
+        .output();
+    let has_input_monitoring = match input_monitoring_check {
+        Ok(result) => result.status.success(),
+        Err(e) => {
+            eprintln!("Error checking Input Monitoring permission: {}", e);
+            false
+        }
+    };
+    // Check Accessibility permission
+    let accessibility_check = Command::new("osascript")
+        .arg("-e")
+        .arg("tell application \"System Events\" to get name of first process")
+        .output();
+    let has_accessibility = match accessibility_check {
+        Ok(result) => result.status.success(),
+        Err(e) => {
+            eprintln!("Error checking Accessibility permission: {}", e);
+            false
+        }
+    };
+    if !has_input_monitoring || !has_accessibility {
+        eprintln!("=== macOS permissions required ===");
+        eprintln!("Go to System Preferences > Security & Privacy > Privacy and set the following:");
+        if !has_accessibility {
+            eprintln!("1. Accessibility: Add and enable the KeyFit app");
+        }
+        if !has_input_monitoring {
+            eprintln!("2. Input Monitoring: Add and enable the KeyFit app");
+        }
+        eprintln!("");
+        eprintln!("Important: On macOS Mojave or later, 'Input Monitoring' permission is required to monitor keyboard events. Without this permission, the rdev library cannot receive keyboard events.");
+        eprintln!("");
+        eprintln!("After setting, please restart the app.");
+        eprintln!("=== End of permission instructions ===");
+        return false;
+    }
+    true
+}
+
+/// 送信側から渡したイベントをそのまま流す入力元（テスト用）
+/// 送信側をすべて破棄すると入力が終わり、listenから戻る
+#[allow(dead_code)]
+pub struct SyntheticSource {
+    receiver: Mutex<Option<Receiver<InputEvent>>>,
+    x11_repeats: bool,
+}
+
+impl SyntheticSource {
+    #[allow(dead_code)]
+    pub fn new() -> (Self, Sender<InputEvent>) {
+        let (sender, receiver) = channel();
+        let source = Self {
+            receiver: Mutex::new(Some(receiver)),
+            x11_repeats: false,
+        };
+        (source, sender)
+    }
+
+    /// X11 と同じくオートリピートを解放と押下の組で送る入力元として扱う
+    #[allow(dead_code)]
+    pub fn with_x11_repeats(mut self) -> Self {
+        self.x11_repeats = true;
+        self
+    }
+}
+
+impl EventSource for SyntheticSource {
+    fn listen(&self, mut callback: EventCallback) -> Result<()> {
+        let receiver = self
+            .receiver
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| anyhow!("Synthetic source is already listening"))?;
+        for event in receiver {
+            callback(event);
+        }
+        Ok(())
+    }
+
+    fn repeats_as_release_press(&self) -> bool {
+        self.x11_repeats
+    }
+}
@@ -0,0 +1,455 @@
+use super::{EventCallback, EventSource, InputDevice, InputEvent, InputEventKind};
+use anyhow::{anyhow, Result};
+use evdev::{Device, Key};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const INPUT_DIR: &str = "/dev/input";
+/// 後から接続されたキーボードを探す間隔
+const DEVICE_SCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// `/dev/input/event*` を直接読む入力元（X11・Waylandのどちらでも、TTYでも動く）
+/// 読むには root か `input` グループへの所属が必要
+#[derive(Default)]
+pub struct EvdevSource {
+    /// None なら文字キーを持つデバイスをすべて使う
+    paths: Option<Vec<PathBuf>>,
+    /// prepare で開いたデバイス（開いた後のイベントを取りこぼさないよう listen まで持っておく）
+    devices: Mutex<Vec<(PathBuf, Device)>>,
+}
+
+impl EvdevSource {
+    /// 指定したデバイスだけを読む
+    #[allow(dead_code)]
+    pub fn with_devices(paths: Vec<PathBuf>) -> Self {
+        Self {
+            paths: Some(paths),
+            devices: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn open_devices(&self) -> Result<Vec<(PathBuf, Device)>> {
+        let Some(paths) = &self.paths else {
+            let keyboards = scan_keyboards(Path::new(INPUT_DIR), &HashSet::new());
+            if keyboards.is_empty() {
+                return Err(anyhow!(
+                    "No readable keyboard found in /dev/input (the user may need to be in the 'input' group)"
+                ));
+            }
+            return Ok(keyboards);
+        };
+        paths
+            .iter()
+            .map(|path| {
+                let device = Device::open(path)
+                    .map_err(|e| anyhow!("Failed to open {}: {}", path.display(), e))?;
+                Ok((path.clone(), device))
+            })
+            .collect()
+    }
+}
+
+/// `dir` にあるキーボードのうち、`skip` に含まれないものを開く
+/// 権限のないデバイスは開けないので飛ばす（udev が権限を設定した後の走査で開ける）
+fn scan_keyboards(dir: &Path, skip: &HashSet<PathBuf>) -> Vec<(PathBuf, Device)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("event"))
+                && !skip.contains(path)
+        })
+        .collect();
+    paths.sort();
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let device = Device::open(&path).ok()?;
+            is_keyboard(&device).then_some((path, device))
+        })
+        .collect()
+}
+
+/// マウスや電源ボタンなども evdev デバイスなので、文字キーとEnterを持つものだけをキーボードとみなす
+fn is_keyboard(device: &Device) -> bool {
+    device.supported_keys().is_some_and(|keys| {
+        keys.contains(Key::KEY_A) && keys.contains(Key::KEY_Z) && keys.contains(Key::KEY_ENTER)
+    })
+}
+
+impl EventSource for EvdevSource {
+    /// 開いたデバイスは listen まで持っておくので、2回目以降は開き直さない
+    fn prepare(&self) -> Result<()> {
+        let mut devices = self.devices.lock().unwrap();
+        if devices.is_empty() {
+            *devices = self.open_devices()?;
+        }
+        Ok(())
+    }
+
+    /// デバイスごとにスレッドで読む
+    /// デバイスを指定していなければ、後から接続・差し直したキーボードも読み始める
+    /// 指定したデバイスがすべて外れたら戻る
+    fn listen(&self, mut callback: EventCallback) -> Result<()> {
+        let mut devices = std::mem::take(&mut *self.devices.lock().unwrap());
+        if devices.is_empty() {
+            devices = self.open_devices()?;
+        }
+        let (sender, receiver) = channel();
+        let active = Arc::new(Mutex::new(HashSet::new()));
+        for (path, device) in devices {
+            spawn_reader(path, device, sender.clone(), active.clone());
+        }
+        if self.paths.is_none() {
+            let sender = sender.clone();
+            thread::spawn(move || loop {
+                thread::sleep(DEVICE_SCAN_INTERVAL);
+                let skip = active.lock().unwrap().clone();
+                for (path, device) in scan_keyboards(Path::new(INPUT_DIR), &skip) {
+                    eprintln!("[KeyFit] evdev device {} connected", path.display());
+                    spawn_reader(path, device, sender.clone(), active.clone());
+                }
+            });
+        }
+        drop(sender);
+        for event in receiver {
+            callback(event);
+        }
+        Err(anyhow!("All keyboard devices were disconnected"))
+    }
+}
+
+/// 1台のデバイスを読むスレッド（読んでいる間は `active` にパスを入れておく）
+fn spawn_reader(
+    path: PathBuf,
+    mut device: Device,
+    sender: Sender<InputEvent>,
+    active: Arc<Mutex<HashSet<PathBuf>>>,
+) {
+    let input_device = Arc::new(InputDevice {
+        name: device.name().unwrap_or("Unknown keyboard").to_string(),
+        vendor_id: Some(device.input_id().vendor()),
+        product_id: Some(device.input_id().product()),
+    });
+    active.lock().unwrap().insert(path.clone());
+    thread::spawn(move || {
+        read_events(&path, &mut device, &input_device, &sender);
+        active.lock().unwrap().remove(&path);
+    });
+}
+
+fn read_events(
+    path: &Path,
+    device: &mut Device,
+    input_device: &Arc<InputDevice>,
+    sender: &Sender<InputEvent>,
+) {
+    loop {
+        let events = match device.fetch_events() {
+            Ok(events) => events,
+            Err(e) => {
+                eprintln!(
+                    "[KeyFit] evdev device {} disconnected: {}",
+                    path.display(),
+                    e
+                );
+                return;
+            }
+        };
+        for event in events {
+            let evdev::InputEventKind::Key(key) = event.kind() else {
+                continue;
+            };
+            // 1: 押下、2: オートリピート（rdev と同じく押下として扱う）、0: 離す
+            let kind = match event.value() {
+                0 => InputEventKind::KeyRelease(key_code(key)),
+                _ => InputEventKind::KeyPress(key_code(key)),
+            };
+            let event = InputEvent {
+                kind,
+                time: event.timestamp(),
+                device: Some(input_device.clone()),
+            };
+            if sender.send(event).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Linuxのキーコードを key_code（rdev の Key の Debug 表記）に変換する
+/// 対応がないキーは rdev と同じく X11 のキーコード（evdev + 8）で `Unknown(n)` にする
+fn key_code(key: Key) -> String {
+    let name = match key {
+        Key::KEY_ESC => "Escape",
+        Key::KEY_1 => "Num1",
+        Key::KEY_2 => "Num2",
+        Key::KEY_3 => "Num3",
+        Key::KEY_4 => "Num4",
+        Key::KEY_5 => "Num5",
+        Key::KEY_6 => "Num6",
+        Key::KEY_7 => "Num7",
+        Key::KEY_8 => "Num8",
+        Key::KEY_9 => "Num9",
+        Key::KEY_0 => "Num0",
+        Key::KEY_MINUS => "Minus",
+        Key::KEY_EQUAL => "Equal",
+        Key::KEY_BACKSPACE => "Backspace",
+        Key::KEY_TAB => "Tab",
+        Key::KEY_Q => "KeyQ",
+        Key::KEY_W => "KeyW",
+        Key::KEY_E => "KeyE",
+        Key::KEY_R => "KeyR",
+        Key::KEY_T => "KeyT",
+        Key::KEY_Y => "KeyY",
+        Key::KEY_U => "KeyU",
+        Key::KEY_I => "KeyI",
+        Key::KEY_O => "KeyO",
+        Key::KEY_P => "KeyP",
+        Key::KEY_LEFTBRACE => "LeftBracket",
+        Key::KEY_RIGHTBRACE => "RightBracket",
+        Key::KEY_ENTER => "Return",
+        Key::KEY_LEFTCTRL => "ControlLeft",
+        Key::KEY_A => "KeyA",
+        Key::KEY_S => "KeyS",
+        Key::KEY_D => "KeyD",
+        Key::KEY_F => "KeyF",
+        Key::KEY_G => "KeyG",
+        Key::KEY_H => "KeyH",
+        Key::KEY_J => "KeyJ",
+        Key::KEY_K => "KeyK",
+        Key::KEY_L => "KeyL",
+        Key::KEY_SEMICOLON => "SemiColon",
+        Key::KEY_APOSTROPHE => "Quote",
+        Key::KEY_GRAVE => "BackQuote",
+        Key::KEY_LEFTSHIFT => "ShiftLeft",
+        Key::KEY_BACKSLASH => "BackSlash",
+        Key::KEY_Z => "KeyZ",
+        Key::KEY_X => "KeyX",
+        Key::KEY_C => "KeyC",
+        Key::KEY_V => "KeyV",
+        Key::KEY_B => "KeyB",
+        Key::KEY_N => "KeyN",
+        Key::KEY_M => "KeyM",
+        Key::KEY_COMMA => "Comma",
+        Key::KEY_DOT => "Dot",
+        Key::KEY_SLASH => "Slash",
+        Key::KEY_RIGHTSHIFT => "ShiftRight",
+        Key::KEY_KPASTERISK => "KpMultiply",
+        Key::KEY_LEFTALT => "Alt",
+        Key::KEY_SPACE => "Space",
+        Key::KEY_CAPSLOCK => "CapsLock",
+        Key::KEY_F1 => "F1",
+        Key::KEY_F2 => "F2",
+        Key::KEY_F3 => "F3",
+        Key::KEY_F4 => "F4",
+        Key::KEY_F5 => "F5",
+        Key::KEY_F6 => "F6",
+        Key::KEY_F7 => "F7",
+        Key::KEY_F8 => "F8",
+        Key::KEY_F9 => "F9",
+        Key::KEY_F10 => "F10",
+        Key::KEY_F11 => "F11",
+        Key::KEY_F12 => "F12",
+        Key::KEY_NUMLOCK => "NumLock",
+        Key::KEY_SCROLLLOCK => "ScrollLock",
+        Key::KEY_KP7 => "Kp7",
+        Key::KEY_KP8 => "Kp8",
+        Key::KEY_KP9 => "Kp9",
+        Key::KEY_KPMINUS => "KpMinus",
+        Key::KEY_KP4 => "Kp4",
+        Key::KEY_KP5 => "Kp5",
+        Key::KEY_KP6 => "Kp6",
+        Key::KEY_KPPLUS => "KpPlus",
+        Key::KEY_KP1 => "Kp1",
+        Key::KEY_KP2 => "Kp2",
+        Key::KEY_KP3 => "Kp3",
+        Key::KEY_KP0 => "Kp0",
+        Key::KEY_KPDOT => "KpDecimal",
+        Key::KEY_102ND => "IntlBackslash",
+        Key::KEY_KATAKANAHIRAGANA => "KanaMode",
+        Key::KEY_KPENTER => "KpReturn",
+        Key::KEY_RIGHTCTRL => "ControlRight",
+        Key::KEY_KPSLASH => "KpDivide",
+        Key::KEY_SYSRQ => "PrintScreen",
+        Key::KEY_RIGHTALT => "AltGr",
+        Key::KEY_HOME => "Home",
+        Key::KEY_UP => "UpArrow",
+        Key::KEY_PAGEUP => "PageUp",
+        Key::KEY_LEFT => "LeftArrow",
+        Key::KEY_RIGHT => "RightArrow",
+        Key::KEY_END => "End",
+        Key::KEY_DOWN => "DownArrow",
+        Key::KEY_PAGEDOWN => "PageDown",
+        Key::KEY_INSERT => "Insert",
+        Key::KEY_DELETE => "Delete",
+        Key::KEY_PAUSE => "Pause",
+        Key::KEY_HANGEUL => "Lang1",
+        Key::KEY_HANJA => "Lang2",
+        Key::KEY_LEFTMETA => "MetaLeft",
+        Key::KEY_RIGHTMETA => "MetaRight",
+        Key::KEY_COMPOSE => "Apps",
+        Key::KEY_FN => "Function",
+        _ => return format!("Unknown({})", key.0 as u32 + 8),
+    };
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::appinfo::{ActiveAppInfo, FixedAppProvider};
+    use crate::db::{Database, StatFilter};
+    use crate::keyboard::KeyboardHook;
+    use evdev::uinput::{VirtualDevice, VirtualDeviceBuilder};
+    use evdev::{AttributeSet, EventType};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tempfile::NamedTempFile;
+
+    fn key(key: Key, value: i32) -> evdev::InputEvent {
+        evdev::InputEvent::new(EventType::KEY, key.0, value)
+    }
+
+    /// 仮想キーボードを作り、デバイスノードが開けるようになるまで待つ
+    fn virtual_keyboard(name: &str) -> (VirtualDevice, PathBuf) {
+        let keys: AttributeSet<Key> = [Key::KEY_A, Key::KEY_Z, Key::KEY_ENTER, Key::KEY_LEFTSHIFT]
+            .into_iter()
+            .collect();
+        let mut keyboard = VirtualDeviceBuilder::new()
+            .unwrap()
+            .name(name)
+            .with_keys(&keys)
+            .unwrap()
+            .build()
+            .unwrap();
+        let path = keyboard
+            .enumerate_dev_nodes_blocking()
+            .unwrap()
+            .find_map(|node| node.ok())
+            .unwrap();
+        // udev がデバイスノードを作るまで待つ
+        for _ in 0..100 {
+            if Device::open(&path).is_ok() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        (keyboard, path)
+    }
+
+    #[test]
+    fn test_key_code() {
+        assert_eq!(key_code(Key::KEY_A), "KeyA");
+        assert_eq!(key_code(Key::KEY_1), "Num1");
+        assert_eq!(key_code(Key::KEY_ENTER), "Return");
+        assert_eq!(key_code(Key::KEY_KPENTER), "KpReturn");
+        assert_eq!(key_code(Key::KEY_LEFTALT), "Alt");
+        assert_eq!(key_code(Key::KEY_RIGHTALT), "AltGr");
+        assert_eq!(key_code(Key::KEY_GRAVE), "BackQuote");
+        assert_eq!(key_code(Key::KEY_102ND), "IntlBackslash");
+        // KEY_MUHENKAN (94) は X11 では 102
+        assert_eq!(key_code(Key::KEY_MUHENKAN), "Unknown(102)");
+    }
+
+    /// `sudo cargo test -- --ignored` などで /dev/uinput に書ける状態で実行する
+    #[test]
+    #[ignore = "requires write access to /dev/uinput"]
+    fn test_evdev_virtual_keyboard() {
+        let (mut keyboard, path) = virtual_keyboard("KeyFit test keyboard");
+        let temp_file = NamedTempFile::new().unwrap();
+        let db = Arc::new(Database::new(temp_file.path()).unwrap());
+        let hook = KeyboardHook::new(
+            db.clone(),
+            Arc::new(EvdevSource::with_devices(vec![path])),
+            Arc::new(FixedAppProvider(Some(ActiveAppInfo::new(
+                "Test App",
+                "com.test.app",
+            )))),
+        );
+        hook.start().unwrap();
+
+        for _ in 0..3 {
+            keyboard.emit(&[key(Key::KEY_A, 1)]).unwrap();
+            keyboard.emit(&[key(Key::KEY_A, 0)]).unwrap();
+        }
+        keyboard.emit(&[key(Key::KEY_ENTER, 1)]).unwrap();
+        keyboard.emit(&[key(Key::KEY_ENTER, 0)]).unwrap();
+
+        let mut ranking = Vec::new();
+        for _ in 0..200 {
+            hook.flush();
+            ranking = db
+                .get_key_ranking(&StatFilter::default(), None, false)
+                .unwrap()
+                .into_iter()
+                .map(|item| (item.key_code, item.count))
+                .collect();
+            if ranking.iter().map(|(_, count)| count).sum::<i64>() >= 4 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            ranking,
+            vec![("KeyA".to_string(), 3), ("Return".to_string(), 1)]
+        );
+        let devices = db.get_devices().unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, "KeyFit test keyboard");
+
+        // 仮想キーボードを外すと入力元が終わり、失敗として扱われる
+        drop(keyboard);
+        for _ in 0..200 {
+            if !hook.is_running() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!hook.is_running());
+    }
+
+    /// 監視を始めた後に接続したキーボードも読む
+    #[test]
+    #[ignore = "requires read access to /dev/input and write access to /dev/uinput"]
+    fn test_evdev_hotplug() {
+        // 開始時に少なくとも1台のキーボードが必要
+        let (_first, _) = virtual_keyboard("KeyFit first keyboard");
+        let source = Arc::new(EvdevSource::default());
+        source.prepare().unwrap();
+        let (sender, receiver) = channel();
+        let listener = source.clone();
+        thread::spawn(move || {
+            let _ = listener.listen(Box::new(move |event| {
+                let _ = sender.send(event);
+            }));
+        });
+
+        let (mut second, _) = virtual_keyboard("KeyFit second keyboard");
+        let deadline = std::time::Instant::now() + DEVICE_SCAN_INTERVAL * 3;
+        while std::time::Instant::now() < deadline {
+            second.emit(&[key(Key::KEY_Z, 1)]).unwrap();
+            second.emit(&[key(Key::KEY_Z, 0)]).unwrap();
+            // 読み始めたのが押下と離す間だと最初のイベントは離す方になる
+            for event in receiver.try_iter() {
+                if event.kind == InputEventKind::KeyPress("KeyZ".to_string())
+                    && event
+                        .device
+                        .is_some_and(|d| d.name == "KeyFit second keyboard")
+                {
+                    return;
+                }
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        panic!("events from the keyboard connected later were not read");
+    }
+}
@@ -0,0 +1,175 @@
+use crate::db::KeyRankingItem;
+use crate::layout::LayoutDefinition;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// 最も押されたキーに対してこの割合以上押されているキーを「先に寿命が来る」とみなす
+const WEARS_FIRST_RATIO: f64 = 0.5;
+
+/// 登録・編集時にフロントエンドから渡すキーボードの情報
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyboardSpec {
+    pub name: String,
+    /// LayoutCatalog のレイアウトid
+    pub layout_id: Option<String>,
+    /// 例: "Gateron Yellow"
+    pub switch_type: Option<String>,
+    /// スイッチの定格寿命（打鍵回数）
+    pub rated_lifetime: i64,
+}
+
+impl KeyboardSpec {
+    pub fn validate(&self) -> Result<()> {
+        if self.rated_lifetime <= 0 {
+            return Err(anyhow!(
+                "Rated lifetime must be positive: {}",
+                self.rated_lifetime
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 在庫のキーボード
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keyboard {
+    pub id: i64,
+    #[serde(flatten)]
+    pub spec: KeyboardSpec,
+    /// 押下を自動で割り当てるデバイス（device.id）
+    pub device_ids: Vec<i64>,
+}
+
+/// デバイスを区別できない押下を手動で割り当てる期間（end_date が None なら現在まで）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyboardPeriod {
+    pub id: i64,
+    pub keyboard_id: i64,
+    pub start_date: i64,
+    pub end_date: Option<i64>,
+}
+
+/// キーごとのスイッチの消耗
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyWear {
+    pub key_code: String,
+    pub presses: i64,
+    /// 定格寿命に対する消費率（%）
+    pub used_percentage: f64,
+    pub remaining: i64,
+    /// 今のペースで定格寿命に達するまでの日数（押されていないキーは None）
+    /// ペースは押下を記録した日の平均なので、使わない日がある場合は実際より短く出る
+    pub days_left: Option<i64>,
+    pub wears_first: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WearReport {
+    pub keyboard_id: i64,
+    pub rated_lifetime: i64,
+    /// 押下を記録した日数（ペースの計算に使う）
+    pub days: i64,
+    /// 押下数の多い順
+    pub keys: Vec<KeyWear>,
+}
+
+/// キーボードに割り当てた押下数から、キーごとの残り寿命を見積もる
+/// レイアウトが分かれば、一度も押されていないキーも含める
+/// days は暦日数ではなく押下を記録した日数（使った日1日あたりのペースで見積もる）
+pub fn build_wear_report(
+    keyboard: &Keyboard,
+    layout: Option<&LayoutDefinition>,
+    ranking: &[KeyRankingItem],
+    days: i64,
+) -> WearReport {
+    let mut presses: HashMap<&str, i64> = HashMap::new();
+    for key_code in layout.iter().flat_map(|l| &l.keys) {
+        presses.insert(key_code, 0);
+    }
+    for item in ranking {
+        *presses.entry(&item.key_code).or_insert(0) += item.count;
+    }
+
+    let rated_lifetime = keyboard.spec.rated_lifetime;
+    let most_pressed = presses.values().copied().max().unwrap_or(0);
+    let mut keys: Vec<KeyWear> = presses
+        .into_iter()
+        .map(|(key_code, presses)| {
+            let remaining = (rated_lifetime - presses).max(0);
+            let days_left = (presses > 0 && days > 0).then(|| {
+                let per_day = presses as f64 / days as f64;
+                (remaining as f64 / per_day).ceil() as i64
+            });
+            KeyWear {
+                key_code: key_code.to_string(),
+                presses,
+                used_percentage: if rated_lifetime > 0 {
+                    presses as f64 / rated_lifetime as f64 * 100.0
+                } else {
+                    0.0
+                },
+                remaining,
+                days_left,
+                wears_first: presses > 0
+                    && presses as f64 >= most_pressed as f64 * WEARS_FIRST_RATIO,
+            }
+        })
+        .collect();
+    keys.sort_by(|a, b| b.presses.cmp(&a.presses).then(a.key_code.cmp(&b.key_code)));
+
+    WearReport {
+        keyboard_id: keyboard.id,
+        rated_lifetime,
+        days,
+        keys,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_wear_report() {
+        let keyboard = Keyboard {
+            id: 1,
+            spec: KeyboardSpec {
+                name: "60%".to_string(),
+                layout_id: Some("tiny".to_string()),
+                switch_type: Some("Gateron Yellow".to_string()),
+                rated_lifetime: 1_000,
+            },
+            device_ids: vec![],
+        };
+        let layout: LayoutDefinition = serde_json::from_str(
+            r#"{"id": "tiny", "name": "Tiny", "keys": ["Space", "KeyE", "KeyQ", "KeyZ"]}"#,
+        )
+        .unwrap();
+        let item = |key_code: &str, count| KeyRankingItem {
+            key_code: key_code.to_string(),
+            count,
+        };
+        let ranking = [item("Space", 400), item("KeyE", 250), item("KeyZ", 50)];
+
+        let report = build_wear_report(&keyboard, Some(&layout), &ranking, 10);
+        let keys: Vec<&str> = report.keys.iter().map(|k| k.key_code.as_str()).collect();
+        assert_eq!(keys, vec!["Space", "KeyE", "KeyZ", "KeyQ"]);
+
+        let space = &report.keys[0];
+        assert_eq!(space.used_percentage, 40.0);
+        assert_eq!(space.remaining, 600);
+        // 1日40回のペースで残り600回
+        assert_eq!(space.days_left, Some(15));
+        assert!(space.wears_first);
+        assert!(report.keys[1].wears_first);
+        assert!(!report.keys[2].wears_first);
+        // 押されていないキーは寿命に達しない
+        assert_eq!(report.keys[3].days_left, None);
+        assert!(!report.keys[3].wears_first);
+
+        // レイアウトがなければ押されたキーだけ
+        let report = build_wear_report(&keyboard, None, &ranking, 10);
+        assert_eq!(report.keys.len(), 3);
+    }
+}
@@ -1,67 +1,636 @@
-use super::appinfo::get_active_app_info;
-use crate::dialog;
+use crate::appinfo::ActiveAppProvider;
+use crate::db::{PurgeReport, UNKNOWN_DEVICE_ID};
+use crate::exclusion::ExclusionSet;
+use crate::foreground::{ActiveApp, ForegroundTracker, FOREGROUND_POLL_INTERVAL};
+use crate::input::{EventSource, InputDevice, InputEvent, InputEventKind};
+use crate::taphold::{TapHoldCounts, TapHoldKey, TapHoldTracker};
 use anyhow::Result;
-use chrono::Local;
-use rdev::{Event, EventType};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Local, Timelike};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::thread::JoinHandle;
-use std::time::Duration;
-use tauri::AppHandle;
+use std::time::{Duration, Instant, SystemTime};
 
-#[derive(Clone)]
+/// バイグラム記録で連続とみなす最大間隔（ミリ秒）の初期値
+pub const DEFAULT_BIGRAM_MAX_GAP_MS: u64 = 1000;
+
+/// バイグラム記録の設定
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BigramCapture {
+    pub enabled: bool,
+    pub max_gap_ms: u64,
+}
+
+impl Default for BigramCapture {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_gap_ms: DEFAULT_BIGRAM_MAX_GAP_MS,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct KeyStat {
     pub ts_day: i64,
+    pub ts_hour: i64,
+    pub key_code: String,
+    pub app_id: i64,
+    pub device_id: i64,
+}
+
+/// 直前のキーから次のキーへの遷移（並びそのものは保存せず回数だけ集計する）
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct KeyBigram {
+    pub ts_day: i64,
+    pub ts_hour: i64,
+    pub prev_key: String,
+    pub key_code: String,
+    pub app_id: i64,
+    pub device_id: i64,
+}
+
+/// 押下から解放までの時間（ミリ秒）を集計するヒストグラムの区間の下限
+pub const DWELL_BUCKETS_MS: &[i64] = &[0, 50, 100, 150, 200, 300, 500, 1000, 2000];
+
+/// 押下時間をヒストグラムの区間（下限のミリ秒）に丸める
+pub fn dwell_bucket(dwell: Duration) -> i64 {
+    let ms = dwell.as_millis() as i64;
+    DWELL_BUCKETS_MS
+        .iter()
+        .rev()
+        .find(|lower| **lower <= ms)
+        .copied()
+        .unwrap_or(0)
+}
+
+/// キーごとの押下時間
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct KeyDwell {
+    pub ts_day: i64,
+    pub ts_hour: i64,
     pub key_code: String,
     pub app_id: i64,
+    pub device_id: i64,
+    pub bucket_ms: i64,
+}
+
+/// 押下と解放を対応させて押下時間を測る
+#[derive(Default)]
+pub struct DwellTracker {
+    pressed: HashMap<String, SystemTime>,
+}
+
+impl DwellTracker {
+    /// 最初の押下時刻を記録する（押しっぱなしのリピートでは更新しない）
+    pub fn press(&mut self, key_code: &str, time: SystemTime) {
+        self.pressed.entry(key_code.to_string()).or_insert(time);
+    }
+
+    /// 対応する押下があれば押下時間を返す
+    pub fn release(&mut self, key_code: &str, time: SystemTime) -> Option<Duration> {
+        let pressed_at = self.pressed.remove(key_code)?;
+        time.duration_since(pressed_at).ok()
+    }
+}
+
+/// 修飾キーを伴うショートカット（例: "Ctrl+Shift+KeyP"）
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ShortcutStat {
+    pub ts_day: i64,
+    pub ts_hour: i64,
+    pub shortcut: String,
+    pub app_id: i64,
+    pub device_id: i64,
+}
+
+/// 押下中の修飾キーからショートカットを組み立てる
+#[derive(Default)]
+pub struct ChordTracker {
+    held: HashSet<String>,
+}
+
+/// 修飾キーのkey_codeと、ショートカット表記での名前（表記順）
+const MODIFIERS: &[(&str, &str)] = &[
+    ("ControlLeft", "Ctrl"),
+    ("ControlRight", "Ctrl"),
+    ("Alt", "Alt"),
+    ("AltGr", "AltGr"),
+    ("ShiftLeft", "Shift"),
+    ("ShiftRight", "Shift"),
+    ("MetaLeft", "Meta"),
+    ("MetaRight", "Meta"),
+];
+
+/// Ctrl/Alt/Shift/Meta（左右とも）
+pub fn is_modifier(key_code: &str) -> bool {
+    MODIFIERS.iter().any(|(code, _)| *code == key_code)
+}
+
+/// 修飾キーなしで文字を入力するキー（Shift/AltGrとの組み合わせは文字入力とみなす）
+fn is_character_key(key_code: &str) -> bool {
+    key_code.starts_with("Key")
+        || (key_code.starts_with("Num") && key_code.len() == 4)
+        || matches!(
+            key_code,
+            "Space"
+                | "BackQuote"
+                | "Minus"
+                | "Equal"
+                | "LeftBracket"
+                | "RightBracket"
+                | "BackSlash"
+                | "IntlBackslash"
+                | "SemiColon"
+                | "Quote"
+                | "Comma"
+                | "Dot"
+                | "Slash"
+        )
+}
+
+impl ChordTracker {
+    /// 押下を記録し、ショートカットとして数えるべき組み合わせなら表記を返す
+    pub fn press(&mut self, key_code: &str) -> Option<String> {
+        if !self.held.insert(key_code.to_string()) {
+            return None;
+        }
+        if is_modifier(key_code) {
+            return None;
+        }
+        let mut names: Vec<&str> = Vec::new();
+        for (code, name) in MODIFIERS {
+            if self.held.contains(*code) && !names.contains(name) {
+                names.push(name);
+            }
+        }
+        let has_command_modifier = names.iter().any(|n| matches!(*n, "Ctrl" | "Alt" | "Meta"));
+        if names.is_empty() || (!has_command_modifier && is_character_key(key_code)) {
+            return None;
+        }
+        names.push(key_code);
+        Some(names.join("+"))
+    }
+
+    pub fn release(&mut self, key_code: &str) {
+        self.held.remove(key_code);
+    }
+}
+
+/// バッファに溜める集計キーの上限（超えたらフラッシュを待たずに書き込む）
+const MAX_BUFFER_ENTRIES: usize = 10_000;
+
+/// フックで受け取ったイベントをDBに書き込むまで集計しておくバッファ
+/// 同じ (時間, キー, アプリ) の押下は件数にまとめ、書き込みは1行ずつで済ませる
+#[derive(Default)]
+struct EventBuffer {
+    stats: HashMap<KeyStat, i64>,
+    bigrams: HashMap<KeyBigram, i64>,
+    shortcuts: HashMap<ShortcutStat, i64>,
+    dwells: HashMap<KeyDwell, i64>,
+    tap_holds: HashMap<TapHoldKey, TapHoldCounts>,
+    repeats: HashMap<KeyStat, i64>,
+}
+
+/// 集計キーの件数を1つ増やす
+fn count<K: std::hash::Hash + Eq>(map: &mut HashMap<K, i64>, key: K) {
+    *map.entry(key).or_insert(0) += 1;
+}
+
+impl EventBuffer {
+    /// 削除したアプリの集計を捨てる
+    fn discard_app(&mut self, app_id: i64) {
+        self.stats.retain(|stat, _| stat.app_id != app_id);
+        self.bigrams.retain(|bigram, _| bigram.app_id != app_id);
+        self.shortcuts
+            .retain(|shortcut, _| shortcut.app_id != app_id);
+        self.dwells.retain(|dwell, _| dwell.app_id != app_id);
+        self.tap_holds
+            .retain(|tap_hold, _| tap_hold.app_id != app_id);
+        self.repeats.retain(|repeat, _| repeat.app_id != app_id);
+    }
+
+    fn len(&self) -> usize {
+        self.stats.len()
+            + self.bigrams.len()
+            + self.shortcuts.len()
+            + self.dwells.len()
+            + self.tap_holds.len()
+            + self.repeats.len()
+    }
+
+    /// 集計をDBに書き込む（ロックを握ったまま呼ばないよう、取り出したバッファで呼ぶ）
+    fn write(self, db: &crate::db::Database) {
+        if !self.stats.is_empty() {
+            if let Err(e) = db.batch_insert_key_stats(&self.stats) {
+                eprintln!("[KeyFit] Failed to batch insert: {}", e);
+            }
+        }
+        if !self.bigrams.is_empty() {
+            if let Err(e) = db.batch_insert_key_bigrams(&self.bigrams) {
+                eprintln!("[KeyFit] Failed to batch insert bigrams: {}", e);
+            }
+        }
+        if !self.shortcuts.is_empty() {
+            if let Err(e) = db.batch_insert_shortcut_stats(&self.shortcuts) {
+                eprintln!("[KeyFit] Failed to batch insert shortcuts: {}", e);
+            }
+        }
+        if !self.dwells.is_empty() {
+            if let Err(e) = db.batch_insert_key_dwells(&self.dwells) {
+                eprintln!("[KeyFit] Failed to batch insert dwell times: {}", e);
+            }
+        }
+        if !self.repeats.is_empty() {
+            if let Err(e) = db.batch_insert_key_repeats(&self.repeats) {
+                eprintln!("[KeyFit] Failed to batch insert repeats: {}", e);
+            }
+        }
+        if !self.tap_holds.is_empty() {
+            if let Err(e) = db.batch_insert_tap_hold_counts(&self.tap_holds) {
+                eprintln!("[KeyFit] Failed to batch insert tap-hold counts: {}", e);
+            }
+        }
+    }
+}
+
+/// バッファに集計を加える。上限に達したらその場で取り出して書き込む
+fn record(
+    buffer: &Mutex<EventBuffer>,
+    db: &crate::db::Database,
+    update: impl FnOnce(&mut EventBuffer),
+) {
+    let overflow = {
+        let mut buf = buffer.lock().unwrap();
+        update(&mut buf);
+        (buf.len() >= MAX_BUFFER_ENTRIES).then(|| std::mem::take(&mut *buf))
+    };
+    if let Some(pending) = overflow {
+        eprintln!("[KeyFit] Event buffer is full, writing early");
+        pending.write(db);
+    }
+}
+
+/// バッファの中身を取り出してDBに書き込む
+fn flush_buffer(buffer: &Mutex<EventBuffer>, db: &crate::db::Database) {
+    let pending = std::mem::take(&mut *buffer.lock().unwrap());
+    pending.write(db);
+}
+
+/// キー押下の順序からバイグラムを取り出す
+#[derive(Default)]
+pub struct BigramTracker {
+    held: HashSet<String>,
+    last: Option<(String, SystemTime, i64)>,
+}
+
+impl BigramTracker {
+    /// 押下を記録し、直前の押下から `max_gap` 以内なら (prev_key, key_code) を返す
+    /// 押しっぱなしによるリピートやアプリをまたいだ遷移は数えない
+    pub fn press(
+        &mut self,
+        key_code: &str,
+        time: SystemTime,
+        app_id: i64,
+        max_gap: Duration,
+    ) -> Option<(String, String)> {
+        if !self.held.insert(key_code.to_string()) {
+            return None;
+        }
+        let bigram = match &self.last {
+            Some((prev, prev_time, prev_app)) if *prev_app == app_id => time
+                .duration_since(*prev_time)
+                .ok()
+                .filter(|gap| *gap <= max_gap)
+                .map(|_| (prev.clone(), key_code.to_string())),
+            _ => None,
+        };
+        self.last = Some((key_code.to_string(), time, app_id));
+        bigram
+    }
+
+    pub fn release(&mut self, key_code: &str) {
+        self.held.remove(key_code);
+    }
+}
+
+/// X11 のオートリピートが解放と押下の組を送るときの、解放から押下までの最大間隔
+/// （人の指で同じキーを離して押し直すにはこれより時間がかかる）
+const SYNTHETIC_RELEASE_MAX_GAP: Duration = Duration::from_millis(5);
+
+/// X11 のオートリピートが送る解放→押下の組を取り除き、解放を挟まない連続した押下にする
+/// 解放は次のイベントまで保留するので、記録は次の押下（か一時停止・入力の終わり）まで遅れる
+#[derive(Default)]
+struct SyntheticReleaseFilter {
+    pending: Option<InputEvent>,
+}
+
+impl SyntheticReleaseFilter {
+    fn push(&mut self, event: InputEvent, handle: &mut impl FnMut(InputEvent)) {
+        if let (Some(release), InputEventKind::KeyPress(key_code)) = (&self.pending, &event.kind) {
+            let same_key = matches!(
+                &release.kind,
+                InputEventKind::KeyRelease(released) if released == key_code
+            );
+            let is_synthetic = same_key
+                && release.device == event.device
+                && event
+                    .time
+                    .duration_since(release.time)
+                    .is_ok_and(|gap| gap <= SYNTHETIC_RELEASE_MAX_GAP);
+            if is_synthetic {
+                self.pending = None;
+                handle(event);
+                return;
+            }
+        }
+        self.finish(handle);
+        match event.kind {
+            InputEventKind::KeyRelease(_) => self.pending = Some(event),
+            InputEventKind::KeyPress(_) => handle(event),
+        }
+    }
+
+    /// 保留中の解放を処理する
+    fn finish(&mut self, handle: &mut impl FnMut(InputEvent)) {
+        if let Some(release) = self.pending.take() {
+            handle(release);
+        }
+    }
+}
+
+type EventHandler = Box<dyn FnMut(InputEvent) + Send>;
+
+/// 入力元のイベントを記録まで流す（一時停止の前に保留中の解放を流せるよう、フックからも使う）
+struct EventPipeline {
+    /// オートリピートを解放と押下の組で送る入力元（X11）だけで使う
+    filter: Option<SyntheticReleaseFilter>,
+    handle: EventHandler,
+}
+
+impl EventPipeline {
+    fn push(&mut self, event: InputEvent) {
+        match &mut self.filter {
+            Some(filter) => filter.push(event, &mut self.handle),
+            None => (self.handle)(event),
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(filter) = &mut self.filter {
+            filter.finish(&mut self.handle);
+        }
+    }
+}
+
+/// 入力元のキーボードから device_id を引く（DBへの問い合わせは解決スレッドに任せる）
+struct DeviceIds {
+    ids: Arc<RwLock<HashMap<InputDevice, i64>>>,
+    requested: HashSet<InputDevice>,
+    resolver: Sender<InputDevice>,
+}
+
+impl DeviceIds {
+    /// 初めて見るデバイスは解決スレッドに頼み、解決するまでの押下は UNKNOWN_DEVICE_ID に数える
+    fn resolve(&mut self, device: Option<&InputDevice>) -> i64 {
+        let Some(device) = device else {
+            return UNKNOWN_DEVICE_ID;
+        };
+        if let Some(device_id) = self.ids.read().unwrap().get(device) {
+            return *device_id;
+        }
+        if self.requested.insert(device.clone()) {
+            let _ = self.resolver.send(device.clone());
+        }
+        UNKNOWN_DEVICE_ID
+    }
+}
+
+/// 記録済みのデバイスを読み込み、新しいデバイスの行を作るスレッドを起動する
+/// （送信側が閉じたら終わる）
+fn spawn_device_resolver(
+    db: Arc<crate::db::Database>,
+    ids: Arc<RwLock<HashMap<InputDevice, i64>>>,
+) -> Sender<InputDevice> {
+    match db.get_devices() {
+        Ok(devices) => {
+            let mut ids = ids.write().unwrap();
+            for device in devices {
+                let input_device = InputDevice {
+                    name: device.name,
+                    vendor_id: device.vendor_id,
+                    product_id: device.product_id,
+                };
+                ids.insert(input_device, device.id);
+            }
+        }
+        Err(e) => eprintln!("[KeyFit] Failed to load devices: {}", e),
+    }
+    let (sender, receiver) = mpsc::channel::<InputDevice>();
+    thread::spawn(move || {
+        for device in receiver {
+            match db.get_or_create_device(&device) {
+                Ok(device_id) => {
+                    ids.write().unwrap().insert(device, device_id);
+                }
+                Err(e) => eprintln!("[KeyFit] Failed to get/create device: {}", e),
+            }
+        }
+    });
+    sender
+}
+
+/// フックのコールバックにかかった時間の統計（マイクロ秒）
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct HookLatency {
+    pub events: u64,
+    pub avg_us: u64,
+    pub max_us: u64,
+}
+
+#[derive(Default)]
+struct LatencyCounter {
+    events: AtomicU64,
+    total_us: AtomicU64,
+    max_us: AtomicU64,
+}
+
+impl LatencyCounter {
+    fn record(&self, elapsed: Duration) {
+        let us = elapsed.as_micros() as u64;
+        self.events.fetch_add(1, Ordering::Relaxed);
+        self.total_us.fetch_add(us, Ordering::Relaxed);
+        self.max_us.fetch_max(us, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> HookLatency {
+        let events = self.events.load(Ordering::Relaxed);
+        let total_us = self.total_us.load(Ordering::Relaxed);
+        HookLatency {
+            events,
+            avg_us: total_us.checked_div(events).unwrap_or(0),
+            max_us: self.max_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// 監視の状態
+/// rdevのlistenは抜けられないため、開始後の停止はイベントを捨てる一時停止（Paused）になる
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MonitoringState {
+    #[default]
+    Stopped,
+    Starting,
+    Running,
+    Paused,
+    Failed,
+}
+
+type StateListener = Box<dyn Fn(MonitoringState) + Send + Sync>;
+type ErrorListener = Box<dyn Fn(&str) + Send + Sync>;
+
+/// 同じエラーを続けて通知しない間隔（前面アプリの監視は失敗し続けることがある）
+const ERROR_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 現在の状態と遷移の通知先（状態の変化はここからだけ通知する）
+#[derive(Default)]
+struct Lifecycle {
+    state: Mutex<MonitoringState>,
+    listener: RwLock<Option<StateListener>>,
+    error_listener: RwLock<Option<ErrorListener>>,
+    last_error: Mutex<Option<(String, Instant)>>,
+}
+
+impl Lifecycle {
+    fn get(&self) -> MonitoringState {
+        *self.state.lock().unwrap()
+    }
+
+    fn set(&self, next: MonitoringState) {
+        let previous = std::mem::replace(&mut *self.state.lock().unwrap(), next);
+        if previous == next {
+            return;
+        }
+        eprintln!("[KeyFit] Monitoring state: {:?} -> {:?}", previous, next);
+        if let Some(listener) = self.listener.read().unwrap().as_ref() {
+            listener(next);
+        }
+    }
+
+    fn report_error(&self, message: &str) {
+        {
+            let mut last_error = self.last_error.lock().unwrap();
+            if let Some((last, reported_at)) = last_error.as_ref() {
+                if last == message && reported_at.elapsed() < ERROR_REPORT_INTERVAL {
+                    return;
+                }
+            }
+            *last_error = Some((message.to_string(), Instant::now()));
+        }
+        eprintln!("[KeyFit] {}", message);
+        if let Some(listener) = self.error_listener.read().unwrap().as_ref() {
+            listener(message);
+        }
+    }
 }
 
 pub struct KeyboardHook {
     running: Arc<AtomicBool>,
+    listening: Arc<AtomicBool>,
+    lifecycle: Arc<Lifecycle>,
+    control: Mutex<()>,
+    source: Arc<dyn EventSource>,
+    app_provider: Arc<dyn ActiveAppProvider>,
     db: Arc<crate::db::Database>,
-    buffer: Arc<Mutex<Vec<KeyStat>>>,
+    buffer: Arc<Mutex<EventBuffer>>,
+    bigram_enabled: Arc<AtomicBool>,
+    bigram_max_gap_ms: Arc<AtomicU64>,
+    exclusions: Arc<RwLock<ExclusionSet>>,
+    foreground: Arc<Mutex<ForegroundTracker>>,
+    active_app: Arc<RwLock<Option<ActiveApp>>>,
+    devices: Arc<RwLock<HashMap<InputDevice, i64>>>,
+    latency: Arc<LatencyCounter>,
+    pipeline: Arc<Mutex<Option<EventPipeline>>>,
     worker: Arc<Mutex<Option<JoinHandle<()>>>>,
     flush_worker: Arc<Mutex<Option<JoinHandle<()>>>>,
 }
 
 impl KeyboardHook {
+    /// 入力元と前面アプリの取得方法は呼び出し側で選ぶ（ここではデバイスやソケットを開かない）
     #[allow(dead_code)]
-    pub fn new(db: Arc<crate::db::Database>) -> Self {
+    pub fn new(
+        db: Arc<crate::db::Database>,
+        source: Arc<dyn EventSource>,
+        app_provider: Arc<dyn ActiveAppProvider>,
+    ) -> Self {
+        let exclusions = db.load_exclusion_set().unwrap_or_else(|e| {
+            eprintln!("[KeyFit] Failed to load exclusions: {}", e);
+            ExclusionSet::default()
+        });
         Self {
             running: Arc::new(AtomicBool::new(false)),
+            listening: Arc::new(AtomicBool::new(false)),
+            lifecycle: Arc::new(Lifecycle::default()),
+            control: Mutex::new(()),
+            source,
+            app_provider,
             db,
-            buffer: Arc::new(Mutex::new(Vec::new())),
+            buffer: Arc::new(Mutex::new(EventBuffer::default())),
+            bigram_enabled: Arc::new(AtomicBool::new(false)),
+            bigram_max_gap_ms: Arc::new(AtomicU64::new(DEFAULT_BIGRAM_MAX_GAP_MS)),
+            exclusions: Arc::new(RwLock::new(exclusions)),
+            foreground: Arc::new(Mutex::new(ForegroundTracker::default())),
+            active_app: Arc::new(RwLock::new(None)),
+            devices: Arc::new(RwLock::new(HashMap::new())),
+            latency: Arc::new(LatencyCounter::default()),
+            pipeline: Arc::new(Mutex::new(None)),
             worker: Arc::new(Mutex::new(None)),
             flush_worker: Arc::new(Mutex::new(None)),
         }
     }
 
-    pub fn start(&self, app: &AppHandle) -> Result<()> {
-        // すでにスレッドが存在する場合は何もしない（runningフラグだけON）
-        if self.worker.lock().unwrap().is_some() {
-            self.running.store(true, Ordering::SeqCst);
+    /// 監視を開始する（一時停止中なら再開する）
+    pub fn start(&self) -> Result<()> {
+        let _control = self.control.lock().unwrap();
+        if matches!(
+            self.lifecycle.get(),
+            MonitoringState::Starting | MonitoringState::Running
+        ) {
             return Ok(());
         }
-        // macOS accessibility permission check
-        #[cfg(target_os = "macos")]
-        {
-            if !check_accessibility_permission() {
-                return Err(anyhow::anyhow!("Insufficient macOS permissions. Please follow the instructions above to set permissions."));
+        self.lifecycle.set(MonitoringState::Starting);
+        let needs_listener = !self.listening.load(Ordering::SeqCst);
+        if needs_listener {
+            if let Err(e) = self.source.prepare() {
+                self.lifecycle.set(MonitoringState::Failed);
+                return Err(e);
             }
         }
-        println!("Starting keyboard hook...");
         self.running.store(true, Ordering::SeqCst);
-        let running = self.running.clone();
-        let db = self.db.clone();
-        let buffer = self.buffer.clone();
-        let app_handle = app.clone();
+        self.spawn_flush_worker();
+        self.lifecycle.set(MonitoringState::Running);
+        if needs_listener {
+            self.spawn_listener();
+        }
+        Ok(())
+    }
 
-        // バッファflush用スレッド
-        let running_flush = running.clone();
-        let db_flush = db.clone();
-        let buffer_flush = buffer.clone();
-        let flush_handle = thread::spawn(move || {
+    /// 実行中の間、定期的にバッファを書き込むスレッド（再開のたびに起動し直す）
+    fn spawn_flush_worker(&self) {
+        let mut flush_worker = self.flush_worker.lock().unwrap();
+        // 失敗で抜けた前回のスレッドが残っていれば片付ける
+        if let Some(previous) = flush_worker.take() {
+            let _ = previous.join();
+        }
+        let running_flush = self.running.clone();
+        let db_flush = self.db.clone();
+        let buffer_flush = self.buffer.clone();
+        *flush_worker = Some(thread::spawn(move || {
             while running_flush.load(Ordering::SeqCst) {
                 for _ in 0..5 {
                     if !running_flush.load(Ordering::SeqCst) {
@@ -69,201 +638,966 @@ impl KeyboardHook {
                     }
                     thread::sleep(Duration::from_secs(1));
                 }
-                let mut buf = buffer_flush.lock().unwrap();
-                if !buf.is_empty() {
-                    if let Err(e) = db_flush.batch_insert_key_stats(&buf) {
-                        eprintln!("[KeyFit] Failed to batch insert: {}", e);
+                flush_buffer(&buffer_flush, &db_flush);
+            }
+        }));
+    }
+
+    /// キーフックと前面アプリ監視のスレッドを起動する
+    fn spawn_listener(&self) {
+        println!("Starting keyboard hook...");
+        self.listening.store(true, Ordering::SeqCst);
+        let running = self.running.clone();
+        let db = self.db.clone();
+        let buffer = self.buffer.clone();
+
+        // 最初のイベントから記録できるよう、前面アプリは先に一度引いておく
+        if let Err(e) =
+            refresh_active_app(&db, &*self.app_provider, &self.foreground, &self.active_app)
+        {
+            self.lifecycle
+                .report_error(&format!("Failed to get/create app: {}", e));
+        }
+
+        // 前面アプリの監視（切り替わったときだけDBで app_id を引く）
+        let running_watch = running.clone();
+        let db_watch = db.clone();
+        let app_provider = self.app_provider.clone();
+        let foreground = self.foreground.clone();
+        let active_app_watch = self.active_app.clone();
+        let listening_watch = self.listening.clone();
+        let lifecycle_watch = self.lifecycle.clone();
+        thread::spawn(move || {
+            while listening_watch.load(Ordering::SeqCst) {
+                if running_watch.load(Ordering::SeqCst) {
+                    if let Err(e) = refresh_active_app(
+                        &db_watch,
+                        &*app_provider,
+                        &foreground,
+                        &active_app_watch,
+                    ) {
+                        lifecycle_watch.report_error(&format!("Failed to get/create app: {}", e));
                     }
-                    buf.clear();
                 }
+                thread::sleep(FOREGROUND_POLL_INTERVAL);
             }
         });
-        *self.flush_worker.lock().unwrap() = Some(flush_handle);
 
         // キーフック本体
         let buffer_key = buffer.clone();
+        let bigram_enabled = self.bigram_enabled.clone();
+        let bigram_max_gap_ms = self.bigram_max_gap_ms.clone();
+        let exclusions = self.exclusions.clone();
+        let active_app = self.active_app.clone();
+        let latency = self.latency.clone();
         let db_key = db.clone();
         let running_key = running.clone();
-        let app_handle_key = app_handle.clone();
+        let running_exit = running.clone();
+        let listening_key = self.listening.clone();
+        let lifecycle = self.lifecycle.clone();
+        let source = self.source.clone();
+        let pipeline = self.pipeline.clone();
+        // デバイスの行もアプリと同じくホットパスの外で作る
+        let devices = self.devices.clone();
+        let device_resolver = spawn_device_resolver(db.clone(), devices.clone());
         let handle = thread::spawn(move || {
-            let callback = move |event: Event| {
+            let mut bigram_tracker = BigramTracker::default();
+            let mut chord_tracker = ChordTracker::default();
+            let mut dwell_tracker = DwellTracker::default();
+            let mut tap_hold_tracker = TapHoldTracker::default();
+            let mut held_keys: HashSet<String> = HashSet::new();
+            let mut device_ids = DeviceIds {
+                ids: devices,
+                requested: HashSet::new(),
+                resolver: device_resolver,
+            };
+            // ホットパスではOSやDBに問い合わせず、監視スレッドが更新した前面アプリを使う
+            // 一時停止中は記録しない（キーの押下状態は追い続ける）
+            let resolve_app_id = move || -> Option<i64> {
                 if !running_key.load(Ordering::SeqCst) {
-                    return;
+                    return None;
+                }
+                let app = active_app.read().unwrap().clone()?;
+                // 除外アプリのイベントはバッファに入れない
+                if exclusions
+                    .read()
+                    .unwrap()
+                    .is_excluded(app.app_id, &app.bundle_id)
+                {
+                    return None;
                 }
-                if let EventType::KeyRelease(key) = event.event_type {
-                    let now = Local::now().date_naive();
-                    let ts_day = now
-                        .and_hms_opt(0, 0, 0)
-                        .unwrap()
-                        .and_local_timezone(Local)
-                        .unwrap()
-                        .timestamp();
-                    let key_code = format!("{:?}", key);
-                    if let Some((app_name, bundle_id)) = get_active_app_info() {
-                        match db_key.get_or_create_app(&app_name, &bundle_id) {
-                            Ok(app_id) => {
-                                let mut buf = buffer_key.lock().unwrap();
-                                buf.push(KeyStat {
-                                    ts_day,
-                                    key_code: key_code.clone(),
-                                    app_id,
+                Some(app.app_id)
+            };
+            let handle_event = move |event: InputEvent| {
+                // 時間の区切りはイベントの時刻で決める
+                let (ts_day, ts_hour) = local_time_buckets(event.time);
+                match event.kind {
+                    InputEventKind::KeyPress(key_code) => {
+                        // 解放を挟まない連続した押下はOSのオートリピート
+                        if !held_keys.insert(key_code.clone()) {
+                            if let Some(app_id) = resolve_app_id() {
+                                let device_id = device_ids.resolve(event.device.as_deref());
+                                record(&buffer_key, &db_key, |buf| {
+                                    count(
+                                        &mut buf.repeats,
+                                        KeyStat {
+                                            ts_day,
+                                            ts_hour,
+                                            key_code,
+                                            app_id,
+                                            device_id,
+                                        },
+                                    )
                                 });
-                                println!(
-                                    "KeyStat: {:?} app_id: {} bundle_id: {}",
-                                    key_code, app_id, bundle_id
-                                );
                             }
-                            Err(e) => {
-                                dialog::show_error(
-                                    &app_handle_key,
-                                    &format!("Failed to get/create app: {}", e),
-                                    Some("KeyFit Error"),
+                            return;
+                        }
+                        dwell_tracker.press(&key_code, event.time);
+                        tap_hold_tracker.press(&key_code, event.time);
+                        let shortcut = chord_tracker.press(&key_code);
+                        // バイグラムは押下順で数える（オプトイン）
+                        let bigram_on = bigram_enabled.load(Ordering::SeqCst);
+                        if shortcut.is_none() && !bigram_on {
+                            return;
+                        }
+                        let Some(app_id) = resolve_app_id() else {
+                            return;
+                        };
+                        let device_id = device_ids.resolve(event.device.as_deref());
+                        record(&buffer_key, &db_key, |buf| {
+                            if let Some(shortcut) = shortcut {
+                                count(
+                                    &mut buf.shortcuts,
+                                    ShortcutStat {
+                                        ts_day,
+                                        ts_hour,
+                                        shortcut,
+                                        app_id,
+                                        device_id,
+                                    },
                                 );
                             }
+                            if bigram_on {
+                                let max_gap =
+                                    Duration::from_millis(bigram_max_gap_ms.load(Ordering::SeqCst));
+                                if let Some((prev_key, key_code)) =
+                                    bigram_tracker.press(&key_code, event.time, app_id, max_gap)
+                                {
+                                    count(
+                                        &mut buf.bigrams,
+                                        KeyBigram {
+                                            ts_day,
+                                            ts_hour,
+                                            prev_key,
+                                            key_code,
+                                            app_id,
+                                            device_id,
+                                        },
+                                    );
+                                }
+                            }
+                        });
+                    }
+                    InputEventKind::KeyRelease(key_code) => {
+                        held_keys.remove(&key_code);
+                        bigram_tracker.release(&key_code);
+                        chord_tracker.release(&key_code);
+                        let dwell = dwell_tracker.release(&key_code, event.time);
+                        let tap_hold = tap_hold_tracker.release(&key_code, event.time);
+                        if let Some(app_id) = resolve_app_id() {
+                            let device_id = device_ids.resolve(event.device.as_deref());
+                            record(&buffer_key, &db_key, |buf| {
+                                if let Some(dwell) = dwell {
+                                    count(
+                                        &mut buf.dwells,
+                                        KeyDwell {
+                                            ts_day,
+                                            ts_hour,
+                                            key_code: key_code.clone(),
+                                            app_id,
+                                            device_id,
+                                            bucket_ms: dwell_bucket(dwell),
+                                        },
+                                    );
+                                }
+                                if let Some((dwell, overlapped)) = tap_hold {
+                                    buf.tap_holds
+                                        .entry(TapHoldKey {
+                                            ts_day,
+                                            ts_hour,
+                                            key_code: key_code.clone(),
+                                            app_id,
+                                            device_id,
+                                        })
+                                        .or_default()
+                                        .add(dwell.as_millis() as i64, overlapped);
+                                }
+                                count(
+                                    &mut buf.stats,
+                                    KeyStat {
+                                        ts_day,
+                                        ts_hour,
+                                        key_code: key_code.clone(),
+                                        app_id,
+                                        device_id,
+                                    },
+                                );
+                            });
                         }
                     }
                 }
             };
-            if let Err(e) = rdev::listen(callback) {
-                eprintln!("Keyboard hook error: {:?}", e);
+            *pipeline.lock().unwrap() = Some(EventPipeline {
+                filter: source
+                    .repeats_as_release_press()
+                    .then(SyntheticReleaseFilter::default),
+                handle: Box::new(handle_event),
+            });
+            let pipeline_key = pipeline.clone();
+            let callback = move |event: InputEvent| {
+                let started = Instant::now();
+                if let Some(pipeline) = pipeline_key.lock().unwrap().as_mut() {
+                    pipeline.push(event);
+                }
+                latency.record(started.elapsed());
+            };
+            let result = source.listen(Box::new(callback));
+            if let Some(mut pipeline) = pipeline.lock().unwrap().take() {
+                pipeline.finish();
+            }
+            running_exit.store(false, Ordering::SeqCst);
+            listening_key.store(false, Ordering::SeqCst);
+            match result {
+                // 入力元が終わった（rdevでは戻らない）
+                Ok(()) => lifecycle.set(MonitoringState::Stopped),
+                Err(e) => {
+                    lifecycle.report_error(&e.to_string());
+                    lifecycle.set(MonitoringState::Failed);
+                }
             }
         });
         *self.worker.lock().unwrap() = Some(handle);
         println!("Keyboard hook started");
-        Ok(())
     }
 
+    /// 監視を一時停止する（バッファは書き込んでから止める）
+    pub fn pause(&self) {
+        let _control = self.control.lock().unwrap();
+        if self.lifecycle.get() == MonitoringState::Running {
+            self.halt(MonitoringState::Paused);
+        }
+    }
+
+    /// アプリ終了時に監視を止める
     pub fn stop(&self) {
-        println!("Keyboard hook stopped");
+        let _control = self.control.lock().unwrap();
+        self.halt(MonitoringState::Stopped);
+    }
+
+    fn halt(&self, next: MonitoringState) {
+        // 保留中の解放は止める前の押下として記録する
+        if let Some(pipeline) = self.pipeline.lock().unwrap().as_mut() {
+            pipeline.finish();
+        }
         self.running.store(false, Ordering::SeqCst);
-        self.flush();
         // workerスレッドはjoinしない（rdev::listenは抜けないため）
-        // スレッドは生かしたまま
         if let Some(flush_handle) = self.flush_worker.lock().unwrap().take() {
             let _ = flush_handle.join();
         }
+        self.flush();
+        self.lifecycle.set(next);
+        println!("Keyboard hook stopped");
+    }
+
+    pub fn state(&self) -> MonitoringState {
+        self.lifecycle.get()
+    }
+
+    /// 状態が変わるたびに呼ばれる通知先を設定する
+    pub fn on_state_change(&self, listener: impl Fn(MonitoringState) + Send + Sync + 'static) {
+        *self.lifecycle.listener.write().unwrap() = Some(Box::new(listener));
+    }
+
+    /// 監視中のエラーの通知先を設定する
+    pub fn on_error(&self, listener: impl Fn(&str) + Send + Sync + 'static) {
+        *self.lifecycle.error_listener.write().unwrap() = Some(Box::new(listener));
     }
 
     pub fn is_running(&self) -> bool {
-        self.running.load(Ordering::SeqCst)
+        self.state() == MonitoringState::Running
     }
 
-    pub fn toggle(&self, _app: &AppHandle) -> bool {
+    /// 実行中なら一時停止し、それ以外なら開始する
+    pub fn toggle(&self) -> Result<MonitoringState> {
         if self.is_running() {
-            self.stop();
+            self.pause();
         } else {
-            self.running.store(true, Ordering::SeqCst);
+            self.start()?;
         }
-        self.is_running()
+        Ok(self.state())
     }
 
     pub fn flush(&self) {
-        let mut buf = self.buffer.lock().unwrap();
-        if !buf.is_empty() {
-            if let Err(e) = self.db.batch_insert_key_stats(&buf) {
-                eprintln!("[KeyFit] Failed to batch insert (flush): {}", e);
-            }
-            buf.clear();
+        flush_buffer(&self.buffer, &self.db);
+    }
+
+    /// バイグラム記録の有効/無効と、連続とみなす最大間隔を設定する
+    pub fn set_bigram_capture(&self, capture: BigramCapture) {
+        self.bigram_max_gap_ms
+            .store(capture.max_gap_ms, Ordering::SeqCst);
+        self.bigram_enabled.store(capture.enabled, Ordering::SeqCst);
+    }
+
+    pub fn bigram_capture(&self) -> BigramCapture {
+        BigramCapture {
+            enabled: self.bigram_enabled.load(Ordering::SeqCst),
+            max_gap_ms: self.bigram_max_gap_ms.load(Ordering::SeqCst),
         }
     }
-}
 
-impl Drop for KeyboardHook {
-    fn drop(&mut self) {
+    /// コールバックにかかった時間の統計
+    pub fn latency(&self) -> HookLatency {
+        self.latency.snapshot()
+    }
+
+    /// アプリの履歴を消す `purge` の間、前面アプリの記録を止める
+    /// 消す前の押下は先に書き込んで消す対象に含め、アプリの行を消したら覚えている app_id も捨てる
+    pub fn purge_app(
+        &self,
+        app_id: i64,
+        purge: impl FnOnce() -> Result<PurgeReport>,
+    ) -> Result<PurgeReport> {
+        let result = {
+            // 終わるまで監視スレッドに app_id を引き直させない
+            let mut tracker = self.foreground.lock().unwrap();
+            *self.active_app.write().unwrap() = None;
+            self.flush();
+            let result = purge();
+            if result.as_ref().is_ok_and(|report| report.app_removed) {
+                // 止める直前に引いた app_id で記録された分は書き込まない
+                self.buffer.lock().unwrap().discard_app(app_id);
+            }
+            tracker.invalidate();
+            result
+        };
         self.flush();
+        if self.listening.load(Ordering::SeqCst) {
+            if let Err(e) = refresh_active_app(
+                &self.db,
+                &*self.app_provider,
+                &self.foreground,
+                &self.active_app,
+            ) {
+                self.lifecycle
+                    .report_error(&format!("Failed to get/create app: {}", e));
+            }
+        }
+        result
+    }
+
+    /// excluded_app / excluded_pattern の変更後に除外設定を読み直す
+    pub fn reload_exclusions(&self) -> Result<()> {
+        let exclusions = self.db.load_exclusion_set()?;
+        *self.exclusions.write().unwrap() = exclusions;
+        Ok(())
     }
 }
 
-#[cfg(target_os = "macos")]
-fn check_accessibility_permission() -> bool {
-    use std::process::Command;
-    // Check Input Monitoring permission
-    let input_monitoring_check = Command::new("osascript")
-        .arg("-e")
-        .arg("tell application \"System Events\" to keystroke \"\"")
-        .output();
-    let has_input_monitoring = match input_monitoring_check {
-        Ok(result) => result.status.success(),
-        Err(e) => {
-            eprintln!("Error checking Input Monitoring permission: {}", e);
-            false
-        }
-    };
-    // Check Accessibility permission
-    let accessibility_check = Command::new("osascript")
-        .arg("-e")
-        .arg("tell application \"System Events\" to get name of first process")
-        .output();
-    let has_accessibility = match accessibility_check {
-        Ok(result) => result.status.success(),
+/// 前面アプリを問い合わせ、変わっていれば記録先を更新する
+fn refresh_active_app(
+    db: &crate::db::Database,
+    app_provider: &dyn ActiveAppProvider,
+    foreground: &Mutex<ForegroundTracker>,
+    active_app: &RwLock<Option<ActiveApp>>,
+) -> Result<()> {
+    let mut tracker = foreground.lock().unwrap();
+    match tracker.update(db, app_provider.active_app()) {
+        Ok(true) => *active_app.write().unwrap() = tracker.current(),
+        Ok(false) => {}
         Err(e) => {
-            eprintln!("Error checking Accessibility permission: {}", e);
-            false
-        }
-    };
-    if !has_input_monitoring || !has_accessibility {
-        eprintln!("=== macOS permissions required ===");
-        eprintln!("Go to System Preferences > Security & Privacy > Privacy and set the following:");
-        if !has_accessibility {
-            eprintln!("1. Accessibility: Add and enable the KeyFit app");
-        }
-        if !has_input_monitoring {
-            eprintln!("2. Input Monitoring: Add and enable the KeyFit app");
+            *active_app.write().unwrap() = None;
+            return Err(e);
         }
-        eprintln!("");
-        eprintln!("Important: On macOS Mojave or later, 'Input Monitoring' permission is required to monitor keyboard events. Without this permission, the rdev library cannot receive keyboard events.");
-        eprintln!("");
-        eprintln!("After setting, please restart the app.");
-        eprintln!("=== End of permission instructions ===");
-        return false;
     }
-    true
+    Ok(())
 }
 
-#[cfg(not(target_os = "macos"))]
-#[allow(dead_code)]
-fn check_accessibility_permission() -> bool {
-    true
+/// 時刻を含むローカルの日と時間の開始時刻 (ts_day, ts_hour)
+fn local_time_buckets(time: SystemTime) -> (i64, i64) {
+    let local: DateTime<Local> = time.into();
+    let ts_day = local
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_local_timezone(Local)
+        .unwrap()
+        .timestamp();
+    let ts_hour = local.timestamp() - i64::from(local.minute() * 60 + local.second());
+    (ts_day, ts_hour)
+}
+
+impl Drop for KeyboardHook {
+    fn drop(&mut self) {
+        self.flush();
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::appinfo::{ActiveAppInfo, FixedAppProvider};
+    use crate::db::{PurgeMode, StatFilter};
+    use crate::input::SyntheticSource;
+    use crate::testutil::wait_until;
+    use std::sync::mpsc::Sender;
     use tempfile::NamedTempFile;
 
-    fn setup_test_hook() -> (KeyboardHook, NamedTempFile) {
+    /// 合成イベントを流すフック（送信側を破棄すると入力が終わる）
+    fn setup_test_hook() -> (KeyboardHook, Sender<InputEvent>, NamedTempFile) {
         let temp_file = NamedTempFile::new().unwrap();
         let db = Arc::new(crate::db::Database::new(temp_file.path()).unwrap());
-        let hook = KeyboardHook::new(db);
-        (hook, temp_file)
+        let (source, sender) = SyntheticSource::new();
+        let hook = KeyboardHook::new(
+            db,
+            Arc::new(source),
+            Arc::new(FixedAppProvider(Some(ActiveAppInfo::new(
+                "Test App",
+                "com.test.app",
+            )))),
+        );
+        (hook, sender, temp_file)
+    }
+
+    /// オートリピートを解放と押下の組で送る入力元（X11）
+    fn setup_x11_test_hook() -> (KeyboardHook, Sender<InputEvent>, NamedTempFile) {
+        let temp_file = NamedTempFile::new().unwrap();
+        let db = Arc::new(crate::db::Database::new(temp_file.path()).unwrap());
+        let (source, sender) = SyntheticSource::new();
+        let hook = KeyboardHook::new(
+            db,
+            Arc::new(source.with_x11_repeats()),
+            Arc::new(FixedAppProvider(Some(ActiveAppInfo::new(
+                "Test App",
+                "com.test.app",
+            )))),
+        );
+        (hook, sender, temp_file)
     }
 
     #[test]
     fn test_keyboard_hook_start_stop() {
-        let (hook, _temp_file) = setup_test_hook();
+        let (hook, _sender, _temp_file) = setup_test_hook();
 
         // 初期状態は停止中
         assert!(!hook.running.load(Ordering::SeqCst));
+        assert_eq!(hook.state(), MonitoringState::Stopped);
 
         // 開始
-        // hook.start().unwrap();
+        hook.start().unwrap();
         assert!(hook.running.load(Ordering::SeqCst));
+        assert_eq!(hook.state(), MonitoringState::Running);
+
+        // 一時停止と再開（入力元は動かしたまま、flushスレッドは起動し直す）
+        assert_eq!(hook.toggle().unwrap(), MonitoringState::Paused);
+        assert!(hook.flush_worker.lock().unwrap().is_none());
+        assert_eq!(hook.toggle().unwrap(), MonitoringState::Running);
+        assert!(hook.flush_worker.lock().unwrap().is_some());
 
         // 停止
         hook.stop();
         assert!(!hook.running.load(Ordering::SeqCst));
+        assert_eq!(hook.state(), MonitoringState::Stopped);
+    }
+
+    #[test]
+    fn test_keyboard_hook_pipeline() {
+        let (hook, sender, _temp_file) = setup_test_hook();
+        hook.start().unwrap();
+
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let ms = Duration::from_millis;
+        for event in [
+            InputEvent::press("KeyA", t0),
+            // 押しっぱなしのリピート
+            InputEvent::press("KeyA", t0 + ms(500)),
+            InputEvent::release("KeyA", t0 + ms(600)),
+            InputEvent::press("KeyB", t0 + ms(700)),
+            InputEvent::release("KeyB", t0 + ms(760)),
+        ] {
+            sender.send(event).unwrap();
+        }
+        // 入力元が終わると停止する
+        drop(sender);
+        wait_until("input to end", || hook.state() == MonitoringState::Stopped);
+        hook.flush();
+
+        let ranking = hook
+            .db
+            .get_key_ranking(&StatFilter::default(), None, false)
+            .unwrap();
+        assert_eq!(ranking.len(), 2);
+        assert!(ranking.iter().all(|item| item.count == 1));
+        let with_repeats = hook
+            .db
+            .get_total_key_count(&StatFilter::default(), true)
+            .unwrap();
+        assert_eq!(with_repeats, 3);
+
+        // イベントの時刻で日ごとに集計される
+        let (ts_day, _) = local_time_buckets(t0);
+        let by_day = hook
+            .db
+            .get_key_stats_by_day(&StatFilter::new(Some(ts_day), Some(ts_day), None))
+            .unwrap();
+        assert_eq!(by_day.len(), 2);
+        assert!(by_day.iter().all(|stat| stat.ts_day == ts_day));
+
+        let dwell = hook
+            .db
+            .get_key_dwell_histogram(&StatFilter::default(), Some("KeyA".to_string()))
+            .unwrap();
+        assert_eq!(dwell[0].bucket_ms, 500);
+        assert_eq!(hook.latency().events, 5);
+    }
+
+    #[test]
+    fn test_x11_auto_repeat() {
+        let (hook, sender, _temp_file) = setup_x11_test_hook();
+        hook.start().unwrap();
+
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let ms = Duration::from_millis;
+        for event in [
+            InputEvent::press("KeyA", t0),
+            // X11 のオートリピートは解放と押下の組で届く
+            InputEvent::release("KeyA", t0 + ms(500)),
+            InputEvent::press("KeyA", t0 + ms(500)),
+            InputEvent::release("KeyA", t0 + ms(533)),
+            InputEvent::press("KeyA", t0 + ms(534)),
+            InputEvent::release("KeyA", t0 + ms(600)),
+            // 指で押し直した場合は別の押下として数える
+            InputEvent::press("KeyA", t0 + ms(650)),
+            InputEvent::release("KeyA", t0 + ms(700)),
+        ] {
+            sender.send(event).unwrap();
+        }
+        drop(sender);
+        wait_until("input to end", || hook.state() == MonitoringState::Stopped);
+        hook.flush();
+
+        let total = |include_repeats| {
+            hook.db
+                .get_total_key_count(&StatFilter::default(), include_repeats)
+                .unwrap()
+        };
+        assert_eq!(total(false), 2);
+        assert_eq!(total(true), 4);
+        let dwell = hook
+            .db
+            .get_key_dwell_histogram(&StatFilter::default(), Some("KeyA".to_string()))
+            .unwrap();
+        // 押しっぱなしの間は解放していないので、押下時間は最初の押下から数える
+        let buckets: Vec<i64> = dwell.iter().map(|item| item.bucket_ms).collect();
+        assert_eq!(buckets, vec![50, 500]);
+    }
+
+    #[test]
+    fn test_key_held_across_pause() {
+        let (hook, sender, _temp_file) = setup_test_hook();
+        hook.start().unwrap();
+
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let ms = Duration::from_millis;
+        sender.send(InputEvent::press("KeyA", t0)).unwrap();
+        wait_until("press to arrive", || hook.latency().events == 1);
+        hook.pause();
+        // 一時停止中の解放は記録しないが、押下状態からは外す
+        sender
+            .send(InputEvent::release("KeyA", t0 + ms(50)))
+            .unwrap();
+        wait_until("release to arrive", || hook.latency().events == 2);
+        hook.start().unwrap();
+        sender
+            .send(InputEvent::press("KeyA", t0 + ms(1000)))
+            .unwrap();
+        sender
+            .send(InputEvent::release("KeyA", t0 + ms(1050)))
+            .unwrap();
+        drop(sender);
+        wait_until("input to end", || hook.state() == MonitoringState::Stopped);
+        hook.flush();
+
+        let total = |include_repeats| {
+            hook.db
+                .get_total_key_count(&StatFilter::default(), include_repeats)
+                .unwrap()
+        };
+        assert_eq!(total(false), 1);
+        assert_eq!(total(true), 1);
+        let dwell = hook
+            .db
+            .get_key_dwell_histogram(&StatFilter::default(), Some("KeyA".to_string()))
+            .unwrap();
+        assert_eq!(dwell.len(), 1);
+        assert_eq!(dwell[0].bucket_ms, 50);
+    }
+
+    #[test]
+    fn test_pause_after_x11_release() {
+        let (hook, sender, _temp_file) = setup_x11_test_hook();
+        hook.start().unwrap();
+
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let ms = Duration::from_millis;
+        sender.send(InputEvent::press("KeyA", t0)).unwrap();
+        sender
+            .send(InputEvent::release("KeyA", t0 + ms(50)))
+            .unwrap();
+        wait_until("release to arrive", || hook.latency().events == 2);
+        // 解放の直後に一時停止しても、解放は一時停止の前に記録する
+        hook.pause();
+        assert_eq!(
+            hook.db
+                .get_total_key_count(&StatFilter::default(), false)
+                .unwrap(),
+            1
+        );
+
+        hook.start().unwrap();
+        sender
+            .send(InputEvent::press("KeyA", t0 + ms(1000)))
+            .unwrap();
+        sender
+            .send(InputEvent::release("KeyA", t0 + ms(1050)))
+            .unwrap();
+        drop(sender);
+        wait_until("input to end", || hook.state() == MonitoringState::Stopped);
+        hook.flush();
+
+        // 再開後の押下はオートリピートではない
+        let total = |include_repeats| {
+            hook.db
+                .get_total_key_count(&StatFilter::default(), include_repeats)
+                .unwrap()
+        };
+        assert_eq!(total(false), 2);
+        assert_eq!(total(true), 2);
+        let dwell = hook
+            .db
+            .get_key_dwell_histogram(&StatFilter::default(), Some("KeyA".to_string()))
+            .unwrap();
+        assert_eq!(dwell.len(), 1);
+        assert_eq!(dwell[0].count, 2);
+    }
+
+    #[test]
+    fn test_device_attribution() {
+        let (hook, sender, _temp_file) = setup_test_hook();
+        let keyboard = |name: &str, product_id| {
+            Arc::new(InputDevice {
+                name: name.to_string(),
+                vendor_id: Some(0x1234),
+                product_id: Some(product_id),
+            })
+        };
+        let laptop = keyboard("Laptop Keyboard", 1);
+        let sixty = keyboard("60% Keyboard", 2);
+        // 記録済みのデバイスは開始時に読み込む
+        hook.db.get_or_create_device(&laptop).unwrap();
+        hook.start().unwrap();
+
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let ms = Duration::from_millis;
+        // 初めて見るデバイスは解決するまで UNKNOWN_DEVICE_ID に数える
+        for event in [
+            InputEvent::press("KeyZ", t0).from_device(&sixty),
+            InputEvent::release("KeyZ", t0 + ms(10)).from_device(&sixty),
+        ] {
+            sender.send(event).unwrap();
+        }
+        wait_until("device to be resolved", || {
+            hook.devices.read().unwrap().contains_key(&sixty)
+        });
+        for event in [
+            InputEvent::press("KeyA", t0 + ms(20)).from_device(&laptop),
+            InputEvent::release("KeyA", t0 + ms(50)).from_device(&laptop),
+            InputEvent::press("KeyA", t0 + ms(100)).from_device(&sixty),
+            InputEvent::release("KeyA", t0 + ms(150)).from_device(&sixty),
+            InputEvent::press("KeyB", t0 + ms(200)).from_device(&sixty),
+            InputEvent::release("KeyB", t0 + ms(250)).from_device(&sixty),
+            // デバイスを区別できない入力元
+            InputEvent::press("KeyC", t0 + ms(300)),
+            InputEvent::release("KeyC", t0 + ms(350)),
+        ] {
+            sender.send(event).unwrap();
+        }
+        drop(sender);
+        wait_until("input to end", || hook.state() == MonitoringState::Stopped);
+        hook.flush();
+
+        let devices = hook.db.get_devices().unwrap();
+        assert_eq!(devices.len(), 2);
+        let total = |device_id| {
+            let filter = StatFilter {
+                device_id: Some(device_id),
+                ..Default::default()
+            };
+            hook.db.get_total_key_count(&filter, false).unwrap()
+        };
+        let id = |name: &str| devices.iter().find(|d| d.name == name).unwrap().id;
+        assert_eq!(total(id("Laptop Keyboard")), 1);
+        assert_eq!(total(id("60% Keyboard")), 2);
+        assert_eq!(total(UNKNOWN_DEVICE_ID), 2);
+    }
+
+    #[test]
+    fn test_excluded_app_creates_no_device() {
+        let (hook, sender, _temp_file) = setup_test_hook();
+        let app_id = hook
+            .db
+            .get_or_create_app("Test App", "com.test.app")
+            .unwrap();
+        hook.db.add_excluded_app(app_id).unwrap();
+        hook.reload_exclusions().unwrap();
+        hook.start().unwrap();
+
+        let device = Arc::new(InputDevice {
+            name: "Laptop Keyboard".to_string(),
+            vendor_id: Some(0x1234),
+            product_id: Some(1),
+        });
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        sender
+            .send(InputEvent::press("KeyA", t0).from_device(&device))
+            .unwrap();
+        sender
+            .send(InputEvent::release("KeyA", t0 + Duration::from_millis(50)).from_device(&device))
+            .unwrap();
+        drop(sender);
+        wait_until("input to end", || hook.state() == MonitoringState::Stopped);
+        hook.flush();
+
+        assert!(hook.db.get_devices().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_bigram_tracker() {
+        let mut tracker = BigramTracker::default();
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let gap = Duration::from_millis(500);
+        let ms = Duration::from_millis;
+
+        assert_eq!(tracker.press("KeyA", t0, 1, gap), None);
+        tracker.release("KeyA");
+        assert_eq!(
+            tracker.press("KeyB", t0 + ms(100), 1, gap),
+            Some(("KeyA".to_string(), "KeyB".to_string()))
+        );
+        // 押しっぱなしのリピートは数えない
+        assert_eq!(tracker.press("KeyB", t0 + ms(150), 1, gap), None);
+        tracker.release("KeyB");
+        // 間隔が空きすぎた遷移は数えない
+        assert_eq!(tracker.press("KeyC", t0 + ms(1000), 1, gap), None);
+        tracker.release("KeyC");
+        // アプリをまたいだ遷移は数えない
+        assert_eq!(tracker.press("KeyD", t0 + ms(1100), 2, gap), None);
+    }
+
+    #[test]
+    fn test_dwell_tracker() {
+        let t0 = SystemTime::UNIX_EPOCH;
+        let mut tracker = DwellTracker::default();
+        tracker.press("Backspace", t0);
+        // リピートで押下時刻は更新されない
+        tracker.press("Backspace", t0 + Duration::from_millis(400));
+        let dwell = tracker
+            .release("Backspace", t0 + Duration::from_millis(620))
+            .unwrap();
+        assert_eq!(dwell_bucket(dwell), 500);
+        // 対応する押下がない解放は無視
+        assert_eq!(tracker.release("Backspace", t0), None);
+        assert_eq!(dwell_bucket(Duration::from_millis(30)), 0);
+        assert_eq!(dwell_bucket(Duration::from_secs(5)), 2000);
+    }
+
+    #[test]
+    fn test_chord_tracker() {
+        let mut tracker = ChordTracker::default();
+        assert_eq!(tracker.press("ControlLeft"), None);
+        assert_eq!(tracker.press("ShiftRight"), None);
+        assert_eq!(tracker.press("KeyP"), Some("Ctrl+Shift+KeyP".to_string()));
+        // 押しっぱなしのリピートは数えない
+        assert_eq!(tracker.press("KeyP"), None);
+        tracker.release("KeyP");
+        tracker.release("ControlLeft");
+        // Shiftと文字キーは通常の入力
+        assert_eq!(tracker.press("KeyA"), None);
+        tracker.release("KeyA");
+        assert_eq!(tracker.press("Tab"), Some("Shift+Tab".to_string()));
+        tracker.release("Tab");
+        tracker.release("ShiftRight");
+        assert_eq!(tracker.press("KeyC"), None);
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let (hook, _sender, _temp_file) = setup_test_hook();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_listener = seen.clone();
+        hook.on_state_change(move |state| seen_listener.lock().unwrap().push(state));
+        assert_eq!(hook.state(), MonitoringState::Stopped);
+
+        // 実行中でなければ一時停止しない
+        hook.pause();
+        assert_eq!(hook.state(), MonitoringState::Stopped);
+
+        hook.start().unwrap();
+        // 実行中の開始は何もしない
+        hook.start().unwrap();
+        hook.pause();
+        assert_eq!(hook.state(), MonitoringState::Paused);
+        assert!(hook.flush_worker.lock().unwrap().is_none());
+
+        // 再開すると flush スレッドが起動し直す
+        hook.start().unwrap();
+        assert_eq!(hook.state(), MonitoringState::Running);
+        assert!(hook.flush_worker.lock().unwrap().is_some());
+        hook.stop();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                MonitoringState::Starting,
+                MonitoringState::Running,
+                MonitoringState::Paused,
+                MonitoringState::Starting,
+                MonitoringState::Running,
+                MonitoringState::Stopped
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repeated_errors_are_reported_once() {
+        let (hook, _sender, _temp_file) = setup_test_hook();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_listener = seen.clone();
+        hook.on_error(move |message| seen_listener.lock().unwrap().push(message.to_string()));
+
+        for message in ["DB is locked", "DB is locked", "Disk full", "DB is locked"] {
+            hook.lifecycle.report_error(message);
+        }
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec!["DB is locked", "Disk full", "DB is locked"]
+        );
+    }
+
+    #[test]
+    fn test_purge_app_while_recording() {
+        let (hook, sender, _temp_file) = setup_test_hook();
+        hook.start().unwrap();
+        let app_id = hook
+            .db
+            .get_or_create_app("Test App", "com.test.app")
+            .unwrap();
+
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        sender.send(InputEvent::press("KeyA", t0)).unwrap();
+        sender
+            .send(InputEvent::release("KeyA", t0 + Duration::from_millis(50)))
+            .unwrap();
+        wait_until("input to arrive", || hook.latency().events == 2);
+
+        let report = hook
+            .purge_app(app_id, || {
+                let report = hook
+                    .db
+                    .purge_app_data(app_id, None, None, PurgeMode::Delete, false);
+                // 削除と同時に届いた押下
+                record(&hook.buffer, &hook.db, |buf| {
+                    count(
+                        &mut buf.stats,
+                        KeyStat {
+                            ts_day: 0,
+                            ts_hour: 0,
+                            key_code: "KeyB".to_string(),
+                            app_id,
+                            device_id: UNKNOWN_DEVICE_ID,
+                        },
+                    )
+                });
+                report
+            })
+            .unwrap();
+        // バッファに残っていた押下も削除の対象になる
+        assert_eq!(report.presses, 1);
+        assert!(report.app_removed);
+        hook.flush();
+        assert_eq!(
+            hook.db
+                .get_total_key_count(&StatFilter::default(), true)
+                .unwrap(),
+            0
+        );
+        // 前面アプリを引き直して記録を再開する
+        assert!(hook.active_app.read().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_event_buffer() {
+        let (hook, _sender, _temp_file) = setup_test_hook();
+        let app_id = hook
+            .db
+            .get_or_create_app("Test App", "com.test.app")
+            .unwrap();
+        let stat = |key_code: &str| KeyStat {
+            ts_day: 100,
+            ts_hour: 100,
+            key_code: key_code.to_string(),
+            app_id,
+            device_id: UNKNOWN_DEVICE_ID,
+        };
+        for _ in 0..1000 {
+            record(&hook.buffer, &hook.db, |buf| {
+                count(&mut buf.stats, stat("KeyA"))
+            });
+        }
+        record(&hook.buffer, &hook.db, |buf| {
+            count(&mut buf.stats, stat("KeyB"))
+        });
+        // 同じキーは1件にまとまる
+        assert_eq!(hook.buffer.lock().unwrap().len(), 2);
+
+        hook.flush();
+        assert_eq!(hook.buffer.lock().unwrap().len(), 0);
+        let ranking = hook
+            .db
+            .get_key_ranking(&crate::db::StatFilter::default(), None, false)
+            .unwrap();
+        assert_eq!(ranking[0].key_code, "KeyA");
+        assert_eq!(ranking[0].count, 1000);
+
+        // 上限に達したらフラッシュを待たずに書き込む
+        for i in 0..MAX_BUFFER_ENTRIES {
+            record(&hook.buffer, &hook.db, |buf| {
+                count(&mut buf.stats, stat(&format!("Key{i}")))
+            });
+        }
+        assert_eq!(hook.buffer.lock().unwrap().len(), 0);
     }
 
     #[test]
     fn test_keyboard_hook_double_start() {
-        let (hook, _temp_file) = setup_test_hook();
+        let (hook, _sender, _temp_file) = setup_test_hook();
 
         // 1回目の開始
-        //hook.start().unwrap();
+        hook.start().unwrap();
         assert!(hook.running.load(Ordering::SeqCst));
 
         // 2回目の開始（エラーにならないことを確認）
-        // hook.start().unwrap();
+        hook.start().unwrap();
         assert!(hook.running.load(Ordering::SeqCst));
+        assert_eq!(hook.state(), MonitoringState::Running);
+        hook.stop();
     }
 }
@@ -0,0 +1,626 @@
+use crate::db::KeyRankingItem;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// QMKのキーコードとkey_code（rdev）の対応表（先頭がQMKの正式な短縮名）
+pub const QMK_KEYCODES: &[(&str, &str)] = &[
+    ("KC_A", "KeyA"),
+    ("KC_B", "KeyB"),
+    ("KC_C", "KeyC"),
+    ("KC_D", "KeyD"),
+    ("KC_E", "KeyE"),
+    ("KC_F", "KeyF"),
+    ("KC_G", "KeyG"),
+    ("KC_H", "KeyH"),
+    ("KC_I", "KeyI"),
+    ("KC_J", "KeyJ"),
+    ("KC_K", "KeyK"),
+    ("KC_L", "KeyL"),
+    ("KC_M", "KeyM"),
+    ("KC_N", "KeyN"),
+    ("KC_O", "KeyO"),
+    ("KC_P", "KeyP"),
+    ("KC_Q", "KeyQ"),
+    ("KC_R", "KeyR"),
+    ("KC_S", "KeyS"),
+    ("KC_T", "KeyT"),
+    ("KC_U", "KeyU"),
+    ("KC_V", "KeyV"),
+    ("KC_W", "KeyW"),
+    ("KC_X", "KeyX"),
+    ("KC_Y", "KeyY"),
+    ("KC_Z", "KeyZ"),
+    ("KC_1", "Num1"),
+    ("KC_2", "Num2"),
+    ("KC_3", "Num3"),
+    ("KC_4", "Num4"),
+    ("KC_5", "Num5"),
+    ("KC_6", "Num6"),
+    ("KC_7", "Num7"),
+    ("KC_8", "Num8"),
+    ("KC_9", "Num9"),
+    ("KC_0", "Num0"),
+    ("KC_ENT", "Return"),
+    ("KC_ESC", "Escape"),
+    ("KC_BSPC", "Backspace"),
+    ("KC_TAB", "Tab"),
+    ("KC_SPC", "Space"),
+    ("KC_MINS", "Minus"),
+    ("KC_EQL", "Equal"),
+    ("KC_LBRC", "LeftBracket"),
+    ("KC_RBRC", "RightBracket"),
+    ("KC_BSLS", "BackSlash"),
+    ("KC_SCLN", "SemiColon"),
+    ("KC_QUOT", "Quote"),
+    ("KC_GRV", "BackQuote"),
+    ("KC_COMM", "Comma"),
+    ("KC_DOT", "Dot"),
+    ("KC_SLSH", "Slash"),
+    ("KC_NUBS", "IntlBackslash"),
+    ("KC_CAPS", "CapsLock"),
+    ("KC_F1", "F1"),
+    ("KC_F2", "F2"),
+    ("KC_F3", "F3"),
+    ("KC_F4", "F4"),
+    ("KC_F5", "F5"),
+    ("KC_F6", "F6"),
+    ("KC_F7", "F7"),
+    ("KC_F8", "F8"),
+    ("KC_F9", "F9"),
+    ("KC_F10", "F10"),
+    ("KC_F11", "F11"),
+    ("KC_F12", "F12"),
+    ("KC_PSCR", "PrintScreen"),
+    ("KC_SCRL", "ScrollLock"),
+    ("KC_PAUS", "Pause"),
+    ("KC_INS", "Insert"),
+    ("KC_HOME", "Home"),
+    ("KC_PGUP", "PageUp"),
+    ("KC_DEL", "Delete"),
+    ("KC_END", "End"),
+    ("KC_PGDN", "PageDown"),
+    ("KC_RGHT", "RightArrow"),
+    ("KC_LEFT", "LeftArrow"),
+    ("KC_DOWN", "DownArrow"),
+    ("KC_UP", "UpArrow"),
+    ("KC_NUM", "NumLock"),
+    ("KC_PSLS", "KpDivide"),
+    ("KC_PAST", "KpMultiply"),
+    ("KC_PMNS", "KpMinus"),
+    ("KC_PPLS", "KpPlus"),
+    ("KC_PENT", "KpReturn"),
+    ("KC_P1", "Kp1"),
+    ("KC_P2", "Kp2"),
+    ("KC_P3", "Kp3"),
+    ("KC_P4", "Kp4"),
+    ("KC_P5", "Kp5"),
+    ("KC_P6", "Kp6"),
+    ("KC_P7", "Kp7"),
+    ("KC_P8", "Kp8"),
+    ("KC_P9", "Kp9"),
+    ("KC_P0", "Kp0"),
+    ("KC_PDOT", "KpDecimal"),
+    ("KC_APP", "Apps"),
+    ("KC_LNG1", "Lang1"),
+    ("KC_LNG2", "Lang2"),
+    ("KC_INT2", "KanaMode"),
+    ("KC_LCTL", "ControlLeft"),
+    ("KC_LSFT", "ShiftLeft"),
+    ("KC_LALT", "Alt"),
+    ("KC_LGUI", "MetaLeft"),
+    ("KC_RCTL", "ControlRight"),
+    ("KC_RSFT", "ShiftRight"),
+    ("KC_RALT", "AltGr"),
+    ("KC_RGUI", "MetaRight"),
+];
+
+/// 長い名前や別名、Shift付き記号（押すキーは同じ）の対応表
+const QMK_ALIASES: &[(&str, &str)] = &[
+    ("KC_ENTER", "KC_ENT"),
+    ("KC_ESCAPE", "KC_ESC"),
+    ("KC_BACKSPACE", "KC_BSPC"),
+    ("KC_SPACE", "KC_SPC"),
+    ("KC_MINUS", "KC_MINS"),
+    ("KC_EQUAL", "KC_EQL"),
+    ("KC_LEFT_BRACKET", "KC_LBRC"),
+    ("KC_RIGHT_BRACKET", "KC_RBRC"),
+    ("KC_BACKSLASH", "KC_BSLS"),
+    ("KC_NONUS_HASH", "KC_BSLS"),
+    ("KC_NUHS", "KC_BSLS"),
+    ("KC_SEMICOLON", "KC_SCLN"),
+    ("KC_QUOTE", "KC_QUOT"),
+    ("KC_GRAVE", "KC_GRV"),
+    ("KC_COMMA", "KC_COMM"),
+    ("KC_SLASH", "KC_SLSH"),
+    ("KC_NONUS_BACKSLASH", "KC_NUBS"),
+    ("KC_CAPS_LOCK", "KC_CAPS"),
+    ("KC_PRINT_SCREEN", "KC_PSCR"),
+    ("KC_SCROLL_LOCK", "KC_SCRL"),
+    ("KC_SLCK", "KC_SCRL"),
+    ("KC_PAUSE", "KC_PAUS"),
+    ("KC_BRK", "KC_PAUS"),
+    ("KC_INSERT", "KC_INS"),
+    ("KC_PAGE_UP", "KC_PGUP"),
+    ("KC_DELETE", "KC_DEL"),
+    ("KC_PAGE_DOWN", "KC_PGDN"),
+    ("KC_RIGHT", "KC_RGHT"),
+    ("KC_NUM_LOCK", "KC_NUM"),
+    ("KC_NLCK", "KC_NUM"),
+    ("KC_KP_SLASH", "KC_PSLS"),
+    ("KC_KP_ASTERISK", "KC_PAST"),
+    ("KC_KP_MINUS", "KC_PMNS"),
+    ("KC_KP_PLUS", "KC_PPLS"),
+    ("KC_KP_ENTER", "KC_PENT"),
+    ("KC_KP_1", "KC_P1"),
+    ("KC_KP_2", "KC_P2"),
+    ("KC_KP_3", "KC_P3"),
+    ("KC_KP_4", "KC_P4"),
+    ("KC_KP_5", "KC_P5"),
+    ("KC_KP_6", "KC_P6"),
+    ("KC_KP_7", "KC_P7"),
+    ("KC_KP_8", "KC_P8"),
+    ("KC_KP_9", "KC_P9"),
+    ("KC_KP_0", "KC_P0"),
+    ("KC_KP_DOT", "KC_PDOT"),
+    ("KC_APPLICATION", "KC_APP"),
+    ("KC_LANG1", "KC_LNG1"),
+    ("KC_LANG2", "KC_LNG2"),
+    ("KC_KANA", "KC_INT2"),
+    ("KC_LEFT_CTRL", "KC_LCTL"),
+    ("KC_LEFT_SHIFT", "KC_LSFT"),
+    ("KC_LEFT_ALT", "KC_LALT"),
+    ("KC_LOPT", "KC_LALT"),
+    ("KC_LEFT_GUI", "KC_LGUI"),
+    ("KC_LCMD", "KC_LGUI"),
+    ("KC_LWIN", "KC_LGUI"),
+    ("KC_RIGHT_CTRL", "KC_RCTL"),
+    ("KC_RIGHT_SHIFT", "KC_RSFT"),
+    ("KC_RIGHT_ALT", "KC_RALT"),
+    ("KC_ROPT", "KC_RALT"),
+    ("KC_ALGR", "KC_RALT"),
+    ("KC_RIGHT_GUI", "KC_RGUI"),
+    ("KC_RCMD", "KC_RGUI"),
+    ("KC_RWIN", "KC_RGUI"),
+    ("KC_EXLM", "KC_1"),
+    ("KC_AT", "KC_2"),
+    ("KC_HASH", "KC_3"),
+    ("KC_DLR", "KC_4"),
+    ("KC_PERC", "KC_5"),
+    ("KC_CIRC", "KC_6"),
+    ("KC_AMPR", "KC_7"),
+    ("KC_ASTR", "KC_8"),
+    ("KC_LPRN", "KC_9"),
+    ("KC_RPRN", "KC_0"),
+    ("KC_UNDS", "KC_MINS"),
+    ("KC_PLUS", "KC_EQL"),
+    ("KC_LCBR", "KC_LBRC"),
+    ("KC_RCBR", "KC_RBRC"),
+    ("KC_PIPE", "KC_BSLS"),
+    ("KC_COLN", "KC_SCLN"),
+    ("KC_DQUO", "KC_QUOT"),
+    ("KC_DQT", "KC_QUOT"),
+    ("KC_TILD", "KC_GRV"),
+    ("KC_LT", "KC_COMM"),
+    ("KC_GT", "KC_DOT"),
+    ("KC_QUES", "KC_SLSH"),
+];
+
+/// QMKのキーコードをkey_codeに変換する
+pub fn qmk_to_key_code(qmk: &str) -> Option<&'static str> {
+    let canonical = QMK_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == qmk)
+        .map(|(_, canonical)| *canonical)
+        .unwrap_or(qmk);
+    QMK_KEYCODES
+        .iter()
+        .find(|(name, _)| *name == canonical)
+        .map(|(_, code)| *code)
+}
+
+/// key_codeをQMKのキーコードに変換する
+pub fn key_code_to_qmk(key_code: &str) -> Option<&'static str> {
+    QMK_KEYCODES
+        .iter()
+        .find(|(_, code)| *code == key_code)
+        .map(|(name, _)| *name)
+}
+
+/// レイヤーへの切り替え方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LayerAccess {
+    /// MO / LT / TT / OSL / LM（押している間、または次の1打だけ有効）
+    Momentary,
+    /// TG / TO（切り替えて戻す操作が必要）
+    Toggle,
+}
+
+impl LayerAccess {
+    /// このレイヤーのキーを1回押すのに追加で必要な打鍵数
+    fn cost(&self) -> i64 {
+        match self {
+            LayerAccess::Momentary => 1,
+            LayerAccess::Toggle => 2,
+        }
+    }
+}
+
+/// 1つのキー位置が持つ動作
+#[derive(Debug, Default, Clone, PartialEq)]
+struct KeyAction {
+    /// タップまたはホールドで入力できるkey_code
+    keys: Vec<String>,
+    layer: Option<(usize, LayerAccess)>,
+}
+
+fn split_args(args: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in args.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&args[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&args[start..]);
+    parts
+}
+
+fn mod_keys(mods: &str) -> Vec<String> {
+    mods.split('|')
+        .flat_map(|m| match m {
+            "MOD_LCTL" => vec!["ControlLeft"],
+            "MOD_LSFT" => vec!["ShiftLeft"],
+            "MOD_LALT" => vec!["Alt"],
+            "MOD_LGUI" => vec!["MetaLeft"],
+            "MOD_RCTL" => vec!["ControlRight"],
+            "MOD_RSFT" => vec!["ShiftRight"],
+            "MOD_RALT" => vec!["AltGr"],
+            "MOD_RGUI" => vec!["MetaRight"],
+            "MOD_MEH" => vec!["ControlLeft", "ShiftLeft", "Alt"],
+            "MOD_HYPR" => vec!["ControlLeft", "ShiftLeft", "Alt", "MetaLeft"],
+            _ => vec![],
+        })
+        .map(|k| k.to_string())
+        .collect()
+}
+
+/// Mod-Tapの短縮形（LCTL_T(kc)など）のホールド側の修飾キー
+fn mod_tap_mods(name: &str) -> Option<&'static str> {
+    let mods = match name {
+        "LCTL_T" | "CTL_T" => "MOD_LCTL",
+        "LSFT_T" | "SFT_T" => "MOD_LSFT",
+        "LALT_T" | "ALT_T" | "LOPT_T" | "OPT_T" => "MOD_LALT",
+        "LGUI_T" | "GUI_T" | "LCMD_T" | "CMD_T" | "LWIN_T" | "WIN_T" => "MOD_LGUI",
+        "RCTL_T" => "MOD_RCTL",
+        "RSFT_T" => "MOD_RSFT",
+        "RALT_T" | "ROPT_T" | "ALGR_T" => "MOD_RALT",
+        "RGUI_T" | "RCMD_T" | "RWIN_T" => "MOD_RGUI",
+        "MEH_T" => "MOD_MEH",
+        "HYPR_T" | "ALL_T" => "MOD_HYPR",
+        _ => return None,
+    };
+    Some(mods)
+}
+
+fn parse_action(keycode: &str) -> KeyAction {
+    let keycode: String = keycode.chars().filter(|c| !c.is_whitespace()).collect();
+    let (name, args) = match keycode.find('(') {
+        Some(open) if keycode.ends_with(')') => (
+            &keycode[..open],
+            split_args(&keycode[open + 1..keycode.len() - 1]),
+        ),
+        _ => {
+            return KeyAction {
+                keys: qmk_to_key_code(&keycode)
+                    .map(|k| vec![k.to_string()])
+                    .unwrap_or_default(),
+                layer: None,
+            };
+        }
+    };
+    let layer_arg = || args.first().and_then(|a| a.parse::<usize>().ok());
+    let inner = |i: usize| args.get(i).map(|a| parse_action(a)).unwrap_or_default();
+
+    match name {
+        "MO" | "TT" | "OSL" => KeyAction {
+            keys: Vec::new(),
+            layer: layer_arg().map(|l| (l, LayerAccess::Momentary)),
+        },
+        "TG" | "TO" => KeyAction {
+            keys: Vec::new(),
+            layer: layer_arg().map(|l| (l, LayerAccess::Toggle)),
+        },
+        "LT" => KeyAction {
+            keys: inner(1).keys,
+            layer: layer_arg().map(|l| (l, LayerAccess::Momentary)),
+        },
+        "LM" => KeyAction {
+            keys: args.get(1).map(|m| mod_keys(m)).unwrap_or_default(),
+            layer: layer_arg().map(|l| (l, LayerAccess::Momentary)),
+        },
+        "MT" => {
+            let mut keys = args.first().map(|m| mod_keys(m)).unwrap_or_default();
+            keys.extend(inner(1).keys);
+            KeyAction { keys, layer: None }
+        }
+        "OSM" => KeyAction {
+            keys: args.first().map(|m| mod_keys(m)).unwrap_or_default(),
+            layer: None,
+        },
+        _ => match mod_tap_mods(name) {
+            Some(mods) => {
+                let mut keys = mod_keys(mods);
+                keys.extend(inner(0).keys);
+                KeyAction { keys, layer: None }
+            }
+            // LSFT(kc)などの修飾付きキーは中身のキーだけを数える
+            None => KeyAction {
+                keys: inner(args.len() - 1).keys,
+                layer: None,
+            },
+        },
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct KeymapFile {
+    #[serde(default)]
+    keymap: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+    layers: Vec<Vec<serde_json::Value>>,
+}
+
+/// QMKのkeymap.jsonまたはVIAのエクスポートを読み込んだもの
+#[derive(Debug)]
+pub struct Keymap {
+    pub name: String,
+    layers: Vec<Vec<KeyAction>>,
+}
+
+impl Keymap {
+    pub fn parse(content: &str) -> Result<Self> {
+        let file: KeymapFile =
+            serde_json::from_str(content).map_err(|e| anyhow!("Invalid keymap: {}", e))?;
+        if file.layers.is_empty() {
+            return Err(anyhow!("Invalid keymap: no layers"));
+        }
+        let layers = file
+            .layers
+            .iter()
+            .map(|layer| {
+                layer
+                    .iter()
+                    .map(|kc| kc.as_str().map(parse_action).unwrap_or_default())
+                    .collect()
+            })
+            .collect();
+        Ok(Self {
+            name: file
+                .keymap
+                .or(file.name)
+                .unwrap_or_else(|| "keymap".to_string()),
+            layers,
+        })
+    }
+
+    pub fn layer_count(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// ベースレイヤーから各レイヤーを有効にするまでの最小の追加打鍵数
+    fn layer_costs(&self) -> Vec<Option<i64>> {
+        let mut costs: Vec<Option<i64>> = vec![None; self.layers.len()];
+        costs[0] = Some(0);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (from, layer) in self.layers.iter().enumerate() {
+                let base = match costs[from] {
+                    Some(c) => c,
+                    None => continue,
+                };
+                for (to, access) in layer.iter().filter_map(|a| a.layer) {
+                    if to >= costs.len() {
+                        continue;
+                    }
+                    let cost = base + access.cost();
+                    let better = match costs[to] {
+                        Some(c) => cost < c,
+                        None => true,
+                    };
+                    if better {
+                        costs[to] = Some(cost);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        costs
+    }
+
+    /// key_codeごとの（最小追加打鍵数, レイヤー番号）
+    fn key_costs(&self) -> HashMap<String, (i64, usize)> {
+        let mut result: HashMap<String, (i64, usize)> = HashMap::new();
+        for (layer, cost) in self.layer_costs().into_iter().enumerate() {
+            let cost = match cost {
+                Some(c) => c,
+                None => continue,
+            };
+            for key in self.layers[layer].iter().flat_map(|a| a.keys.iter()) {
+                let entry = result.entry(key.clone()).or_insert((cost, layer));
+                if cost < entry.0 {
+                    *entry = (cost, layer);
+                }
+            }
+        }
+        result
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LayerKeyUsage {
+    pub key_code: String,
+    pub count: i64,
+    pub layer: usize,
+    pub extra_keystrokes: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeymapReport {
+    pub name: String,
+    pub layer_count: usize,
+    pub total_presses: i64,
+    pub base_layer_presses: i64,
+    pub layer_presses: i64,
+    pub layer_presses_per_day: f64,
+    pub extra_keystrokes: i64,
+    pub extra_keystrokes_per_day: f64,
+    pub unreachable_presses: i64,
+    pub unreachable_keys: Vec<KeyRankingItem>,
+    pub layer_keys: Vec<LayerKeyUsage>,
+}
+
+/// 過去の押下数をキーマップ上で再生した場合のレイヤー操作コストを求める
+pub fn simulate_keymap(keymap: &Keymap, ranking: &[KeyRankingItem], days: i64) -> KeymapReport {
+    let key_costs = keymap.key_costs();
+    let days = days.max(1) as f64;
+    let mut report = KeymapReport {
+        name: keymap.name.clone(),
+        layer_count: keymap.layer_count(),
+        total_presses: 0,
+        base_layer_presses: 0,
+        layer_presses: 0,
+        layer_presses_per_day: 0.0,
+        extra_keystrokes: 0,
+        extra_keystrokes_per_day: 0.0,
+        unreachable_presses: 0,
+        unreachable_keys: Vec::new(),
+        layer_keys: Vec::new(),
+    };
+
+    for item in ranking {
+        report.total_presses += item.count;
+        match key_costs.get(&item.key_code) {
+            Some((0, _)) => report.base_layer_presses += item.count,
+            Some((cost, layer)) => {
+                report.layer_presses += item.count;
+                report.extra_keystrokes += item.count * cost;
+                report.layer_keys.push(LayerKeyUsage {
+                    key_code: item.key_code.clone(),
+                    count: item.count,
+                    layer: *layer,
+                    extra_keystrokes: item.count * cost,
+                });
+            }
+            None => {
+                report.unreachable_presses += item.count;
+                report.unreachable_keys.push(KeyRankingItem {
+                    key_code: item.key_code.clone(),
+                    count: item.count,
+                });
+            }
+        }
+    }
+
+    report.layer_keys.sort_by_key(|k| Reverse(k.count));
+    report.unreachable_keys.sort_by_key(|k| Reverse(k.count));
+    report.layer_presses_per_day = report.layer_presses as f64 / days;
+    report.extra_keystrokes_per_day = report.extra_keystrokes as f64 / days;
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(key_code: &str, count: i64) -> KeyRankingItem {
+        KeyRankingItem {
+            key_code: key_code.to_string(),
+            count,
+        }
+    }
+
+    const KEYMAP: &str = r#"{
+        "keyboard": "test",
+        "keymap": "mine",
+        "layout": "LAYOUT",
+        "layers": [
+            ["KC_Q", "LSFT_T(KC_A)", "LT(1, KC_SPC)", "TG(3)", "KC_TRNS"],
+            ["KC_1", "S(KC_2)", "KC_TRNS", "MO(2)", "KC_NO"],
+            ["KC_F1", "KC_TRNS", "KC_TRNS", "KC_TRNS", "KC_TRNS"],
+            ["KC_P1", "KC_TRNS", "KC_TRNS", "KC_TRNS", "KC_TRNS"],
+            ["KC_F13", "KC_TRNS", "KC_TRNS", "KC_TRNS", "KC_TRNS"]
+        ]
+    }"#;
+
+    #[test]
+    fn test_parse_action() {
+        assert_eq!(parse_action("KC_SPACE").keys, vec!["Space"]);
+        assert_eq!(parse_action("KC_EXLM").keys, vec!["Num1"]);
+        assert_eq!(
+            parse_action("MT(MOD_LCTL | MOD_LSFT, KC_ESC)").keys,
+            vec!["ControlLeft", "ShiftLeft", "Escape"]
+        );
+        assert_eq!(
+            parse_action("LT(2,KC_BSPC)").layer,
+            Some((2, LayerAccess::Momentary))
+        );
+        assert_eq!(parse_action("LCTL(KC_C)").keys, vec!["KeyC"]);
+        assert!(parse_action("KC_TRNS").keys.is_empty());
+        assert!(parse_action("QK_BOOT").keys.is_empty());
+    }
+
+    #[test]
+    fn test_simulate_keymap() {
+        let keymap = Keymap::parse(KEYMAP).unwrap();
+        assert_eq!(keymap.name, "mine");
+        assert_eq!(keymap.layer_count(), 5);
+
+        let ranking = vec![
+            item("KeyQ", 100),
+            item("Space", 80),
+            item("ShiftLeft", 20),
+            item("Num1", 10),
+            item("Num2", 5),
+            item("F1", 4),
+            item("Kp1", 3),
+            item("F13", 2),
+            item("Escape", 1),
+        ];
+        let report = simulate_keymap(&keymap, &ranking, 2);
+        assert_eq!(report.total_presses, 225);
+        assert_eq!(report.base_layer_presses, 200);
+        // Num1/Num2: MOで+1、F1: MO→MOで+2、Kp1: TGで+2
+        assert_eq!(report.layer_presses, 22);
+        assert_eq!(report.extra_keystrokes, 10 + 5 + 4 * 2 + 3 * 2);
+        assert!((report.layer_presses_per_day - 11.0).abs() < f64::EPSILON);
+        assert_eq!(report.layer_keys[0].key_code, "Num1");
+        assert_eq!(report.layer_keys[0].layer, 1);
+
+        // レイヤー4へ到達する手段がないのでF13は到達不能
+        let unreachable: Vec<&str> = report
+            .unreachable_keys
+            .iter()
+            .map(|k| k.key_code.as_str())
+            .collect();
+        assert_eq!(unreachable, vec!["F13", "Escape"]);
+        assert_eq!(report.unreachable_presses, 3);
+    }
+
+    #[test]
+    fn test_key_code_to_qmk() {
+        assert_eq!(key_code_to_qmk("SemiColon"), Some("KC_SCLN"));
+        assert_eq!(qmk_to_key_code("KC_SEMICOLON"), Some("SemiColon"));
+        assert_eq!(key_code_to_qmk("Unknown(243)"), None);
+    }
+}
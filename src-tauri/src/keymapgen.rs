@@ -0,0 +1,507 @@
+use crate::db::KeyRankingItem;
+use crate::keymap::key_code_to_qmk;
+use crate::layout::LayoutDefinition;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+
+/// レイヤー配列に並べる順序（US配列の行順）
+const KEY_ORDER: &[&str] = &[
+    "Escape",
+    "F1",
+    "F2",
+    "F3",
+    "F4",
+    "F5",
+    "F6",
+    "F7",
+    "F8",
+    "F9",
+    "F10",
+    "F11",
+    "F12",
+    "PrintScreen",
+    "ScrollLock",
+    "Pause",
+    "BackQuote",
+    "Num1",
+    "Num2",
+    "Num3",
+    "Num4",
+    "Num5",
+    "Num6",
+    "Num7",
+    "Num8",
+    "Num9",
+    "Num0",
+    "Minus",
+    "Equal",
+    "Backspace",
+    "Insert",
+    "Home",
+    "PageUp",
+    "NumLock",
+    "KpDivide",
+    "KpMultiply",
+    "KpMinus",
+    "Tab",
+    "KeyQ",
+    "KeyW",
+    "KeyE",
+    "KeyR",
+    "KeyT",
+    "KeyY",
+    "KeyU",
+    "KeyI",
+    "KeyO",
+    "KeyP",
+    "LeftBracket",
+    "RightBracket",
+    "BackSlash",
+    "Delete",
+    "End",
+    "PageDown",
+    "Kp7",
+    "Kp8",
+    "Kp9",
+    "KpPlus",
+    "CapsLock",
+    "KeyA",
+    "KeyS",
+    "KeyD",
+    "KeyF",
+    "KeyG",
+    "KeyH",
+    "KeyJ",
+    "KeyK",
+    "KeyL",
+    "SemiColon",
+    "Quote",
+    "Return",
+    "Kp4",
+    "Kp5",
+    "Kp6",
+    "ShiftLeft",
+    "IntlBackslash",
+    "KeyZ",
+    "KeyX",
+    "KeyC",
+    "KeyV",
+    "KeyB",
+    "KeyN",
+    "KeyM",
+    "Comma",
+    "Dot",
+    "Slash",
+    "ShiftRight",
+    "UpArrow",
+    "Kp1",
+    "Kp2",
+    "Kp3",
+    "KpReturn",
+    "ControlLeft",
+    "MetaLeft",
+    "Alt",
+    "Lang1",
+    "Space",
+    "Lang2",
+    "KanaMode",
+    "AltGr",
+    "MetaRight",
+    "Apps",
+    "ControlRight",
+    "LeftArrow",
+    "DownArrow",
+    "RightArrow",
+    "Kp0",
+    "KpDecimal",
+];
+
+fn key_order(key_code: &str) -> usize {
+    KEY_ORDER
+        .iter()
+        .position(|k| *k == key_code)
+        .unwrap_or(KEY_ORDER.len())
+}
+
+/// key_codeをZMKのキーコードに変換する
+fn key_code_to_zmk(key_code: &str) -> Option<String> {
+    if let Some(letter) = key_code.strip_prefix("Key") {
+        return Some(letter.to_string());
+    }
+    if let Some(digit) = key_code.strip_prefix("Num") {
+        if digit.len() == 1 {
+            return Some(format!("N{}", digit));
+        }
+    }
+    if let Some(digit) = key_code.strip_prefix("Kp") {
+        if digit.len() == 1 {
+            return Some(format!("KP_N{}", digit));
+        }
+    }
+    if key_code.starts_with('F') && key_code[1..].parse::<u8>().is_ok() {
+        return Some(key_code.to_string());
+    }
+    let zmk = match key_code {
+        "Return" => "RET",
+        "Escape" => "ESC",
+        "Backspace" => "BSPC",
+        "Tab" => "TAB",
+        "Space" => "SPACE",
+        "Minus" => "MINUS",
+        "Equal" => "EQUAL",
+        "LeftBracket" => "LBKT",
+        "RightBracket" => "RBKT",
+        "BackSlash" => "BSLH",
+        "SemiColon" => "SEMI",
+        "Quote" => "SQT",
+        "BackQuote" => "GRAVE",
+        "Comma" => "COMMA",
+        "Dot" => "DOT",
+        "Slash" => "FSLH",
+        "IntlBackslash" => "NON_US_BSLH",
+        "CapsLock" => "CAPS",
+        "PrintScreen" => "PSCRN",
+        "ScrollLock" => "SLCK",
+        "Pause" => "PAUSE_BREAK",
+        "Insert" => "INS",
+        "Home" => "HOME",
+        "PageUp" => "PG_UP",
+        "Delete" => "DEL",
+        "End" => "END",
+        "PageDown" => "PG_DN",
+        "RightArrow" => "RIGHT",
+        "LeftArrow" => "LEFT",
+        "DownArrow" => "DOWN",
+        "UpArrow" => "UP",
+        "NumLock" => "KP_NUM",
+        "KpDivide" => "KP_DIVIDE",
+        "KpMultiply" => "KP_MULTIPLY",
+        "KpMinus" => "KP_MINUS",
+        "KpPlus" => "KP_PLUS",
+        "KpReturn" => "KP_ENTER",
+        "KpDecimal" => "KP_DOT",
+        "Apps" => "K_APP",
+        "Lang1" => "LANG1",
+        "Lang2" => "LANG2",
+        "KanaMode" => "INT_KANA",
+        "ControlLeft" => "LCTRL",
+        "ShiftLeft" => "LSHFT",
+        "Alt" => "LALT",
+        "MetaLeft" => "LGUI",
+        "ControlRight" => "RCTRL",
+        "ShiftRight" => "RSHFT",
+        "AltGr" => "RALT",
+        "MetaRight" => "RGUI",
+        _ => return None,
+    };
+    Some(zmk.to_string())
+}
+
+/// アプリごとの重みを反映したランキング
+///
+/// `app_rankings` は（重み, そのアプリのランキング）で、全体のランキングに
+/// `(重み - 1) × アプリの押下数` を加える。
+pub fn weight_ranking(
+    ranking: &[KeyRankingItem],
+    app_rankings: &[(f64, Vec<KeyRankingItem>)],
+) -> Vec<(String, f64)> {
+    let mut weighted: HashMap<String, f64> = ranking
+        .iter()
+        .map(|item| (item.key_code.clone(), item.count as f64))
+        .collect();
+    for (weight, app_ranking) in app_rankings {
+        for item in app_ranking {
+            *weighted.entry(item.key_code.clone()).or_insert(0.0) +=
+                (weight - 1.0) * item.count as f64;
+        }
+    }
+    let mut result: Vec<(String, f64)> = weighted.into_iter().filter(|(_, w)| *w > 0.0).collect();
+    result.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    result
+}
+
+/// ベースレイヤーに置くために必要な押下数の割合
+const BASE_LAYER_MIN_SHARE: f64 = 0.001;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Slot {
+    Key(String),
+    LayerKey,
+    Empty,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeymapSuggestion {
+    pub layout_id: String,
+    /// 各位置のkey_code（レイヤーキーは "MO(1)"、空きは空文字列）
+    pub base_layer: Vec<String>,
+    pub fn_layer: Vec<String>,
+    /// 一度も使われていないため外したキー
+    pub dropped_keys: Vec<String>,
+    /// 空きが足りない、またはQMK/ZMKで表せないため配置できなかったキー
+    pub unplaced_keys: Vec<KeyRankingItem>,
+    pub qmk_json: String,
+    pub zmk_keymap: String,
+}
+
+/// 使用頻度からレイアウトのキー数に合わせた2レイヤーのキーマップを作る
+///
+/// 頻度の高いキーをベースレイヤーに、残りの使用済みキーをファンクションレイヤーに置き、
+/// 使われていないキーは外す。配列の順序はレイアウトのキーをUS配列の行順に並べたもの。
+pub fn suggest_keymap(
+    layout: &LayoutDefinition,
+    weighted: &[(String, f64)],
+    keyboard: &str,
+    layout_macro: &str,
+) -> KeymapSuggestion {
+    let mut slots_keys: Vec<&str> = layout.keys.iter().map(|k| k.as_str()).collect();
+    slots_keys.sort_by_key(|k| (key_order(k), *k));
+    let slot_count = slots_keys.len();
+
+    let mut unplaced_keys = Vec::new();
+    let mut candidates: Vec<(&str, f64)> = Vec::new();
+    for (key, weight) in weighted {
+        if key_code_to_qmk(key).is_some() && key_code_to_zmk(key).is_some() {
+            candidates.push((key.as_str(), *weight));
+        } else {
+            unplaced_keys.push(KeyRankingItem {
+                key_code: key.clone(),
+                count: weight.round() as i64,
+            });
+        }
+    }
+
+    // ベースレイヤーはレイヤーキーの分を1つ空けておく
+    // 空きがあってもほとんど使わないキーはファンクションレイヤーに回す
+    let base_capacity = slot_count.saturating_sub(1);
+    let total: f64 = candidates.iter().map(|(_, w)| w).sum();
+    let base_list: Vec<&str> = candidates
+        .iter()
+        .filter(|(_, w)| *w >= total * BASE_LAYER_MIN_SHARE)
+        .take(base_capacity)
+        .map(|(k, _)| *k)
+        .collect();
+    let base_keys: HashSet<&str> = base_list.iter().copied().collect();
+    let fn_keys: Vec<&str> = candidates
+        .iter()
+        .map(|(k, _)| *k)
+        .filter(|k| !base_keys.contains(k))
+        .collect();
+
+    // レイアウト上にあるキーは元の位置に置き、空いた位置に他のキーを詰める
+    let mut base: Vec<Slot> = slots_keys
+        .iter()
+        .map(|k| {
+            if base_keys.contains(k) {
+                Slot::Key(k.to_string())
+            } else {
+                Slot::Empty
+            }
+        })
+        .collect();
+    let mut promoted: Vec<&str> = base_list
+        .iter()
+        .copied()
+        .filter(|k| !slots_keys.contains(k))
+        .collect();
+    promoted.sort_by_key(|k| (key_order(k), *k));
+    let mut promoted = promoted.into_iter();
+    for slot in base.iter_mut().filter(|s| **s == Slot::Empty) {
+        match promoted.next() {
+            Some(key) => *slot = Slot::Key(key.to_string()),
+            None => break,
+        }
+    }
+    if let Some(slot) = base.iter_mut().find(|s| **s == Slot::Empty) {
+        *slot = Slot::LayerKey;
+    }
+
+    let mut fn_layer: Vec<Slot> = vec![Slot::Empty; slot_count];
+    let mut fn_keys = fn_keys.into_iter();
+    for (i, slot) in fn_layer.iter_mut().enumerate() {
+        if base[i] == Slot::LayerKey {
+            continue;
+        }
+        match fn_keys.next() {
+            Some(key) => *slot = Slot::Key(key.to_string()),
+            None => break,
+        }
+    }
+    for key in fn_keys {
+        let weight = candidates.iter().find(|(k, _)| *k == key).unwrap().1;
+        unplaced_keys.push(KeyRankingItem {
+            key_code: key.to_string(),
+            count: weight.round() as i64,
+        });
+    }
+
+    let used: HashSet<&str> = weighted.iter().map(|(k, _)| k.as_str()).collect();
+    let dropped_keys = slots_keys
+        .iter()
+        .filter(|k| !used.contains(*k))
+        .map(|k| k.to_string())
+        .collect();
+
+    KeymapSuggestion {
+        layout_id: layout.id.clone(),
+        base_layer: base.iter().map(slot_label).collect(),
+        fn_layer: fn_layer.iter().map(slot_label).collect(),
+        dropped_keys,
+        unplaced_keys,
+        qmk_json: render_qmk(&base, &fn_layer, keyboard, layout_macro),
+        zmk_keymap: render_zmk(&base, &fn_layer),
+    }
+}
+
+fn slot_label(slot: &Slot) -> String {
+    match slot {
+        Slot::Key(key) => key.clone(),
+        Slot::LayerKey => "MO(1)".to_string(),
+        Slot::Empty => String::new(),
+    }
+}
+
+fn render_qmk(base: &[Slot], fn_layer: &[Slot], keyboard: &str, layout_macro: &str) -> String {
+    let layer = |slots: &[Slot], empty: &str| -> Vec<String> {
+        slots
+            .iter()
+            .map(|slot| match slot {
+                Slot::Key(key) => key_code_to_qmk(key).unwrap_or("KC_NO").to_string(),
+                Slot::LayerKey if empty == "KC_NO" => "MO(1)".to_string(),
+                _ => empty.to_string(),
+            })
+            .collect()
+    };
+    let keymap = json!({
+        "version": 1,
+        "notes": "Generated by KeyFit",
+        "keyboard": keyboard,
+        "keymap": "keyfit",
+        "layout": layout_macro,
+        "layers": [layer(base, "KC_NO"), layer(fn_layer, "KC_TRNS")],
+    });
+    serde_json::to_string_pretty(&keymap).unwrap()
+}
+
+/// &kp・&mo などの behavior とキー名の定義（これがないと単体の .keymap として読めない）
+const ZMK_INCLUDES: &str = "#include <behaviors.dtsi>\n#include <dt-bindings/zmk/keys.h>\n";
+
+fn render_zmk(base: &[Slot], fn_layer: &[Slot]) -> String {
+    let layer = |name: &str, slots: &[Slot], empty: &str| -> String {
+        let bindings: Vec<String> = slots
+            .iter()
+            .map(|slot| match slot {
+                Slot::Key(key) => format!("&kp {}", key_code_to_zmk(key).unwrap()),
+                Slot::LayerKey if empty == "&none" => "&mo 1".to_string(),
+                _ => empty.to_string(),
+            })
+            .collect();
+        let rows: Vec<String> = bindings
+            .chunks(12)
+            .map(|row| format!("                {}", row.join(" ")))
+            .collect();
+        format!(
+            "        {} {{\n            bindings = <\n{}\n            >;\n        }};\n",
+            name,
+            rows.join("\n")
+        )
+    };
+    format!(
+        "{ZMK_INCLUDES}\n/ {{\n    keymap {{\n        compatible = \"zmk,keymap\";\n\n{}\n{}    }};\n}};\n",
+        layer("base_layer", base, "&none"),
+        layer("fn_layer", fn_layer, "&trans")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(key_code: &str, count: i64) -> KeyRankingItem {
+        KeyRankingItem {
+            key_code: key_code.to_string(),
+            count,
+        }
+    }
+
+    fn tiny_layout() -> LayoutDefinition {
+        serde_json::from_str(
+            r#"{ "id": "tiny", "name": "Tiny", "keys": ["KeyA", "KeyB", "KeyC", "Space"] }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_weight_ranking() {
+        let ranking = vec![item("KeyA", 100), item("KeyB", 50)];
+        let app = vec![item("KeyB", 50)];
+        let weighted = weight_ranking(&ranking, &[(3.0, app)]);
+        assert_eq!(
+            weighted,
+            vec![("KeyB".to_string(), 150.0), ("KeyA".to_string(), 100.0)]
+        );
+
+        let ignored = weight_ranking(&ranking, &[(0.0, vec![item("KeyA", 100)])]);
+        assert_eq!(ignored, vec![("KeyB".to_string(), 50.0)]);
+    }
+
+    #[test]
+    fn test_suggest_keymap() {
+        let weighted = weight_ranking(
+            &[
+                item("Space", 500),
+                item("KeyA", 300),
+                item("UpArrow", 200),
+                item("F5", 10),
+                item("Unknown(243)", 5),
+            ],
+            &[],
+        );
+        let suggestion = suggest_keymap(&tiny_layout(), &weighted, "test", "LAYOUT");
+        assert_eq!(
+            suggestion.base_layer,
+            vec!["KeyA", "UpArrow", "MO(1)", "Space"]
+        );
+        assert_eq!(suggestion.fn_layer, vec!["F5", "", "", ""]);
+        assert_eq!(suggestion.dropped_keys, vec!["KeyC", "KeyB"]);
+        assert_eq!(suggestion.unplaced_keys[0].key_code, "Unknown(243)");
+
+        let qmk: serde_json::Value = serde_json::from_str(&suggestion.qmk_json).unwrap();
+        assert_eq!(
+            qmk["layers"][0],
+            json!(["KC_A", "KC_UP", "MO(1)", "KC_SPC"])
+        );
+        assert_eq!(
+            qmk["layers"][1],
+            json!(["KC_F5", "KC_TRNS", "KC_TRNS", "KC_TRNS"])
+        );
+        assert!(suggestion
+            .zmk_keymap
+            .contains("&kp A &kp UP &mo 1 &kp SPACE"));
+        assert!(suggestion
+            .zmk_keymap
+            .contains("&kp F5 &trans &trans &trans"));
+        assert!(suggestion
+            .zmk_keymap
+            .starts_with("#include <behaviors.dtsi>\n#include <dt-bindings/zmk/keys.h>\n"));
+    }
+
+    #[test]
+    fn test_suggest_keymap_round_trips_through_simulation() {
+        // F5は空きがあっても0.1%未満なのでファンクションレイヤーに回る
+        let ranking = vec![item("KeyA", 10000), item("F5", 5)];
+        let suggestion = suggest_keymap(
+            &tiny_layout(),
+            &weight_ranking(&ranking, &[]),
+            "test",
+            "LAYOUT",
+        );
+        let keymap = crate::keymap::Keymap::parse(&suggestion.qmk_json).unwrap();
+        let report = crate::keymap::simulate_keymap(&keymap, &ranking, 1);
+        assert_eq!(suggestion.fn_layer[0], "F5");
+        assert_eq!(report.base_layer_presses, 10000);
+        assert_eq!(report.layer_presses, 5);
+        assert!(report.unreachable_keys.is_empty());
+    }
+}
@@ -0,0 +1,382 @@
+use crate::layout::{KeyGeometry, LayoutDefinition};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// 対応付けできなかったキー
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnmappedKey {
+    pub index: usize,
+    pub legend: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KleImport {
+    pub layout: LayoutDefinition,
+    pub unmapped: Vec<UnmappedKey>,
+}
+
+/// keyboard-layout-editor.com のraw data（またはダウンロードしたJSON）をレイアウトに変換する
+///
+/// `overrides` のキーは凡例文字列か `#<キー番号>`（0始まり、出現順）で、
+/// 値はkey_code（空文字列なら割り当てなし）。キー番号指定が凡例指定より優先される。
+pub fn parse_kle(
+    content: &str,
+    id: &str,
+    name: &str,
+    overrides: &HashMap<String, String>,
+) -> Result<KleImport> {
+    let rows: Value = serde_json::from_str(&quote_bare_keys(content))
+        .map_err(|e| anyhow!("Invalid KLE data: {}", e))?;
+    let rows = rows
+        .as_array()
+        .ok_or_else(|| anyhow!("Invalid KLE data: top level must be an array"))?;
+
+    let mut geometry = Vec::new();
+    let mut unmapped = Vec::new();
+    let mut used: HashSet<String> = HashSet::new();
+    let mut modifier_seen: HashMap<&'static str, usize> = HashMap::new();
+
+    let mut y = 0.0;
+    let mut index = 0;
+    for row in rows {
+        // 先頭のメタデータ（オブジェクト）は無視する
+        let row = match row.as_array() {
+            Some(row) => row,
+            None => continue,
+        };
+        let mut x = 0.0;
+        let (mut w, mut h, mut decal) = (1.0, 1.0, false);
+        for item in row {
+            if let Some(props) = item.as_object() {
+                let num = |k: &str| props.get(k).and_then(|v| v.as_f64());
+                x += num("x").unwrap_or(0.0);
+                y += num("y").unwrap_or(0.0);
+                w = num("w").unwrap_or(w);
+                h = num("h").unwrap_or(h);
+                decal = props.get("d").and_then(|v| v.as_bool()).unwrap_or(decal);
+                continue;
+            }
+            let legend = match item.as_str() {
+                Some(legend) => legend.to_string(),
+                None => continue,
+            };
+            if !decal {
+                let key_code = match overrides
+                    .get(&format!("#{}", index))
+                    .or_else(|| overrides.get(&legend))
+                {
+                    Some(code) if code.is_empty() => None,
+                    Some(code) => Some(code.clone()),
+                    None => map_legend(&legend, w, &used, &mut modifier_seen),
+                };
+                match &key_code {
+                    Some(code) => {
+                        used.insert(code.clone());
+                    }
+                    None => unmapped.push(UnmappedKey {
+                        index,
+                        legend: legend.clone(),
+                    }),
+                }
+                geometry.push(KeyGeometry {
+                    x,
+                    y,
+                    w,
+                    h,
+                    legend,
+                    key_code,
+                });
+                index += 1;
+            }
+            x += w;
+            w = 1.0;
+            h = 1.0;
+            decal = false;
+        }
+        y += 1.0;
+    }
+
+    if geometry.is_empty() {
+        return Err(anyhow!("Invalid KLE data: no keys found"));
+    }
+
+    let keys: BTreeSet<String> = geometry.iter().filter_map(|k| k.key_code.clone()).collect();
+    let layout = LayoutDefinition {
+        id: id.to_string(),
+        name: name.to_string(),
+        standard: Some("custom".to_string()),
+        form_factor: None,
+        split: false,
+        extends: None,
+        keys: keys.into_iter().collect(),
+        remove: Vec::new(),
+        geometry,
+        builtin: false,
+    };
+    Ok(KleImport { layout, unmapped })
+}
+
+/// KLEのraw dataはキーがクォートされていないので、JSONとして読めるように補う
+fn quote_bare_keys(content: &str) -> String {
+    let chars: Vec<char> = content.chars().collect();
+    let mut out = String::with_capacity(content.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if in_string {
+            out.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            i += 1;
+            continue;
+        }
+        if c == '"' {
+            in_string = true;
+        } else if (c.is_ascii_alphabetic() || c == '_')
+            && matches!(out.trim_end().chars().next_back(), Some('{') | Some(','))
+        {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let ident: String = chars[start..i].iter().collect();
+            let rest = chars[i..].iter().find(|c| !c.is_whitespace());
+            if rest == Some(&':') {
+                out.push_str(&format!("\"{}\"", ident));
+            } else {
+                out.push_str(&ident);
+            }
+            continue;
+        }
+        out.push(c);
+        i += 1;
+    }
+    out
+}
+
+/// 左右のある修飾キー（1回目が左、2回目以降が右）
+const SIDED_MODIFIERS: &[(&str, &[&str], &str, &str)] = &[
+    ("shift", &["shift", "⇧"], "ShiftLeft", "ShiftRight"),
+    (
+        "ctrl",
+        &["ctrl", "control", "ctl", "⌃"],
+        "ControlLeft",
+        "ControlRight",
+    ),
+    ("alt", &["alt", "option", "opt", "⌥"], "Alt", "AltGr"),
+    (
+        "meta",
+        &[
+            "win", "super", "meta", "cmd", "command", "gui", "os", "⌘", "◆",
+        ],
+        "MetaLeft",
+        "MetaRight",
+    ),
+];
+
+fn map_legend(
+    legend: &str,
+    width: f64,
+    used: &HashSet<String>,
+    modifier_seen: &mut HashMap<&'static str, usize>,
+) -> Option<String> {
+    let lines: Vec<String> = legend
+        .split('\n')
+        .map(|l| l.trim().to_lowercase())
+        .filter(|l| !l.is_empty() && !l.starts_with('<'))
+        .collect();
+
+    // 凡例のない幅広キーはスペースとみなす
+    if lines.is_empty() {
+        return if width >= 3.0 {
+            Some("Space".to_string())
+        } else {
+            None
+        };
+    }
+
+    for line in &lines {
+        for (group, names, left, right) in SIDED_MODIFIERS {
+            if names.contains(&line.as_str()) {
+                let seen = modifier_seen.entry(group).or_insert(0);
+                *seen += 1;
+                let code = if *seen == 1 { left } else { right };
+                return Some(code.to_string());
+            }
+        }
+        if let Some(code) = legend_key_code(line) {
+            // テンキーは同じ凡例が2回目に現れるのでKp側に割り当てる
+            if used.contains(&code) {
+                if let Some(kp) = keypad_key_code(line) {
+                    return Some(kp);
+                }
+            }
+            return Some(code);
+        }
+    }
+    None
+}
+
+fn legend_key_code(line: &str) -> Option<String> {
+    let mut chars = line.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c.is_ascii_lowercase() {
+            return Some(format!("Key{}", c.to_ascii_uppercase()));
+        }
+        if c.is_ascii_digit() {
+            return Some(format!("Num{}", c));
+        }
+    }
+    if let Some(n) = line.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+        if (1..=24).contains(&n) {
+            return Some(format!("F{}", n));
+        }
+    }
+    let code = match line {
+        "!" => "Num1",
+        "@" => "Num2",
+        "#" => "Num3",
+        "$" => "Num4",
+        "%" => "Num5",
+        "^" => "Num6",
+        "&" => "Num7",
+        "*" => "Num8",
+        "(" => "Num9",
+        ")" => "Num0",
+        "`" | "~" => "BackQuote",
+        "-" | "_" => "Minus",
+        "=" | "+" => "Equal",
+        "[" | "{" => "LeftBracket",
+        "]" | "}" => "RightBracket",
+        "\\" | "|" => "BackSlash",
+        ";" | ":" => "SemiColon",
+        "'" | "\"" => "Quote",
+        "," | "<" => "Comma",
+        "." | ">" => "Dot",
+        "/" | "?" => "Slash",
+        "esc" | "escape" => "Escape",
+        "tab" | "⇥" => "Tab",
+        "caps" | "caps lock" | "capslock" | "⇪" => "CapsLock",
+        "enter" | "return" | "↵" | "⏎" => "Return",
+        "backspace" | "back space" | "bksp" | "bspc" | "⌫" => "Backspace",
+        "space" | "spacebar" => "Space",
+        "del" | "delete" | "⌦" => "Delete",
+        "ins" | "insert" => "Insert",
+        "home" => "Home",
+        "end" => "End",
+        "pgup" | "pg up" | "page up" | "pageup" => "PageUp",
+        "pgdn" | "pg dn" | "page down" | "pagedown" => "PageDown",
+        "prtsc" | "prtscn" | "print" | "print screen" | "prt sc" => "PrintScreen",
+        "scroll" | "scrlk" | "scroll lock" => "ScrollLock",
+        "pause" | "break" | "pause break" => "Pause",
+        "num" | "num lock" | "numlock" => "NumLock",
+        "menu" | "app" | "apps" => "Apps",
+        "altgr" | "alt gr" => "AltGr",
+        "↑" | "up" => "UpArrow",
+        "↓" | "down" => "DownArrow",
+        "←" | "left" => "LeftArrow",
+        "→" | "right" => "RightArrow",
+        _ => return None,
+    };
+    Some(code.to_string())
+}
+
+fn keypad_key_code(line: &str) -> Option<String> {
+    let mut chars = line.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c.is_ascii_digit() {
+            return Some(format!("Kp{}", c));
+        }
+    }
+    let code = match line {
+        "/" => "KpDivide",
+        "*" => "KpMultiply",
+        "-" => "KpMinus",
+        "+" => "KpPlus",
+        "." | "del" => "KpDecimal",
+        "enter" | "return" => "KpReturn",
+        _ => return None,
+    };
+    Some(code.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+        [{name:"Sample"},
+        ["~\n`","!\n1","@\n2",{w:2},"Backspace"],
+        [{w:1.5},"Tab","Q","W",{x:0.5},"7\nHome"],
+        [{w:1.25},"Shift","Fn",{w:6.25},"",{w:1.25},"Shift",{d:true},"logo"],
+        ["1","*","Enter"]]
+    "#;
+
+    #[test]
+    fn test_parse_kle() {
+        let result = parse_kle(SAMPLE, "sample", "Sample", &HashMap::new()).unwrap();
+        let codes: Vec<Option<&str>> = result
+            .layout
+            .geometry
+            .iter()
+            .map(|k| k.key_code.as_deref())
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                Some("BackQuote"),
+                Some("Num1"),
+                Some("Num2"),
+                Some("Backspace"),
+                Some("Tab"),
+                Some("KeyQ"),
+                Some("KeyW"),
+                Some("Num7"),
+                Some("ShiftLeft"),
+                None,
+                Some("Space"),
+                Some("ShiftRight"),
+                Some("Kp1"),
+                Some("Num8"),
+                Some("Return"),
+            ]
+        );
+        assert_eq!(result.unmapped.len(), 1);
+        assert_eq!(result.unmapped[0].legend, "Fn");
+
+        let backspace = &result.layout.geometry[3];
+        assert_eq!((backspace.x, backspace.y, backspace.w), (3.0, 0.0, 2.0));
+        let seven = &result.layout.geometry[7];
+        assert_eq!((seven.x, seven.y), (4.0, 1.0));
+        assert!(result.layout.keys.contains(&"Space".to_string()));
+    }
+
+    #[test]
+    fn test_parse_kle_overrides() {
+        let mut overrides = HashMap::new();
+        overrides.insert("Fn".to_string(), "F13".to_string());
+        overrides.insert("#7".to_string(), "Kp7".to_string());
+        overrides.insert("#0".to_string(), String::new());
+        let result = parse_kle(SAMPLE, "sample", "Sample", &overrides).unwrap();
+        let geometry = &result.layout.geometry;
+        assert_eq!(geometry[9].key_code.as_deref(), Some("F13"));
+        assert_eq!(geometry[7].key_code.as_deref(), Some("Kp7"));
+        assert_eq!(geometry[0].key_code, None);
+        assert_eq!(result.unmapped[0].index, 0);
+    }
+
+    #[test]
+    fn test_parse_kle_invalid() {
+        assert!(parse_kle("{}", "x", "X", &HashMap::new()).is_err());
+        assert!(parse_kle("[[]]", "x", "X", &HashMap::new()).is_err());
+    }
+}
@@ -0,0 +1,309 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// アプリに同梱する標準レイアウト定義
+const BUILTIN_LAYOUTS: &str = include_str!("../layouts/builtin.json");
+
+/// 物理レイアウトの定義（どのkey_codeが物理キーとして存在するか）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LayoutDefinition {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub standard: Option<String>,
+    #[serde(default)]
+    pub form_factor: Option<String>,
+    #[serde(default)]
+    pub split: bool,
+    /// 継承元レイアウトのid（keysを追加、removeを削除して解決する）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    #[serde(default)]
+    pub keys: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove: Vec<String>,
+    /// キーの物理配置（KLEから取り込んだレイアウトのみ）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub geometry: Vec<KeyGeometry>,
+    #[serde(default, skip_deserializing)]
+    pub builtin: bool,
+}
+
+/// 1キー分の位置とサイズ（単位はキー幅 = 1u）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyGeometry {
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+    pub legend: String,
+    pub key_code: Option<String>,
+}
+
+impl LayoutDefinition {
+    pub fn key_set(&self) -> HashSet<&str> {
+        self.keys.iter().map(|k| k.as_str()).collect()
+    }
+}
+
+/// 定義ファイルは単体の定義か `layouts` 配列のどちらでもよい
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LayoutFile {
+    Many { layouts: Vec<LayoutDefinition> },
+    One(LayoutDefinition),
+}
+
+impl LayoutFile {
+    fn into_definitions(self) -> Vec<LayoutDefinition> {
+        match self {
+            LayoutFile::Many { layouts } => layouts,
+            LayoutFile::One(layout) => vec![layout],
+        }
+    }
+}
+
+/// JSON / TOML文字列からレイアウト定義を読み込む
+pub fn parse_layouts(content: &str, extension: &str) -> Result<Vec<LayoutDefinition>> {
+    let file: LayoutFile = match extension {
+        "json" => serde_json::from_str(content)?,
+        "toml" => toml::from_str(content)?,
+        _ => return Err(anyhow!("Unsupported layout file type: {}", extension)),
+    };
+    Ok(file.into_definitions())
+}
+
+pub struct LayoutCatalog {
+    layouts: Vec<LayoutDefinition>,
+}
+
+impl LayoutCatalog {
+    /// 同梱の標準レイアウトのみのカタログ
+    pub fn builtin() -> Self {
+        Self::from_definitions(builtin_definitions())
+    }
+
+    /// 標準レイアウトに、ディレクトリ内のカスタム定義（*.json / *.toml）を加えたカタログ
+    /// 同じidのカスタム定義は標準レイアウトを上書きする
+    pub fn load(custom_dir: &Path) -> Self {
+        let mut definitions = builtin_definitions();
+        definitions.extend(load_custom_definitions(custom_dir));
+        Self::from_definitions(definitions)
+    }
+
+    /// extendsを解決してカタログを作る（解決できない定義はスキップ）
+    pub fn from_definitions(definitions: Vec<LayoutDefinition>) -> Self {
+        let mut order: Vec<String> = Vec::new();
+        let mut raw: HashMap<String, LayoutDefinition> = HashMap::new();
+        for def in definitions {
+            if !raw.contains_key(&def.id) {
+                order.push(def.id.clone());
+            }
+            raw.insert(def.id.clone(), def);
+        }
+
+        let mut layouts = Vec::new();
+        for id in &order {
+            match resolve_keys(id, &raw, &mut Vec::new()) {
+                Ok(keys) => {
+                    let mut layout = raw[id].clone();
+                    layout.keys = keys.into_iter().collect();
+                    layout.extends = None;
+                    layout.remove.clear();
+                    layouts.push(layout);
+                }
+                Err(e) => {
+                    eprintln!("[KeyFit] Skipped layout '{}': {}", id, e);
+                }
+            }
+        }
+        Self { layouts }
+    }
+
+    pub fn layouts(&self) -> &[LayoutDefinition] {
+        &self.layouts
+    }
+
+    pub fn get(&self, id: &str) -> Option<&LayoutDefinition> {
+        self.layouts.iter().find(|l| l.id == id)
+    }
+}
+
+fn builtin_definitions() -> Vec<LayoutDefinition> {
+    let mut definitions =
+        parse_layouts(BUILTIN_LAYOUTS, "json").expect("Invalid builtin layout definitions");
+    for def in &mut definitions {
+        def.builtin = true;
+    }
+    definitions
+}
+
+fn load_custom_definitions(dir: &Path) -> Vec<LayoutDefinition> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut paths: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    paths.sort();
+
+    let mut definitions = Vec::new();
+    for path in paths {
+        let extension = match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) => ext.to_lowercase(),
+            None => continue,
+        };
+        if extension != "json" && extension != "toml" {
+            continue;
+        }
+        let parsed = fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| parse_layouts(&content, &extension));
+        match parsed {
+            Ok(defs) => definitions.extend(defs),
+            Err(e) => {
+                eprintln!(
+                    "[KeyFit] Failed to load layout file {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+    definitions
+}
+
+fn resolve_keys(
+    id: &str,
+    raw: &HashMap<String, LayoutDefinition>,
+    visiting: &mut Vec<String>,
+) -> Result<BTreeSet<String>> {
+    if visiting.iter().any(|v| v == id) {
+        return Err(anyhow!(
+            "Circular extends: {} -> {}",
+            visiting.join(" -> "),
+            id
+        ));
+    }
+    let def = raw
+        .get(id)
+        .ok_or_else(|| anyhow!("Unknown layout: {}", id))?;
+
+    visiting.push(id.to_string());
+    let mut keys = match &def.extends {
+        Some(parent) => resolve_keys(parent, raw, visiting)?,
+        None => BTreeSet::new(),
+    };
+    visiting.pop();
+
+    keys.extend(def.keys.iter().cloned());
+    for key in &def.remove {
+        keys.remove(key);
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_builtin_catalog() {
+        let catalog = LayoutCatalog::builtin();
+        for id in ["ansi-60", "iso-tkl", "jis-full", "ortho-40", "split-36"] {
+            assert!(catalog.get(id).is_some(), "missing {}", id);
+        }
+        assert!(catalog.layouts().iter().all(|l| l.builtin));
+
+        let ansi_60 = catalog.get("ansi-60").unwrap().key_set();
+        let iso_60 = catalog.get("iso-60").unwrap().key_set();
+        assert!(ansi_60.is_subset(&iso_60));
+        assert!(iso_60.contains("IntlBackslash"));
+        assert_eq!(catalog.get("split-36").unwrap().keys.len(), 36);
+    }
+
+    #[test]
+    fn test_builtin_key_counts() {
+        let expected = [
+            ("ansi-40", 43),
+            ("ansi-60", 61),
+            ("ansi-65", 69),
+            ("ansi-75", 84),
+            ("ansi-tkl", 87),
+            ("ansi-full", 104),
+            ("iso-60", 62),
+            ("iso-65", 70),
+            ("iso-75", 85),
+            ("iso-tkl", 88),
+            ("iso-full", 105),
+            ("jis-60", 67),
+            ("jis-65", 74),
+            ("jis-75", 89),
+            ("jis-tkl", 92),
+            ("jis-full", 109),
+            ("ortho-40", 45),
+            ("ortho-50", 56),
+            ("split-36", 36),
+            ("split-42", 42),
+        ];
+        let catalog = LayoutCatalog::builtin();
+        assert_eq!(catalog.layouts().len(), expected.len());
+        for (id, count) in expected {
+            let layout = catalog.get(id).unwrap_or_else(|| panic!("missing {}", id));
+            assert_eq!(layout.keys.len(), count, "{}", id);
+        }
+    }
+
+    #[test]
+    fn test_load_custom_layouts() {
+        let dir = TempDir::new().unwrap();
+        fs::write(
+            dir.path().join("mine.toml"),
+            r#"
+id = "my-60"
+name = "My 60%"
+extends = "ansi-60"
+keys = ["UpArrow"]
+remove = ["CapsLock"]
+"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("override.json"),
+            r#"{ "layouts": [{ "id": "ansi-40", "name": "Tiny", "keys": ["Space"] }] }"#,
+        )
+        .unwrap();
+        fs::write(dir.path().join("broken.json"), "{ not json").unwrap();
+
+        let catalog = LayoutCatalog::load(dir.path());
+        let mine = catalog.get("my-60").unwrap();
+        assert!(!mine.builtin);
+        assert!(mine.keys.contains(&"UpArrow".to_string()));
+        assert!(!mine.keys.contains(&"CapsLock".to_string()));
+        assert!(mine.keys.contains(&"Num1".to_string()));
+
+        // ansi-40の上書きはansi-60以降の継承にも反映される
+        assert_eq!(catalog.get("ansi-40").unwrap().keys, vec!["Space"]);
+        assert!(!catalog.get("ansi-60").unwrap().key_set().contains("KeyA"));
+    }
+
+    #[test]
+    fn test_circular_extends_is_skipped() {
+        let defs = parse_layouts(
+            r#"{ "layouts": [
+                { "id": "a", "name": "A", "extends": "b" },
+                { "id": "b", "name": "B", "extends": "a" },
+                { "id": "c", "name": "C", "keys": ["KeyC"] }
+            ] }"#,
+            "json",
+        )
+        .unwrap();
+        let catalog = LayoutCatalog::from_definitions(defs);
+        assert!(catalog.get("a").is_none());
+        assert!(catalog.get("b").is_none());
+        assert!(catalog.get("c").is_some());
+    }
+}
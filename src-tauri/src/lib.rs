@@ -1,14 +1,43 @@
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
-use crate::db::{AppInfo, Database, DateRange, KeyRankingItem};
-use crate::keyboard::KeyboardHook;
+use crate::db::{
+    AppInfo, Database, DateRange, DeviceInfo, HourlyKeyCount, KeyBigramItem, KeyDwellItem,
+    KeyRankingItem, PurgeMode, PurgeReport, ShortcutRankingItem, StatFilter,
+};
+use crate::exclusion::{ExclusionPattern, PatternKind};
+use crate::experiment::{Experiment, ExperimentReport, ExperimentSpec, PeriodStats};
+use crate::formfactor::{FormFactorScore, HeatmapKey};
+use crate::inventory::{Keyboard, KeyboardPeriod, KeyboardSpec, WearReport};
+use crate::keyboard::{BigramCapture, HookLatency, KeyboardHook, MonitoringState};
+use crate::keymap::{Keymap, KeymapReport};
+use crate::keymapgen::KeymapSuggestion;
+use crate::kle::KleImport;
+use crate::layout::{LayoutCatalog, LayoutDefinition};
+use crate::taphold::TapHoldReport;
+use std::collections::HashMap;
 use std::fs;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tauri::{AppHandle, Emitter, Manager, State};
+use tauri_plugin_store::StoreExt;
 
 mod appinfo;
 mod db;
 mod dialog;
+mod exclusion;
+mod experiment;
+mod foreground;
+mod formfactor;
+mod input;
+mod inventory;
 mod keyboard;
+mod keymap;
+mod keymapgen;
+mod kle;
+mod layout;
+mod migration;
+mod taphold;
+#[cfg(test)]
+mod testutil;
 mod tray;
 
 #[tauri::command]
@@ -17,53 +46,612 @@ fn get_monitoring_status(state: State<'_, Arc<KeyboardHook>>) -> bool {
 }
 
 #[tauri::command]
-fn toggle_monitoring(app: AppHandle, keyboard_hook: tauri::State<Arc<KeyboardHook>>) -> bool {
-    let running = keyboard_hook.toggle(&app);
+fn get_monitoring_state(state: State<'_, Arc<KeyboardHook>>) -> MonitoringState {
+    state.state()
+}
 
-    // トレイメニューを再生成してセット
-    if let Some(tray) = tray::TRAY_ICON.lock().unwrap().as_mut() {
-        if let Ok(menu) = tray::build_tray_menu(&app, running) {
-            let _ = tray.set_menu(Some(menu));
-        }
-    }
+/// キーフックのコールバックにかかった時間の統計
+#[tauri::command]
+fn get_hook_latency(state: State<'_, Arc<KeyboardHook>>) -> HookLatency {
+    state.latency()
+}
+
+#[tauri::command]
+fn toggle_monitoring(keyboard_hook: tauri::State<Arc<KeyboardHook>>) -> Result<bool, String> {
+    let state = keyboard_hook.toggle().map_err(|e| e.to_string())?;
+    Ok(state == MonitoringState::Running)
+}
 
-    app.emit("monitoring_status_changed", running).unwrap();
-    running
+/// 監視状態の変化をトレイと画面に反映する
+fn notify_monitoring_state(app: &AppHandle, state: MonitoringState) {
+    tray::update_tray_menu(app, state);
+    let _ = app.emit("monitoring_state_changed", state);
+    let _ = app.emit(
+        "monitoring_status_changed",
+        state == MonitoringState::Running,
+    );
 }
 
 #[tauri::command]
 fn get_key_ranking(
     db_state: State<'_, Arc<Database>>,
-    start_date: Option<i64>,
-    end_date: Option<i64>,
-    app_id: Option<i64>,
+    filter: StatFilter,
     limit: Option<i64>,
+    include_repeats: Option<bool>,
 ) -> Result<Vec<KeyRankingItem>, String> {
     db_state
-        .get_key_ranking(start_date, end_date, app_id, limit)
+        .get_key_ranking(&filter, limit, include_repeats.unwrap_or(false))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_bigram_ranking(
+    db_state: State<'_, Arc<Database>>,
+    filter: StatFilter,
+    limit: Option<i64>,
+) -> Result<Vec<KeyBigramItem>, String> {
+    db_state
+        .get_bigram_ranking(&filter, limit)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_shortcut_ranking(
+    db_state: State<'_, Arc<Database>>,
+    filter: StatFilter,
+    limit: Option<i64>,
+) -> Result<Vec<ShortcutRankingItem>, String> {
+    db_state
+        .get_shortcut_ranking(&filter, limit)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_key_dwell_histogram(
+    db_state: State<'_, Arc<Database>>,
+    filter: StatFilter,
+    key_code: Option<String>,
+) -> Result<Vec<KeyDwellItem>, String> {
+    db_state
+        .get_key_dwell_histogram(&filter, key_code)
         .map_err(|e| e.to_string())
 }
 
+/// ホームロウモッドの誤爆リスク（タッピングタームごと）
+#[tauri::command]
+fn get_tap_hold_report(
+    db_state: State<'_, Arc<Database>>,
+    filter: StatFilter,
+) -> Result<Vec<TapHoldReport>, String> {
+    let items = db_state
+        .get_tap_hold_stats(&filter)
+        .map_err(|e| e.to_string())?;
+    Ok(taphold::build_report(items))
+}
+
+/// 設定を保存するストア（フロントエンドと共有）
+const SETTINGS_STORE: &str = "store.json";
+const BIGRAM_CAPTURE_KEY: &str = "bigramCapture";
+
+#[tauri::command]
+fn get_bigram_capture(keyboard_hook: State<'_, Arc<KeyboardHook>>) -> BigramCapture {
+    keyboard_hook.bigram_capture()
+}
+
+#[tauri::command]
+fn set_bigram_capture(
+    app: AppHandle,
+    keyboard_hook: State<'_, Arc<KeyboardHook>>,
+    enabled: bool,
+    max_gap_ms: Option<u64>,
+) -> Result<BigramCapture, String> {
+    let capture = BigramCapture {
+        enabled,
+        max_gap_ms: max_gap_ms.unwrap_or(keyboard_hook.bigram_capture().max_gap_ms),
+    };
+    keyboard_hook.set_bigram_capture(capture);
+    let store = app.store(SETTINGS_STORE).map_err(|e| e.to_string())?;
+    store.set(
+        BIGRAM_CAPTURE_KEY,
+        serde_json::to_value(capture).map_err(|e| e.to_string())?,
+    );
+    store.save().map_err(|e| e.to_string())?;
+    Ok(capture)
+}
+
 #[tauri::command]
 fn get_apps(db_state: State<'_, Arc<Database>>) -> Result<Vec<AppInfo>, String> {
     db_state.get_apps().map_err(|e| e.to_string())
 }
 
+/// 押下を記録したことのあるキーボード（統計の device_id で絞り込める）
 #[tauri::command]
-fn get_total_key_count(
+fn get_devices(db_state: State<'_, Arc<Database>>) -> Result<Vec<DeviceInfo>, String> {
+    db_state.get_devices().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_keyboards(db_state: State<'_, Arc<Database>>) -> Result<Vec<Keyboard>, String> {
+    db_state.get_keyboards().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn add_keyboard(db_state: State<'_, Arc<Database>>, keyboard: KeyboardSpec) -> Result<i64, String> {
+    db_state.add_keyboard(&keyboard).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn update_keyboard(
+    db_state: State<'_, Arc<Database>>,
+    keyboard_id: i64,
+    keyboard: KeyboardSpec,
+) -> Result<(), String> {
+    db_state
+        .update_keyboard(keyboard_id, &keyboard)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn remove_keyboard(db_state: State<'_, Arc<Database>>, keyboard_id: i64) -> Result<(), String> {
+    db_state
+        .remove_keyboard(keyboard_id)
+        .map_err(|e| e.to_string())
+}
+
+/// デバイスの押下を自動でキーボードに割り当てる（keyboard_id が None なら外す）
+#[tauri::command]
+fn set_device_keyboard(
+    db_state: State<'_, Arc<Database>>,
+    device_id: i64,
+    keyboard_id: Option<i64>,
+) -> Result<(), String> {
+    db_state
+        .set_device_keyboard(device_id, keyboard_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_keyboard_periods(
     db_state: State<'_, Arc<Database>>,
+    keyboard_id: i64,
+) -> Result<Vec<KeyboardPeriod>, String> {
+    db_state
+        .get_keyboard_periods(keyboard_id)
+        .map_err(|e| e.to_string())
+}
+
+/// デバイスを区別できない期間の押下をキーボードに割り当てる
+#[tauri::command]
+fn add_keyboard_period(
+    db_state: State<'_, Arc<Database>>,
+    keyboard_id: i64,
+    start_date: i64,
+    end_date: Option<i64>,
+) -> Result<KeyboardPeriod, String> {
+    let id = db_state
+        .add_keyboard_period(keyboard_id, start_date, end_date)
+        .map_err(|e| e.to_string())?;
+    Ok(KeyboardPeriod {
+        id,
+        keyboard_id,
+        start_date,
+        end_date,
+    })
+}
+
+#[tauri::command]
+fn remove_keyboard_period(db_state: State<'_, Arc<Database>>, id: i64) -> Result<(), String> {
+    db_state
+        .remove_keyboard_period(id)
+        .map_err(|e| e.to_string())
+}
+
+/// キーごとのスイッチの残り寿命
+#[tauri::command]
+fn get_keyboard_wear_report(
+    app: AppHandle,
+    db_state: State<'_, Arc<Database>>,
+    keyboard_id: i64,
+) -> Result<WearReport, String> {
+    let keyboard = db_state
+        .get_keyboards()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|k| k.id == keyboard_id)
+        .ok_or_else(|| format!("Unknown keyboard: {keyboard_id}"))?;
+    let catalog = LayoutCatalog::load(&layouts_dir(&app)?);
+    let layout = keyboard
+        .spec
+        .layout_id
+        .as_ref()
+        .and_then(|layout_id| catalog.get(layout_id));
+    let ranking = db_state
+        .get_keyboard_key_ranking(keyboard_id)
+        .map_err(|e| e.to_string())?;
+    let days = db_state
+        .get_keyboard_active_day_count(keyboard_id)
+        .map_err(|e| e.to_string())?;
+    Ok(inventory::build_wear_report(
+        &keyboard, layout, &ranking, days,
+    ))
+}
+
+#[tauri::command]
+fn get_experiments(db_state: State<'_, Arc<Database>>) -> Result<Vec<Experiment>, String> {
+    db_state.get_experiments().map_err(|e| e.to_string())
+}
+
+/// 分割キーボードや新しいレイアウトを試す期間と、比較の基準にする期間を登録する
+#[tauri::command]
+fn add_experiment(
+    db_state: State<'_, Arc<Database>>,
+    experiment: ExperimentSpec,
+) -> Result<Experiment, String> {
+    let id = db_state
+        .add_experiment(&experiment)
+        .map_err(|e| e.to_string())?;
+    Ok(Experiment {
+        id,
+        spec: experiment,
+    })
+}
+
+#[tauri::command]
+fn update_experiment(
+    db_state: State<'_, Arc<Database>>,
+    experiment_id: i64,
+    experiment: ExperimentSpec,
+) -> Result<(), String> {
+    db_state
+        .update_experiment(experiment_id, &experiment)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn remove_experiment(db_state: State<'_, Arc<Database>>, experiment_id: i64) -> Result<(), String> {
+    db_state
+        .remove_experiment(experiment_id)
+        .map_err(|e| e.to_string())
+}
+
+fn period_stats(db_state: &Database, filter: &StatFilter) -> Result<PeriodStats, String> {
+    Ok(PeriodStats {
+        ranking: db_state
+            .get_key_ranking(filter, None, false)
+            .map_err(|e| e.to_string())?,
+        total: db_state
+            .get_total_key_count(filter, false)
+            .map_err(|e| e.to_string())?,
+        days: db_state
+            .get_active_day_count(filter)
+            .map_err(|e| e.to_string())?,
+    })
+}
+
+/// 実験期間を基準期間と比べる（filter の期間は無視し、アプリ・デバイス・時刻の絞り込みを両方の期間に適用する）
+#[tauri::command]
+fn get_experiment_report(
+    db_state: State<'_, Arc<Database>>,
+    experiment_id: i64,
+    filter: StatFilter,
+) -> Result<ExperimentReport, String> {
+    let experiment = db_state
+        .get_experiments()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|e| e.id == experiment_id)
+        .ok_or_else(|| format!("Unknown experiment: {experiment_id}"))?;
+    let baseline = period_stats(
+        &db_state,
+        &StatFilter {
+            start_date: Some(experiment.spec.baseline_start_date),
+            end_date: Some(experiment.spec.baseline_end_date),
+            ..filter.clone()
+        },
+    )?;
+    let experiment_stats = period_stats(
+        &db_state,
+        &StatFilter {
+            start_date: Some(experiment.spec.start_date),
+            end_date: experiment.spec.end_date,
+            ..filter
+        },
+    )?;
+    Ok(experiment::build_report(
+        experiment_id,
+        &baseline,
+        &experiment_stats,
+    ))
+}
+
+#[tauri::command]
+fn get_excluded_apps(db_state: State<'_, Arc<Database>>) -> Result<Vec<AppInfo>, String> {
+    db_state.get_excluded_apps().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn add_excluded_app(
+    db_state: State<'_, Arc<Database>>,
+    keyboard_hook: State<'_, Arc<KeyboardHook>>,
+    app_id: i64,
+) -> Result<(), String> {
+    db_state
+        .add_excluded_app(app_id)
+        .map_err(|e| e.to_string())?;
+    keyboard_hook.reload_exclusions().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn remove_excluded_app(
+    db_state: State<'_, Arc<Database>>,
+    keyboard_hook: State<'_, Arc<KeyboardHook>>,
+    app_id: i64,
+) -> Result<(), String> {
+    db_state
+        .remove_excluded_app(app_id)
+        .map_err(|e| e.to_string())?;
+    keyboard_hook.reload_exclusions().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_excluded_patterns(
+    db_state: State<'_, Arc<Database>>,
+) -> Result<Vec<ExclusionPattern>, String> {
+    db_state.get_excluded_patterns().map_err(|e| e.to_string())
+}
+
+/// バンドルパス（Windowsでは実行ファイルのパス）のglob/正規表現で除外する
+#[tauri::command]
+fn add_excluded_pattern(
+    db_state: State<'_, Arc<Database>>,
+    keyboard_hook: State<'_, Arc<KeyboardHook>>,
+    kind: PatternKind,
+    pattern: String,
+) -> Result<ExclusionPattern, String> {
+    exclusion::validate(kind, &pattern).map_err(|e| e.to_string())?;
+    let id = db_state
+        .add_excluded_pattern(kind, &pattern)
+        .map_err(|e| e.to_string())?;
+    keyboard_hook
+        .reload_exclusions()
+        .map_err(|e| e.to_string())?;
+    Ok(ExclusionPattern { id, kind, pattern })
+}
+
+#[tauri::command]
+fn remove_excluded_pattern(
+    db_state: State<'_, Arc<Database>>,
+    keyboard_hook: State<'_, Arc<KeyboardHook>>,
+    id: i64,
+) -> Result<(), String> {
+    db_state
+        .remove_excluded_pattern(id)
+        .map_err(|e| e.to_string())?;
+    keyboard_hook.reload_exclusions().map_err(|e| e.to_string())
+}
+
+/// アプリの履歴を削除または匿名化する（dry_run では件数のみ返す）
+#[tauri::command]
+fn purge_app_data(
+    db_state: State<'_, Arc<Database>>,
+    keyboard_hook: State<'_, Arc<KeyboardHook>>,
+    app_id: i64,
     start_date: Option<i64>,
     end_date: Option<i64>,
-    app_id: Option<i64>,
+    mode: PurgeMode,
+    dry_run: bool,
+    vacuum: Option<bool>,
+) -> Result<PurgeReport, String> {
+    if dry_run {
+        return db_state
+            .purge_app_data(app_id, start_date, end_date, mode, true)
+            .map_err(|e| e.to_string());
+    }
+    // バッファに残っている分も対象にし、消したアプリの記録は止める
+    let report = keyboard_hook
+        .purge_app(app_id, || {
+            db_state.purge_app_data(app_id, start_date, end_date, mode, false)
+        })
+        .map_err(|e| e.to_string())?;
+    // 削除した内容がファイルに残らないようにする（その間は記録の書き込みも待つ）
+    if vacuum.unwrap_or(false) {
+        db_state.vacuum().map_err(|e| e.to_string())?;
+    }
+    Ok(report)
+}
+
+#[tauri::command]
+fn get_total_key_count(
+    db_state: State<'_, Arc<Database>>,
+    filter: StatFilter,
+    include_repeats: Option<bool>,
 ) -> Result<i64, String> {
     db_state
-        .get_total_key_count(start_date, end_date, app_id)
+        .get_total_key_count(&filter, include_repeats.unwrap_or(false))
+        .map_err(|e| e.to_string())
+}
+
+/// 曜日×時刻ごとの押下数
+#[tauri::command]
+fn get_hourly_key_counts(
+    db_state: State<'_, Arc<Database>>,
+    filter: StatFilter,
+    include_repeats: Option<bool>,
+) -> Result<Vec<HourlyKeyCount>, String> {
+    db_state
+        .get_hourly_key_counts(&filter, include_repeats.unwrap_or(false))
         .map_err(|e| e.to_string())
 }
 
+/// カスタムレイアウト定義を置くディレクトリ
+fn layouts_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .resolve("layouts", tauri::path::BaseDirectory::AppData)
+        .map_err(|e| format!("Failed to resolve layouts dir: {e}"))
+}
+
+#[tauri::command]
+fn get_layouts(app: AppHandle) -> Result<Vec<LayoutDefinition>, String> {
+    let catalog = LayoutCatalog::load(&layouts_dir(&app)?);
+    Ok(catalog.layouts().to_vec())
+}
+
+#[tauri::command]
+fn get_form_factor_scores(
+    app: AppHandle,
+    db_state: State<'_, Arc<Database>>,
+    filter: StatFilter,
+    standard: Option<String>,
+) -> Result<Vec<FormFactorScore>, String> {
+    let catalog = LayoutCatalog::load(&layouts_dir(&app)?);
+    let ranking = db_state
+        .get_key_ranking(&filter, None, false)
+        .map_err(|e| e.to_string())?;
+    let days = db_state
+        .get_active_day_count(&filter)
+        .map_err(|e| e.to_string())?;
+    let layouts = catalog
+        .layouts()
+        .iter()
+        .filter(|l| standard.is_none() || l.standard == standard);
+    Ok(formfactor::score_layouts(layouts, &ranking, days))
+}
+
 #[tauri::command]
-fn get_key_stat_date_range(state: State<'_, Arc<Database>>) -> Result<DateRange, String> {
-    state.get_date_range().map_err(|e| e.to_string())
+fn get_layout_coverage(
+    app: AppHandle,
+    db_state: State<'_, Arc<Database>>,
+    layout_id: String,
+    filter: StatFilter,
+) -> Result<FormFactorScore, String> {
+    let catalog = LayoutCatalog::load(&layouts_dir(&app)?);
+    let layout = catalog
+        .get(&layout_id)
+        .ok_or_else(|| format!("Unknown layout: {layout_id}"))?;
+    let ranking = db_state
+        .get_key_ranking(&filter, None, false)
+        .map_err(|e| e.to_string())?;
+    let days = db_state
+        .get_active_day_count(&filter)
+        .map_err(|e| e.to_string())?;
+    Ok(formfactor::score_layout(layout, &ranking, days))
+}
+
+#[tauri::command]
+fn get_layout_heatmap(
+    app: AppHandle,
+    db_state: State<'_, Arc<Database>>,
+    layout_id: String,
+    filter: StatFilter,
+) -> Result<Vec<HeatmapKey>, String> {
+    let catalog = LayoutCatalog::load(&layouts_dir(&app)?);
+    let layout = catalog
+        .get(&layout_id)
+        .ok_or_else(|| format!("Unknown layout: {layout_id}"))?;
+    if layout.geometry.is_empty() {
+        return Err(format!("Layout has no key geometry: {layout_id}"));
+    }
+    let ranking = db_state
+        .get_key_ranking(&filter, None, false)
+        .map_err(|e| e.to_string())?;
+    Ok(formfactor::layout_heatmap(layout, &ranking))
+}
+
+#[tauri::command]
+fn import_kle_layout(
+    app: AppHandle,
+    import_path: String,
+    layout_id: String,
+    name: String,
+    overrides: Option<HashMap<String, String>>,
+) -> Result<KleImport, String> {
+    if layout_id.is_empty()
+        || !layout_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!("Invalid layout id: {layout_id}"));
+    }
+    let content =
+        fs::read_to_string(&import_path).map_err(|e| format!("Failed to read KLE file: {e}"))?;
+    let result = kle::parse_kle(&content, &layout_id, &name, &overrides.unwrap_or_default())
+        .map_err(|e| e.to_string())?;
+
+    // カスタムレイアウトとして保存し、カタログから参照できるようにする
+    let dir = layouts_dir(&app)?;
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create layouts dir: {e}"))?;
+    let json = serde_json::to_string_pretty(&result.layout).map_err(|e| e.to_string())?;
+    fs::write(dir.join(format!("{layout_id}.json")), json)
+        .map_err(|e| format!("Failed to save layout: {e}"))?;
+    Ok(result)
+}
+
+#[tauri::command]
+fn simulate_keymap(
+    db_state: State<'_, Arc<Database>>,
+    import_path: String,
+    filter: StatFilter,
+) -> Result<KeymapReport, String> {
+    let content =
+        fs::read_to_string(&import_path).map_err(|e| format!("Failed to read keymap: {e}"))?;
+    let keymap = Keymap::parse(&content).map_err(|e| e.to_string())?;
+    let ranking = db_state
+        .get_key_ranking(&filter, None, false)
+        .map_err(|e| e.to_string())?;
+    let days = db_state
+        .get_active_day_count(&filter)
+        .map_err(|e| e.to_string())?;
+    Ok(keymap::simulate_keymap(&keymap, &ranking, days))
+}
+
+/// app_weights でアプリごとに重みを付ける（filter のアプリ指定は使わない）
+#[tauri::command]
+fn suggest_keymap(
+    app: AppHandle,
+    db_state: State<'_, Arc<Database>>,
+    layout_id: String,
+    filter: StatFilter,
+    app_weights: Option<HashMap<i64, f64>>,
+    keyboard: Option<String>,
+    layout_macro: Option<String>,
+) -> Result<KeymapSuggestion, String> {
+    let catalog = LayoutCatalog::load(&layouts_dir(&app)?);
+    let layout = catalog
+        .get(&layout_id)
+        .ok_or_else(|| format!("Unknown layout: {layout_id}"))?;
+    let filter = StatFilter {
+        app_id: None,
+        ..filter
+    };
+    let ranking = db_state
+        .get_key_ranking(&filter, None, false)
+        .map_err(|e| e.to_string())?;
+    let mut app_rankings = Vec::new();
+    for (app_id, weight) in app_weights.unwrap_or_default() {
+        let app_filter = StatFilter {
+            app_id: Some(app_id),
+            ..filter.clone()
+        };
+        let app_ranking = db_state
+            .get_key_ranking(&app_filter, None, false)
+            .map_err(|e| e.to_string())?;
+        app_rankings.push((weight, app_ranking));
+    }
+    let weighted = keymapgen::weight_ranking(&ranking, &app_rankings);
+    Ok(keymapgen::suggest_keymap(
+        layout,
+        &weighted,
+        keyboard.as_deref().unwrap_or(""),
+        layout_macro.as_deref().unwrap_or("LAYOUT"),
+    ))
+}
+
+#[tauri::command]
+fn get_key_stat_date_range(
+    state: State<'_, Arc<Database>>,
+    filter: StatFilter,
+) -> Result<DateRange, String> {
+    state.get_date_range(&filter).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -117,12 +705,52 @@ pub fn run() {
             if let Some(parent) = db_path.parent() {
                 std::fs::create_dir_all(parent).expect("Failed to create DB directory");
             }
-            let db = Arc::new(db::Database::new(&db_path).unwrap());
-            let keyboard_hook = Arc::new(keyboard::KeyboardHook::new(db.clone()));
+            if let Ok(dir) = layouts_dir(app.handle()) {
+                let _ = std::fs::create_dir_all(dir);
+            }
+            let db = match db::Database::new(&db_path) {
+                Ok(db) => Arc::new(db),
+                Err(e) => {
+                    dialog::show_error(
+                        app.handle(),
+                        &format!("Failed to open database: {e}"),
+                        Some("KeyFit Error"),
+                    );
+                    return Err(e.into());
+                }
+            };
+            // 入力元と前面アプリの取得方法はここで選ぶ（デバイスやIPCソケットを開く）
+            let keyboard_hook = Arc::new(keyboard::KeyboardHook::new(
+                db.clone(),
+                input::system_source(),
+                appinfo::system_provider(),
+            ));
             let app_handle = app.handle();
 
+            // 保存済みのバイグラム記録設定を反映
+            if let Some(capture) = app_handle
+                .store(SETTINGS_STORE)
+                .ok()
+                .and_then(|store| store.get(BIGRAM_CAPTURE_KEY))
+                .and_then(|value| serde_json::from_value::<BigramCapture>(value).ok())
+            {
+                keyboard_hook.set_bigram_capture(capture);
+            }
+
+            // 状態の変化はこの通知先からトレイと画面に伝える
+            let notify_handle = app_handle.clone();
+            keyboard_hook
+                .on_state_change(move |state| notify_monitoring_state(&notify_handle, state));
+            let error_handle = app_handle.clone();
+            // 起動処理やトレイの操作から呼ばれることもあるので、ダイアログの終了は待たない
+            keyboard_hook.on_error(move |message| {
+                dialog::notify_error(&error_handle, message, Some("KeyFit Error"))
+            });
+
             // 起動時に監視開始
-            keyboard_hook.start(&app_handle).ok();
+            if let Err(e) = keyboard_hook.start() {
+                eprintln!("[KeyFit] Failed to start monitoring: {}", e);
+            }
 
             // トレイ初期化
             tray::init_tray(&app_handle, keyboard_hook.clone())?;
@@ -148,11 +776,49 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .invoke_handler(tauri::generate_handler![
             get_monitoring_status,
+            get_monitoring_state,
             toggle_monitoring,
+            get_hook_latency,
             get_key_ranking,
+            get_bigram_ranking,
+            get_shortcut_ranking,
+            get_key_dwell_histogram,
+            get_tap_hold_report,
+            get_bigram_capture,
+            set_bigram_capture,
             get_apps,
+            get_devices,
+            get_keyboards,
+            add_keyboard,
+            update_keyboard,
+            remove_keyboard,
+            set_device_keyboard,
+            get_keyboard_periods,
+            add_keyboard_period,
+            remove_keyboard_period,
+            get_keyboard_wear_report,
+            get_experiments,
+            add_experiment,
+            update_experiment,
+            remove_experiment,
+            get_experiment_report,
             get_total_key_count,
+            get_excluded_apps,
+            add_excluded_app,
+            remove_excluded_app,
+            get_excluded_patterns,
+            add_excluded_pattern,
+            remove_excluded_pattern,
+            purge_app_data,
+            get_hourly_key_counts,
             get_key_stat_date_range,
+            get_layouts,
+            get_form_factor_scores,
+            get_layout_coverage,
+            get_layout_heatmap,
+            import_kle_layout,
+            simulate_keymap,
+            suggest_keymap,
             import_database,
             export_database,
             quit_app,
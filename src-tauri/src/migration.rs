@@ -0,0 +1,675 @@
+use anyhow::{anyhow, Context, Result};
+use rusqlite::Connection;
+use std::fs;
+use std::path::Path;
+
+type Migration = (&'static str, fn(&Connection) -> Result<()>);
+
+/// スキーマの変更は末尾に追加する（PRAGMA user_version = 適用済みの件数）
+const MIGRATIONS: &[Migration] = &[
+    ("initial schema", initial_schema),
+    ("hourly buckets and analysis tables", hourly_buckets),
+    ("exclusion patterns", exclusion_patterns),
+    ("input devices", input_devices),
+    ("keyboard inventory", keyboard_inventory),
+    ("experiments", experiments),
+];
+
+/// このアプリが扱えるスキーマのバージョン
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// 未適用のマイグレーションを順に適用する（1件ずつトランザクション）
+/// 既存のDBは適用前にバックアップし、新しすぎるDBは開かない
+pub fn migrate(conn: &mut Connection, db_path: &Path) -> Result<()> {
+    let version: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > SCHEMA_VERSION {
+        return Err(anyhow!(
+            "Database schema version {version} is newer than this app supports ({SCHEMA_VERSION}). Please update KeyFit."
+        ));
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(());
+    }
+    if has_tables(conn)? {
+        backup(db_path, version)?;
+    }
+
+    for (index, (description, step)) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let next = index as u32 + 1;
+        let tx = conn.transaction()?;
+        step(&tx).with_context(|| format!("Migration {next} ({description}) failed"))?;
+        tx.pragma_update(None, "user_version", next)?;
+        tx.commit()?;
+        println!("[KeyFit] Migrated database to version {next}: {description}");
+    }
+    Ok(())
+}
+
+fn has_tables(conn: &Connection) -> Result<bool> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table'",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// マイグレーション前のDBファイルを複製しておく
+fn backup(db_path: &Path, version: u32) -> Result<()> {
+    if !db_path.is_file() {
+        return Ok(());
+    }
+    let backup_path = db_path.with_extension(format!(
+        "backup_v{}_{}.db",
+        version,
+        chrono::Local::now().format("%Y%m%d_%H%M%S")
+    ));
+    fs::copy(db_path, &backup_path)
+        .with_context(|| format!("Failed to backup database before migration: {backup_path:?}"))?;
+    println!("[KeyFit] Backed up database to {:?}", backup_path);
+    Ok(())
+}
+
+/// バージョン管理を導入する前のスキーマ（user_version = 0 の既存DBでは何もしない）
+fn initial_schema(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS app (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            bundle_id TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS key_stat (
+            ts_day INTEGER NOT NULL,
+            key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL,
+            count INTEGER NOT NULL,
+            PRIMARY KEY (ts_day, key_code, app_id),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS excluded_app (
+            app_id INTEGER PRIMARY KEY,
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// 時間単位の集計（ts_hour）と分析用の集計テーブル
+fn hourly_buckets(conn: &Connection) -> Result<()> {
+    create_hourly_table(
+        conn,
+        "key_stat",
+        "CREATE TABLE IF NOT EXISTS key_stat (
+            ts_day INTEGER NOT NULL,
+            ts_hour INTEGER,
+            key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL,
+            count INTEGER NOT NULL,
+            UNIQUE (ts_hour, key_code, app_id),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        )",
+    )?;
+
+    create_hourly_table(
+        conn,
+        "key_bigram",
+        "CREATE TABLE IF NOT EXISTS key_bigram (
+            ts_day INTEGER NOT NULL,
+            ts_hour INTEGER,
+            prev_key TEXT NOT NULL,
+            key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL,
+            count INTEGER NOT NULL,
+            UNIQUE (ts_hour, prev_key, key_code, app_id),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        )",
+    )?;
+
+    create_hourly_table(
+        conn,
+        "shortcut_stat",
+        "CREATE TABLE IF NOT EXISTS shortcut_stat (
+            ts_day INTEGER NOT NULL,
+            ts_hour INTEGER,
+            shortcut TEXT NOT NULL,
+            app_id INTEGER NOT NULL,
+            count INTEGER NOT NULL,
+            UNIQUE (ts_hour, shortcut, app_id),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        )",
+    )?;
+
+    create_hourly_table(
+        conn,
+        "key_dwell",
+        "CREATE TABLE IF NOT EXISTS key_dwell (
+            ts_day INTEGER NOT NULL,
+            ts_hour INTEGER,
+            key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL,
+            bucket_ms INTEGER NOT NULL,
+            count INTEGER NOT NULL,
+            UNIQUE (ts_hour, key_code, app_id, bucket_ms),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        )",
+    )?;
+
+    create_hourly_table(
+        conn,
+        "tap_hold_stat",
+        "CREATE TABLE IF NOT EXISTS tap_hold_stat (
+            ts_day INTEGER NOT NULL,
+            ts_hour INTEGER,
+            key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL,
+            tapping_term_ms INTEGER NOT NULL,
+            presses INTEGER NOT NULL,
+            misfires INTEGER NOT NULL,
+            UNIQUE (ts_hour, key_code, app_id, tapping_term_ms),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        )",
+    )?;
+
+    create_hourly_table(
+        conn,
+        "key_repeat_stat",
+        "CREATE TABLE IF NOT EXISTS key_repeat_stat (
+            ts_day INTEGER NOT NULL,
+            ts_hour INTEGER,
+            key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL,
+            count INTEGER NOT NULL,
+            UNIQUE (ts_hour, key_code, app_id),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        )",
+    )?;
+
+    Ok(())
+}
+
+/// バンドルパスのglob/正規表現による除外
+fn exclusion_patterns(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS excluded_pattern (
+            id INTEGER PRIMARY KEY,
+            kind TEXT NOT NULL,
+            pattern TEXT NOT NULL,
+            UNIQUE (kind, pattern)
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// 物理キーボードごとの集計（既存の行はデバイス不明 = 0 になる）
+fn input_devices(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS device (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            vendor_id INTEGER,
+            product_id INTEGER,
+            identifier TEXT NOT NULL UNIQUE
+        )",
+        [],
+    )?;
+
+    recreate_table(
+        conn,
+        "key_stat",
+        "CREATE TABLE key_stat (
+            ts_day INTEGER NOT NULL,
+            ts_hour INTEGER,
+            key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL,
+            device_id INTEGER NOT NULL DEFAULT 0,
+            count INTEGER NOT NULL,
+            UNIQUE (ts_hour, key_code, app_id, device_id),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        )",
+    )?;
+
+    recreate_table(
+        conn,
+        "key_bigram",
+        "CREATE TABLE key_bigram (
+            ts_day INTEGER NOT NULL,
+            ts_hour INTEGER,
+            prev_key TEXT NOT NULL,
+            key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL,
+            device_id INTEGER NOT NULL DEFAULT 0,
+            count INTEGER NOT NULL,
+            UNIQUE (ts_hour, prev_key, key_code, app_id, device_id),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        )",
+    )?;
+
+    recreate_table(
+        conn,
+        "shortcut_stat",
+        "CREATE TABLE shortcut_stat (
+            ts_day INTEGER NOT NULL,
+            ts_hour INTEGER,
+            shortcut TEXT NOT NULL,
+            app_id INTEGER NOT NULL,
+            device_id INTEGER NOT NULL DEFAULT 0,
+            count INTEGER NOT NULL,
+            UNIQUE (ts_hour, shortcut, app_id, device_id),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        )",
+    )?;
+
+    recreate_table(
+        conn,
+        "key_dwell",
+        "CREATE TABLE key_dwell (
+            ts_day INTEGER NOT NULL,
+            ts_hour INTEGER,
+            key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL,
+            device_id INTEGER NOT NULL DEFAULT 0,
+            bucket_ms INTEGER NOT NULL,
+            count INTEGER NOT NULL,
+            UNIQUE (ts_hour, key_code, app_id, device_id, bucket_ms),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        )",
+    )?;
+
+    recreate_table(
+        conn,
+        "tap_hold_stat",
+        "CREATE TABLE tap_hold_stat (
+            ts_day INTEGER NOT NULL,
+            ts_hour INTEGER,
+            key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL,
+            device_id INTEGER NOT NULL DEFAULT 0,
+            tapping_term_ms INTEGER NOT NULL,
+            presses INTEGER NOT NULL,
+            misfires INTEGER NOT NULL,
+            UNIQUE (ts_hour, key_code, app_id, device_id, tapping_term_ms),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        )",
+    )?;
+
+    recreate_table(
+        conn,
+        "key_repeat_stat",
+        "CREATE TABLE key_repeat_stat (
+            ts_day INTEGER NOT NULL,
+            ts_hour INTEGER,
+            key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL,
+            device_id INTEGER NOT NULL DEFAULT 0,
+            count INTEGER NOT NULL,
+            UNIQUE (ts_hour, key_code, app_id, device_id),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        )",
+    )?;
+
+    Ok(())
+}
+
+/// キーボードの在庫と、集計をキーボードに割り当てる方法
+/// デバイスが分かる押下は keyboard_device で、分からない押下は keyboard_period の期間で割り当てる
+fn keyboard_inventory(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS keyboard (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            layout_id TEXT,
+            switch_type TEXT,
+            rated_lifetime INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS keyboard_device (
+            device_id INTEGER PRIMARY KEY,
+            keyboard_id INTEGER NOT NULL,
+            FOREIGN KEY (device_id) REFERENCES device(id),
+            FOREIGN KEY (keyboard_id) REFERENCES keyboard(id)
+        );
+        CREATE TABLE IF NOT EXISTS keyboard_period (
+            id INTEGER PRIMARY KEY,
+            keyboard_id INTEGER NOT NULL,
+            start_date INTEGER NOT NULL,
+            end_date INTEGER,
+            FOREIGN KEY (keyboard_id) REFERENCES keyboard(id)
+        );",
+    )?;
+    Ok(())
+}
+
+fn experiments(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS experiment (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            start_date INTEGER NOT NULL,
+            end_date INTEGER,
+            baseline_start_date INTEGER NOT NULL,
+            baseline_end_date INTEGER NOT NULL
+        );",
+    )?;
+    Ok(())
+}
+
+/// 一意制約を変えるためにテーブルを作り直し、既存の列の値を引き継ぐ
+fn recreate_table(conn: &Connection, table: &str, create_sql: &str) -> Result<()> {
+    let column_list = table_columns(conn, table)?.join(", ");
+    conn.execute_batch(&format!(
+        "ALTER TABLE {table} RENAME TO {table}_old;
+        {create_sql};
+        INSERT INTO {table} ({column_list}) SELECT {column_list} FROM {table}_old;
+        DROP TABLE {table}_old;"
+    ))?;
+    Ok(())
+}
+
+/// 集計テーブルを作成する。ts_hour 導入前のテーブルは作り直し、
+/// 既存の行は ts_hour = NULL（日単位の集計）として引き継ぐ
+fn create_hourly_table(conn: &Connection, table: &str, create_sql: &str) -> Result<()> {
+    let columns = table_columns(conn, table)?;
+    if columns.is_empty() || columns.iter().any(|c| c == "ts_hour") {
+        conn.execute(create_sql, [])?;
+        return Ok(());
+    }
+    let column_list = columns.join(", ");
+    conn.execute_batch(&format!(
+        "ALTER TABLE {table} RENAME TO {table}_daily;
+        {create_sql};
+        INSERT INTO {table} ({column_list}) SELECT {column_list} FROM {table}_daily;
+        DROP TABLE {table}_daily;"
+    ))?;
+    Ok(())
+}
+
+fn table_columns(conn: &Connection, table: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(1))?;
+    let mut columns = Vec::new();
+    for row in rows {
+        columns.push(row?);
+    }
+    Ok(columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Database, StatFilter};
+    use tempfile::TempDir;
+
+    // 各バージョンのスキーマをSQLのまま固定しておく（過去のマイグレーションを
+    // 変更してしまったときにテストで気付けるよう、MIGRATIONS からは作らない）
+
+    /// バージョン管理を導入する前（user_version = 0）に作成されたDB
+    const FIXTURE_V0: &str = "
+        CREATE TABLE app (id INTEGER PRIMARY KEY, name TEXT NOT NULL, bundle_id TEXT NOT NULL);
+        CREATE TABLE key_stat (
+            ts_day INTEGER NOT NULL,
+            key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL,
+            count INTEGER NOT NULL,
+            PRIMARY KEY (ts_day, key_code, app_id),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        );
+        CREATE TABLE excluded_app (app_id INTEGER PRIMARY KEY, FOREIGN KEY (app_id) REFERENCES app(id));
+        INSERT INTO app (id, name, bundle_id) VALUES (1, 'Test App', 'com.test.app');
+        INSERT INTO key_stat VALUES (100, 'KeyA', 1, 5);
+        INSERT INTO key_stat VALUES (100, 'KeyB', 1, 2);
+    ";
+
+    const APP_TABLES: &str = "
+        CREATE TABLE app (id INTEGER PRIMARY KEY, name TEXT NOT NULL, bundle_id TEXT NOT NULL);
+        CREATE TABLE excluded_app (app_id INTEGER PRIMARY KEY, FOREIGN KEY (app_id) REFERENCES app(id));
+        INSERT INTO app (id, name, bundle_id) VALUES (1, 'Test App', 'com.test.app');
+    ";
+
+    /// v2: 時間単位の集計（KeyB は v1 から引き継いだ日単位の行）
+    const HOURLY_TABLES: &str = "
+        CREATE TABLE key_stat (
+            ts_day INTEGER NOT NULL, ts_hour INTEGER, key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL, count INTEGER NOT NULL,
+            UNIQUE (ts_hour, key_code, app_id), FOREIGN KEY (app_id) REFERENCES app(id)
+        );
+        CREATE TABLE key_bigram (
+            ts_day INTEGER NOT NULL, ts_hour INTEGER, prev_key TEXT NOT NULL,
+            key_code TEXT NOT NULL, app_id INTEGER NOT NULL, count INTEGER NOT NULL,
+            UNIQUE (ts_hour, prev_key, key_code, app_id), FOREIGN KEY (app_id) REFERENCES app(id)
+        );
+        CREATE TABLE shortcut_stat (
+            ts_day INTEGER NOT NULL, ts_hour INTEGER, shortcut TEXT NOT NULL,
+            app_id INTEGER NOT NULL, count INTEGER NOT NULL,
+            UNIQUE (ts_hour, shortcut, app_id), FOREIGN KEY (app_id) REFERENCES app(id)
+        );
+        CREATE TABLE key_dwell (
+            ts_day INTEGER NOT NULL, ts_hour INTEGER, key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL, bucket_ms INTEGER NOT NULL, count INTEGER NOT NULL,
+            UNIQUE (ts_hour, key_code, app_id, bucket_ms), FOREIGN KEY (app_id) REFERENCES app(id)
+        );
+        CREATE TABLE tap_hold_stat (
+            ts_day INTEGER NOT NULL, ts_hour INTEGER, key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL, tapping_term_ms INTEGER NOT NULL,
+            presses INTEGER NOT NULL, misfires INTEGER NOT NULL,
+            UNIQUE (ts_hour, key_code, app_id, tapping_term_ms),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        );
+        CREATE TABLE key_repeat_stat (
+            ts_day INTEGER NOT NULL, ts_hour INTEGER, key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL, count INTEGER NOT NULL,
+            UNIQUE (ts_hour, key_code, app_id), FOREIGN KEY (app_id) REFERENCES app(id)
+        );
+        INSERT INTO key_stat VALUES (100, 100, 'KeyA', 1, 5);
+        INSERT INTO key_stat VALUES (100, NULL, 'KeyB', 1, 2);
+        INSERT INTO key_bigram VALUES (100, 100, 'KeyA', 'KeyB', 1, 1);
+        INSERT INTO key_repeat_stat VALUES (100, 100, 'KeyA', 1, 3);
+    ";
+
+    /// v3: バンドルパスによる除外
+    const EXCLUSION_TABLE: &str = "
+        CREATE TABLE excluded_pattern (
+            id INTEGER PRIMARY KEY, kind TEXT NOT NULL, pattern TEXT NOT NULL,
+            UNIQUE (kind, pattern)
+        );
+        INSERT INTO excluded_pattern VALUES (1, 'glob', '*1Password*');
+    ";
+
+    /// v4: デバイスごとの集計
+    const DEVICE_TABLES: &str = "
+        CREATE TABLE device (
+            id INTEGER PRIMARY KEY, name TEXT NOT NULL, vendor_id INTEGER,
+            product_id INTEGER, identifier TEXT NOT NULL UNIQUE
+        );
+        CREATE TABLE key_stat (
+            ts_day INTEGER NOT NULL, ts_hour INTEGER, key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL, device_id INTEGER NOT NULL DEFAULT 0, count INTEGER NOT NULL,
+            UNIQUE (ts_hour, key_code, app_id, device_id), FOREIGN KEY (app_id) REFERENCES app(id)
+        );
+        CREATE TABLE key_bigram (
+            ts_day INTEGER NOT NULL, ts_hour INTEGER, prev_key TEXT NOT NULL,
+            key_code TEXT NOT NULL, app_id INTEGER NOT NULL,
+            device_id INTEGER NOT NULL DEFAULT 0, count INTEGER NOT NULL,
+            UNIQUE (ts_hour, prev_key, key_code, app_id, device_id),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        );
+        CREATE TABLE shortcut_stat (
+            ts_day INTEGER NOT NULL, ts_hour INTEGER, shortcut TEXT NOT NULL,
+            app_id INTEGER NOT NULL, device_id INTEGER NOT NULL DEFAULT 0, count INTEGER NOT NULL,
+            UNIQUE (ts_hour, shortcut, app_id, device_id), FOREIGN KEY (app_id) REFERENCES app(id)
+        );
+        CREATE TABLE key_dwell (
+            ts_day INTEGER NOT NULL, ts_hour INTEGER, key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL, device_id INTEGER NOT NULL DEFAULT 0,
+            bucket_ms INTEGER NOT NULL, count INTEGER NOT NULL,
+            UNIQUE (ts_hour, key_code, app_id, device_id, bucket_ms),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        );
+        CREATE TABLE tap_hold_stat (
+            ts_day INTEGER NOT NULL, ts_hour INTEGER, key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL, device_id INTEGER NOT NULL DEFAULT 0,
+            tapping_term_ms INTEGER NOT NULL, presses INTEGER NOT NULL, misfires INTEGER NOT NULL,
+            UNIQUE (ts_hour, key_code, app_id, device_id, tapping_term_ms),
+            FOREIGN KEY (app_id) REFERENCES app(id)
+        );
+        CREATE TABLE key_repeat_stat (
+            ts_day INTEGER NOT NULL, ts_hour INTEGER, key_code TEXT NOT NULL,
+            app_id INTEGER NOT NULL, device_id INTEGER NOT NULL DEFAULT 0, count INTEGER NOT NULL,
+            UNIQUE (ts_hour, key_code, app_id, device_id), FOREIGN KEY (app_id) REFERENCES app(id)
+        );
+        INSERT INTO device VALUES (1, 'Sixty', 1, 2, '0001:0002:Sixty');
+        INSERT INTO key_stat VALUES (100, 100, 'KeyA', 1, 1, 5);
+        INSERT INTO key_stat VALUES (100, NULL, 'KeyB', 1, 0, 2);
+        INSERT INTO key_bigram VALUES (100, 100, 'KeyA', 'KeyB', 1, 1, 1);
+        INSERT INTO key_repeat_stat VALUES (100, 100, 'KeyA', 1, 1, 3);
+    ";
+
+    /// v5: キーボードの在庫
+    const INVENTORY_TABLES: &str = "
+        CREATE TABLE keyboard (
+            id INTEGER PRIMARY KEY, name TEXT NOT NULL, layout_id TEXT,
+            switch_type TEXT, rated_lifetime INTEGER NOT NULL
+        );
+        CREATE TABLE keyboard_device (
+            device_id INTEGER PRIMARY KEY, keyboard_id INTEGER NOT NULL,
+            FOREIGN KEY (device_id) REFERENCES device(id),
+            FOREIGN KEY (keyboard_id) REFERENCES keyboard(id)
+        );
+        CREATE TABLE keyboard_period (
+            id INTEGER PRIMARY KEY, keyboard_id INTEGER NOT NULL,
+            start_date INTEGER NOT NULL, end_date INTEGER,
+            FOREIGN KEY (keyboard_id) REFERENCES keyboard(id)
+        );
+        INSERT INTO keyboard VALUES (1, '60%', 'ansi-60', NULL, 50000000);
+        INSERT INTO keyboard_device VALUES (1, 1);
+        INSERT INTO keyboard_period VALUES (1, 1, 0, NULL);
+    ";
+
+    /// 各バージョンのフィクスチャ（バージョン, 作成SQL）
+    fn fixtures() -> Vec<(u32, String)> {
+        let v0 = FIXTURE_V0.to_string();
+        // v1 は既存のDBに何もしない
+        let v1 = format!("{FIXTURE_V0} PRAGMA user_version = 1;");
+        let v2 = format!("{APP_TABLES} {HOURLY_TABLES} PRAGMA user_version = 2;");
+        let v3 = format!("{APP_TABLES} {HOURLY_TABLES} {EXCLUSION_TABLE} PRAGMA user_version = 3;");
+        let v4 = format!("{APP_TABLES} {EXCLUSION_TABLE} {DEVICE_TABLES} PRAGMA user_version = 4;");
+        let v5 = format!(
+            "{APP_TABLES} {EXCLUSION_TABLE} {DEVICE_TABLES} {INVENTORY_TABLES}
+            PRAGMA user_version = 5;"
+        );
+        vec![(0, v0), (1, v1), (2, v2), (3, v3), (4, v4), (5, v5)]
+    }
+
+    fn create_fixture(dir: &TempDir, sql: &str) -> std::path::PathBuf {
+        let path = dir.path().join("keyfit.db");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(sql).unwrap();
+        path
+    }
+
+    /// テーブルごとの列名（スキーマの比較用）
+    fn schema(conn: &Connection) -> Vec<(String, Vec<String>)> {
+        let mut stmt = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap();
+        let tables: Vec<String> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        tables
+            .into_iter()
+            .map(|table| {
+                let columns = table_columns(conn, &table).unwrap();
+                (table, columns)
+            })
+            .collect()
+    }
+
+    fn backups(dir: &TempDir) -> usize {
+        fs::read_dir(dir.path())
+            .unwrap()
+            .filter(|e| {
+                e.as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .contains("backup_v")
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_fixtures_cover_every_version() {
+        let versions: Vec<u32> = fixtures().iter().map(|(v, _)| *v).collect();
+        assert_eq!(versions, (0..SCHEMA_VERSION).collect::<Vec<_>>());
+    }
+
+    /// 新規DBに途中までマイグレーションを適用した結果がフィクスチャと一致する
+    /// （適用済みのマイグレーションを変更するとここで失敗する）
+    #[test]
+    fn test_fixtures_match_migrations() {
+        for (version, sql) in fixtures().into_iter().skip(1) {
+            let fixture = Connection::open_in_memory().unwrap();
+            fixture.execute_batch(&sql).unwrap();
+
+            let migrated = Connection::open_in_memory().unwrap();
+            for (_, step) in MIGRATIONS.iter().take(version as usize) {
+                step(&migrated).unwrap();
+            }
+            assert_eq!(schema(&migrated), schema(&fixture), "fixture v{version}");
+        }
+    }
+
+    #[test]
+    fn test_upgrade_fixtures() {
+        for (version, sql) in fixtures() {
+            let dir = TempDir::new().unwrap();
+            let path = create_fixture(&dir, &sql);
+
+            let db = Database::new(&path).unwrap();
+            let ranking = db
+                .get_key_ranking(&StatFilter::default(), None, false)
+                .unwrap();
+            assert_eq!(ranking.len(), 2, "fixture v{version}");
+            assert_eq!(ranking[0].count, 5, "fixture v{version}");
+            assert_eq!(backups(&dir), 1, "fixture v{version}");
+            drop(db);
+
+            let conn = Connection::open(&path).unwrap();
+            let upgraded: u32 = conn
+                .query_row("PRAGMA user_version", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(upgraded, SCHEMA_VERSION);
+        }
+    }
+
+    #[test]
+    fn test_new_database_skips_backup() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("keyfit.db");
+        Database::new(&path).unwrap();
+        // 2回目は最新なので何もしない
+        Database::new(&path).unwrap();
+        assert_eq!(backups(&dir), 0);
+    }
+
+    #[test]
+    fn test_refuse_newer_database() {
+        let dir = TempDir::new().unwrap();
+        let path = create_fixture(
+            &dir,
+            &format!("PRAGMA user_version = {};", SCHEMA_VERSION + 1),
+        );
+        assert!(Database::new(&path).is_err());
+    }
+}
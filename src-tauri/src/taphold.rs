@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/// ホームロウモッドを割り当てる想定のキー
+pub const HOME_ROW_KEYS: &[&str] = &[
+    "KeyA",
+    "KeyS",
+    "KeyD",
+    "KeyF",
+    "KeyJ",
+    "KeyK",
+    "KeyL",
+    "SemiColon",
+];
+
+/// 誤爆率を集計するタッピングターム（ミリ秒）
+pub const TAPPING_TERMS_MS: &[i64] = &[150, 175, 200, 250, 300];
+
+/// ホームロウキーの押下を集計する単位
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct TapHoldKey {
+    pub ts_day: i64,
+    pub ts_hour: i64,
+    pub key_code: String,
+    pub app_id: i64,
+    pub device_id: i64,
+}
+
+/// 押下数と、タッピングタームごと（`TAPPING_TERMS_MS` と同じ順）の誤爆数
+#[derive(Debug, Clone, PartialEq)]
+pub struct TapHoldCounts {
+    pub presses: i64,
+    pub misfires: Vec<i64>,
+}
+
+impl Default for TapHoldCounts {
+    fn default() -> Self {
+        Self {
+            presses: 0,
+            misfires: vec![0; TAPPING_TERMS_MS.len()],
+        }
+    }
+}
+
+impl TapHoldCounts {
+    /// 押下を1回加える。押している間に次のキーが押され（overlapped）、
+    /// タッピングターム以上押していればホールド扱いになり誤爆する
+    pub fn add(&mut self, dwell_ms: i64, overlapped: bool) {
+        self.presses += 1;
+        for (misfires, term) in self.misfires.iter_mut().zip(TAPPING_TERMS_MS) {
+            if overlapped && dwell_ms >= *term {
+                *misfires += 1;
+            }
+        }
+    }
+}
+
+/// ホームロウキーの押下時間と次のキーとの重なりを追跡する
+#[derive(Default)]
+pub struct TapHoldTracker {
+    held: HashMap<String, (SystemTime, bool)>,
+}
+
+impl TapHoldTracker {
+    pub fn press(&mut self, key_code: &str, time: SystemTime) {
+        if self.held.contains_key(key_code) {
+            // 押しっぱなしのリピート
+            return;
+        }
+        for (_, overlapped) in self.held.values_mut() {
+            *overlapped = true;
+        }
+        if HOME_ROW_KEYS.contains(&key_code) {
+            self.held.insert(key_code.to_string(), (time, false));
+        }
+    }
+
+    /// ホームロウキーの解放なら (押下時間, 重なりの有無) を返す
+    pub fn release(&mut self, key_code: &str, time: SystemTime) -> Option<(Duration, bool)> {
+        let (pressed_at, overlapped) = self.held.remove(key_code)?;
+        let dwell = time.duration_since(pressed_at).ok()?;
+        Some((dwell, overlapped))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TapHoldItem {
+    pub tapping_term_ms: i64,
+    pub key_code: String,
+    pub presses: i64,
+    pub misfires: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TapHoldReport {
+    pub tapping_term_ms: i64,
+    pub presses: i64,
+    pub misfires: i64,
+    pub misfire_rate: f64,
+    pub keys: Vec<TapHoldItem>,
+}
+
+/// キーごとの集計をタッピングタームごとのレポートにまとめる
+pub fn build_report(items: Vec<TapHoldItem>) -> Vec<TapHoldReport> {
+    let mut by_term: HashMap<i64, Vec<TapHoldItem>> = HashMap::new();
+    for item in items {
+        by_term.entry(item.tapping_term_ms).or_default().push(item);
+    }
+    let mut reports: Vec<TapHoldReport> = by_term
+        .into_iter()
+        .map(|(tapping_term_ms, mut keys)| {
+            keys.sort_by(|a, b| {
+                b.misfires
+                    .cmp(&a.misfires)
+                    .then(a.key_code.cmp(&b.key_code))
+            });
+            let presses = keys.iter().map(|k| k.presses).sum();
+            let misfires = keys.iter().map(|k| k.misfires).sum();
+            let misfire_rate = if presses > 0 {
+                misfires as f64 / presses as f64 * 100.0
+            } else {
+                0.0
+            };
+            TapHoldReport {
+                tapping_term_ms,
+                presses,
+                misfires,
+                misfire_rate,
+                keys,
+            }
+        })
+        .collect();
+    reports.sort_by_key(|r| r.tapping_term_ms);
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tap_hold_tracker() {
+        let t0 = SystemTime::UNIX_EPOCH;
+        let ms = Duration::from_millis;
+        let mut tracker = TapHoldTracker::default();
+
+        // Fを押したままJを押すロール
+        tracker.press("KeyF", t0);
+        tracker.press("KeyF", t0 + ms(100));
+        tracker.press("KeyJ", t0 + ms(120));
+        assert_eq!(tracker.release("KeyF", t0 + ms(210)), Some((ms(210), true)));
+        assert_eq!(
+            tracker.release("KeyJ", t0 + ms(260)),
+            Some((ms(140), false))
+        );
+
+        // ホームロウ以外は追跡しない
+        tracker.press("KeyQ", t0);
+        assert_eq!(tracker.release("KeyQ", t0 + ms(50)), None);
+    }
+
+    #[test]
+    fn test_build_report() {
+        let mut counts = TapHoldCounts::default();
+        counts.add(210, true);
+        counts.add(400, false);
+        assert_eq!(counts.presses, 2);
+        assert_eq!(counts.misfires, vec![1, 1, 1, 0, 0]);
+
+        let item = |term, key: &str, presses, misfires| TapHoldItem {
+            tapping_term_ms: term,
+            key_code: key.to_string(),
+            presses,
+            misfires,
+        };
+        let reports = build_report(vec![
+            item(200, "KeyF", 10, 1),
+            item(150, "KeyF", 10, 3),
+            item(150, "KeyJ", 30, 5),
+        ]);
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].tapping_term_ms, 150);
+        assert_eq!(reports[0].presses, 40);
+        assert_eq!(reports[0].misfires, 8);
+        assert_eq!(reports[0].misfire_rate, 20.0);
+        assert_eq!(reports[0].keys[0].key_code, "KeyJ");
+    }
+}
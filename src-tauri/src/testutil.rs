@@ -0,0 +1,13 @@
+use std::thread;
+use std::time::Duration;
+
+/// 別スレッドでの処理を待つ（5秒で満たされなければ失敗する）
+pub fn wait_until(what: &str, done: impl Fn() -> bool) {
+    for _ in 0..500 {
+        if done() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("timed out waiting for {what}");
+}
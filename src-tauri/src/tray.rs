@@ -1,35 +1,46 @@
-use crate::keyboard::KeyboardHook;
+use crate::keyboard::{KeyboardHook, MonitoringState};
 use std::sync::Arc;
 use std::sync::Mutex;
 use tauri::{
     menu::{Menu, MenuItem},
     tray::{TrayIcon, TrayIconBuilder},
-    AppHandle, Emitter, Manager, Wry,
+    AppHandle, Manager, Wry,
 };
 
 pub static TRAY_ICON: Mutex<Option<TrayIcon>> = Mutex::new(None);
 
-pub fn build_tray_menu(app: &AppHandle, is_running: bool) -> tauri::Result<Menu<Wry>> {
-    let status_label = if is_running {
-        "Logging Status: ✅ Monitoring"
-    } else {
-        "Logging Status: ❌ Stopped"
+pub fn build_tray_menu(app: &AppHandle, state: MonitoringState) -> tauri::Result<Menu<Wry>> {
+    let status_label = match state {
+        MonitoringState::Running => "Logging Status: ✅ Monitoring",
+        MonitoringState::Starting => "Logging Status: ⏳ Starting",
+        MonitoringState::Paused => "Logging Status: ⏸ Paused",
+        MonitoringState::Stopped => "Logging Status: ❌ Stopped",
+        MonitoringState::Failed => "Logging Status: ⚠️ Failed",
     };
-    let toggle_label = if is_running {
+    let toggle_label = if state == MonitoringState::Running {
         "Stop Monitoring"
     } else {
         "Start Monitoring"
     };
+    let toggle_enabled = state != MonitoringState::Starting;
     let status_i = MenuItem::with_id(app, "status", status_label, false, None::<&str>)?;
-    let toggle_i = MenuItem::with_id(app, "toggle", toggle_label, true, None::<&str>)?;
+    let toggle_i = MenuItem::with_id(app, "toggle", toggle_label, toggle_enabled, None::<&str>)?;
     let show_i = MenuItem::with_id(app, "show", "Show App", true, None::<&str>)?;
     let quit_i = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
     Menu::with_items(app, &[&status_i, &toggle_i, &show_i, &quit_i])
 }
 
+/// 監視状態に合わせてメニューを再生成してセットする
+pub fn update_tray_menu(app: &AppHandle, state: MonitoringState) {
+    if let Some(tray) = TRAY_ICON.lock().unwrap().as_mut() {
+        if let Ok(menu) = build_tray_menu(app, state) {
+            let _ = tray.set_menu(Some(menu));
+        }
+    }
+}
+
 pub fn init_tray(app: &AppHandle, keyboard_hook: Arc<KeyboardHook>) -> tauri::Result<()> {
-    let is_running = keyboard_hook.is_running();
-    let menu = build_tray_menu(app, is_running)?;
+    let menu = build_tray_menu(app, keyboard_hook.state())?;
 
     let tray_keyboard_hook = keyboard_hook.clone();
     let tray = TrayIconBuilder::new()
@@ -43,16 +54,9 @@ pub fn init_tray(app: &AppHandle, keyboard_hook: Arc<KeyboardHook>) -> tauri::Re
                     app.exit(0);
                 }
                 "toggle" => {
-                    tray_keyboard_hook.toggle(app);
-                    let is_running = tray_keyboard_hook.is_running();
-                    // メニューを再生成してセット
-                    if let Ok(menu) = build_tray_menu(app, is_running) {
-                        if let Some(tray) = TRAY_ICON.lock().unwrap().as_mut() {
-                            let _ = tray.set_menu(Some(menu));
-                        }
-                    }
-                    if let Some(window) = app.get_webview_window("main") {
-                        let _ = window.emit("monitoring_status_changed", is_running);
+                    // メニューと画面への反映は状態の変化の通知で行う
+                    if let Err(e) = tray_keyboard_hook.toggle() {
+                        eprintln!("[KeyFit] Failed to toggle monitoring: {}", e);
                     }
                 }
                 "show" => {
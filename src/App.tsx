@@ -119,7 +119,7 @@ function App() {
       const appsWithCount = await Promise.all(
         result.map(async (app) => {
           const count = await invoke<number>('get_total_key_count', {
-            appId: app.id,
+            filter: { app_id: app.id },
           });
           return { ...app, totalCount: count };
         }),
@@ -128,7 +128,7 @@ function App() {
 
       // All Apps分も取得
       const allCount = await invoke<number>('get_total_key_count', {
-        appId: null,
+        filter: {},
       });
       setAllAppsTotal(allCount);
     } catch (error) {
@@ -140,19 +140,15 @@ function App() {
     try {
       const startTimestamp = startDate ? startDate.startOf('day').unix() : null;
       const endTimestamp = endDate ? endDate.endOf('day').unix() : null;
-      const appId = selectedApp === 'all' ? null : selectedApp;
+      const filter = {
+        start_date: startTimestamp,
+        end_date: endTimestamp,
+        app_id: selectedApp === 'all' ? null : selectedApp,
+      };
 
       const [ranking, total] = await Promise.all([
-        invoke<KeyRankingItem[]>('get_key_ranking', {
-          startDate: startTimestamp,
-          endDate: endTimestamp,
-          appId,
-        }),
-        invoke<number>('get_total_key_count', {
-          startDate: startTimestamp,
-          endDate: endTimestamp,
-          appId,
-        }),
+        invoke<KeyRankingItem[]>('get_key_ranking', { filter }),
+        invoke<number>('get_total_key_count', { filter }),
       ]);
 
       setKeyRanking(ranking);
@@ -203,8 +199,9 @@ function App() {
   const fetchDateRange = useCallback(async () => {
     return await invoke<{ min: number; max: number }>(
       'get_key_stat_date_range',
+      { filter: { app_id: selectedApp === 'all' ? null : selectedApp } },
     );
-  }, []);
+  }, [selectedApp]);
 
   // --- ヒートマップ描画用関数 ---
   const keyCountMap = keyRanking.reduce(